@@ -1,7 +1,24 @@
 //! Configuration management
 
+use crate::middleware::rate_limit::{
+    FailMode, MAX_WINDOW, RateLimitAlgorithm, RateLimitPolicies, RateLimiter,
+};
+use crate::middleware::rate_limit_redis::RedisStore;
+use crate::utils::bans::BanConfig;
+use crate::utils::cache::DnsCache;
+use crate::utils::cache_redis::RedisDnsStore;
+use crate::utils::cdn::CdnPreset;
+use crate::utils::dns::{self, DnsProtocol, DnsResolverConfig};
+use crate::utils::gossip::GossipConfig;
+use crate::utils::network::{self, Cidr};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
 use std::time::Duration;
 
+/// Shortest gossip secret accepted
+const MIN_GOSSIP_SECRET_LEN: usize = 16;
+
 /// Application configuration
 #[derive(Clone, Debug)]
 pub struct Config {
@@ -14,11 +31,238 @@ pub struct Config {
     /// Rate limit: time window in seconds
     pub rate_limit_window_secs: u64,
 
-    /// DNS cache TTL in seconds
+    /// Rate limit: algorithm (fixed_window, token_bucket or gcra)
+    pub rate_limit_algorithm: RateLimitAlgorithm,
+
+    /// Rate limit: requests allowed at once by token_bucket and gcra
+    pub rate_limit_burst: usize,
+
+    /// Rate limit: IPv4 prefix length clients are grouped by
+    pub rate_limit_ipv4_prefix: u8,
+
+    /// Rate limit: IPv6 prefix length clients are grouped by
+    pub rate_limit_ipv6_prefix: u8,
+
+    /// Rate limit: networks exempt from the default policy
+    pub rate_limit_exempt: Vec<Cidr>,
+
+    /// Rate limit: named policies for specific routes
+    pub rate_limit_policies: Vec<RateLimitPolicyConfig>,
+
+    /// Rate limit: Redis server shared by all replicas (in-memory if unset)
+    pub rate_limit_redis_url: Option<String>,
+
+    /// Rate limit: prefix for Redis keys
+    pub rate_limit_redis_prefix: String,
+
+    /// Rate limit: Redis connection and command timeout in milliseconds
+    pub rate_limit_redis_timeout_ms: u64,
+
+    /// Rate limit: allow (open) or reject (closed) requests while Redis is unreachable
+    pub rate_limit_fail_mode: FailMode,
+
+    /// Bans: strikes within the find time that trigger a ban (0 disables)
+    pub ban_max_strikes: u32,
+
+    /// Bans: window strikes are counted in, in seconds
+    pub ban_find_time_secs: u64,
+
+    /// Bans: first ban duration in seconds, doubled for every repeat
+    pub ban_time_secs: u64,
+
+    /// Bans: longest automatic ban in seconds
+    pub ban_max_time_secs: u64,
+
+    /// Bans: file bans are saved to across restarts
+    pub ban_file: Option<PathBuf>,
+
+    /// Bearer token for the admin API (disabled if unset)
+    pub admin_token: Option<String>,
+
+    /// File rate limiter and ban state is saved to on shutdown
+    pub snapshot_file: Option<PathBuf>,
+
+    /// Seconds between snapshots while running (0 saves on shutdown only)
+    pub snapshot_interval_secs: u64,
+
+    /// Gossip: UDP address to exchange state with peers on (disabled if unset)
+    pub gossip_bind: Option<SocketAddr>,
+
+    /// Gossip: peers to send state to, as `host:port`
+    pub gossip_peers: Vec<String>,
+
+    /// Gossip: secret shared by all peers
+    pub gossip_secret: Option<String>,
+
+    /// Gossip: milliseconds between rounds
+    pub gossip_interval_ms: u64,
+
+    /// DNS cache TTL in seconds, for answers without one from the upstream
     pub dns_cache_ttl_secs: u64,
 
+    /// Shortest time an upstream DNS answer is cached, in seconds
+    pub dns_cache_min_ttl_secs: u64,
+
+    /// Longest time an upstream DNS answer is cached, in seconds
+    pub dns_cache_max_ttl_secs: u64,
+
+    /// DNS cache TTL for empty answers and failed lookups, in seconds
+    pub dns_cache_negative_ttl_secs: u64,
+
+    /// Most DNS cache entries kept before the least recently used are evicted
+    pub dns_cache_max_entries: usize,
+
+    /// Seconds an expired DNS cache entry is still served while it is
+    /// refreshed (0 disables)
+    pub dns_cache_stale_secs: u64,
+
+    /// Seconds before expiry at which popular DNS cache entries are
+    /// refreshed (0 disables)
+    pub dns_cache_prefetch_secs: u64,
+
+    /// Times a DNS cache entry must be served before it is prefetched
+    pub dns_cache_prefetch_min_hits: u32,
+
+    /// DNS cache: Redis server shared by all replicas as a second tier
+    /// (in-memory only if unset)
+    pub dns_cache_redis_url: Option<String>,
+
+    /// DNS cache: prefix for Redis keys
+    pub dns_cache_redis_prefix: String,
+
+    /// DNS cache: Redis connection and command timeout in milliseconds
+    pub dns_cache_redis_timeout_ms: u64,
+
+    /// DNS: upstream nameservers (from `/etc/resolv.conf` if empty)
+    pub dns_servers: Vec<SocketAddr>,
+
+    /// DNS: transport used to reach the upstreams (udp or tcp)
+    pub dns_protocol: DnsProtocol,
+
+    /// DNS: per-query timeout in milliseconds
+    pub dns_timeout_ms: Option<u64>,
+
+    /// DNS: attempts per query
+    pub dns_attempts: Option<usize>,
+
+    /// DNS: advertise EDNS(0) in queries
+    pub dns_edns: Option<bool>,
+
+    /// DNS: longest a lookup may take in milliseconds, across all attempts
+    /// (0 disables)
+    pub dns_deadline_ms: u64,
+
+    /// DNS: consecutive timeouts or unreachable upstreams that open the
+    /// circuit breaker (0 disables)
+    pub dns_breaker_failures: u32,
+
+    /// DNS: seconds the circuit breaker stays open before a lookup is retried
+    pub dns_breaker_cooldown_secs: u64,
+
+    /// DNS: trust anchor file (DNSKEY records) that enables DNSSEC validation
+    pub dns_trust_anchor_file: Option<PathBuf>,
+
     /// Request timeout in seconds
     pub request_timeout_secs: u64,
+
+    /// Proxies whose forwarding headers are trusted
+    pub trusted_proxies: Vec<Cidr>,
+
+    /// Expect a PROXY protocol header on every connection
+    pub proxy_protocol: bool,
+
+    /// CDN presets whose client IP headers are trusted
+    pub cdn_presets: Vec<CdnPreset>,
+
+    /// Directory holding `<preset>.txt` range files
+    pub cdn_ranges_dir: PathBuf,
+}
+
+/// A named rate limit policy applied to specific routes
+#[derive(Clone, Debug)]
+pub struct RateLimitPolicyConfig {
+    /// Policy name
+    pub name: String,
+
+    /// Max requests per window
+    pub requests: usize,
+
+    /// Time window in seconds
+    pub window_secs: u64,
+
+    /// Algorithm (fixed_window, token_bucket or gcra)
+    pub algorithm: RateLimitAlgorithm,
+
+    /// Requests allowed at once by token_bucket and gcra
+    pub burst: usize,
+
+    /// Routes (exact paths) the policy applies to
+    pub routes: Vec<String>,
+
+    /// Networks exempt from the policy
+    pub exempt: Vec<Cidr>,
+}
+
+impl RateLimitPolicyConfig {
+    /// Load a policy from `RATE_LIMIT_POLICY_<NAME>_*` variables
+    ///
+    /// Limits not set for the policy fall back to the default policy's.
+    fn from_env(name: &str, defaults: &Config) -> Result<Self, Box<dyn std::error::Error>> {
+        if !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+        {
+            return Err(format!("invalid rate limit policy name: {}", name).into());
+        }
+
+        let prefix = format!(
+            "RATE_LIMIT_POLICY_{}",
+            name.to_uppercase().replace('-', "_")
+        );
+        let var = |key: &str| std::env::var(format!("{}_{}", prefix, key)).ok();
+
+        let requests = var("REQUESTS")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(defaults.rate_limit_requests);
+
+        let window_secs = var("WINDOW_SECS")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(defaults.rate_limit_window_secs);
+        check_window(&format!("{}_WINDOW_SECS", prefix), window_secs)?;
+
+        let algorithm = match var("ALGORITHM") {
+            Some(v) => v.parse()?,
+            None => defaults.rate_limit_algorithm,
+        };
+
+        let burst = var("BURST")
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(requests);
+
+        let routes: Vec<String> = var("ROUTES")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|route| !route.is_empty())
+            .map(String::from)
+            .collect();
+
+        if routes.is_empty() || routes.iter().any(|route| !route.starts_with('/')) {
+            return Err(format!("{}_ROUTES must list paths starting with '/'", prefix).into());
+        }
+
+        let exempt = network::parse_cidr_list(&var("EXEMPT").unwrap_or_default())?;
+
+        Ok(Self {
+            name: name.to_string(),
+            requests,
+            window_secs,
+            algorithm,
+            burst,
+            routes,
+            exempt,
+        })
+    }
 }
 
 impl Config {
@@ -37,6 +281,137 @@ impl Config {
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(60);
+        check_window("RATE_LIMIT_WINDOW_SECS", rate_limit_window_secs)?;
+
+        let rate_limit_algorithm = match std::env::var("RATE_LIMIT_ALGORITHM") {
+            Ok(v) => v.parse()?,
+            Err(_) => RateLimitAlgorithm::FixedWindow,
+        };
+
+        // Burst defaults to the full per-window allowance
+        let rate_limit_burst = std::env::var("RATE_LIMIT_BURST")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(rate_limit_requests);
+
+        // Prefix lengths used to group clients for rate limiting
+        let rate_limit_ipv4_prefix = std::env::var("RATE_LIMIT_IPV4_PREFIX")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|p| *p <= 32)
+            .unwrap_or(32);
+
+        let rate_limit_ipv6_prefix = std::env::var("RATE_LIMIT_IPV6_PREFIX")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|p| *p <= 128)
+            .unwrap_or(64);
+
+        // Networks never rate limited by the default policy (e.g. monitoring)
+        let rate_limit_exempt =
+            network::parse_cidr_list(&std::env::var("RATE_LIMIT_EXEMPT").unwrap_or_default())?;
+
+        // Shared Redis backend
+        let rate_limit_redis_url = std::env::var("RATE_LIMIT_REDIS_URL")
+            .ok()
+            .filter(|url| !url.is_empty());
+
+        let rate_limit_redis_prefix =
+            std::env::var("RATE_LIMIT_REDIS_PREFIX").unwrap_or_else(|_| "ip-api:ratelimit".into());
+
+        let rate_limit_redis_timeout_ms = std::env::var("RATE_LIMIT_REDIS_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(250);
+
+        let rate_limit_fail_mode = match std::env::var("RATE_LIMIT_FAIL_MODE") {
+            Ok(v) => v.parse()?,
+            Err(_) => FailMode::Open,
+        };
+
+        // Automatic bans
+        let ban_max_strikes = std::env::var("BAN_MAX_STRIKES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(10);
+
+        let ban_find_time_secs = std::env::var("BAN_FIND_TIME_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(600);
+
+        let ban_time_secs = std::env::var("BAN_TIME_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(600);
+
+        let ban_max_time_secs = std::env::var("BAN_MAX_TIME_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(86400);
+
+        let ban_file = std::env::var("BAN_FILE")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+
+        // Admin API token
+        let admin_token = std::env::var("ADMIN_TOKEN")
+            .ok()
+            .filter(|token| !token.is_empty());
+
+        // State snapshots
+        let snapshot_file = std::env::var("SNAPSHOT_FILE")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+
+        let snapshot_interval_secs = std::env::var("SNAPSHOT_INTERVAL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        // Gossip between instances
+        let gossip_bind = match std::env::var("GOSSIP_BIND") {
+            Ok(bind) if !bind.is_empty() => Some(
+                bind.parse::<SocketAddr>()
+                    .map_err(|e| format!("invalid GOSSIP_BIND {}: {}", bind, e))?,
+            ),
+            _ => None,
+        };
+
+        let gossip_peers: Vec<String> = std::env::var("GOSSIP_PEERS")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::trim)
+            .filter(|peer| !peer.is_empty())
+            .map(String::from)
+            .collect();
+
+        let gossip_secret = std::env::var("GOSSIP_SECRET")
+            .ok()
+            .filter(|secret| !secret.is_empty());
+
+        let gossip_interval_ms = std::env::var("GOSSIP_INTERVAL_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&ms| ms > 0)
+            .unwrap_or(1000);
+
+        if gossip_bind.is_none() && !gossip_peers.is_empty() {
+            return Err("GOSSIP_PEERS requires GOSSIP_BIND".into());
+        }
+        if gossip_bind.is_some()
+            && gossip_secret
+                .as_ref()
+                .is_none_or(|secret| secret.len() < MIN_GOSSIP_SECRET_LEN)
+        {
+            return Err(format!(
+                "GOSSIP_BIND requires a GOSSIP_SECRET of at least {} bytes",
+                MIN_GOSSIP_SECRET_LEN
+            )
+            .into());
+        }
 
         // DNS cache TTL
         let dns_cache_ttl_secs = std::env::var("DNS_CACHE_TTL_SECS")
@@ -44,19 +419,276 @@ impl Config {
             .and_then(|v| v.parse().ok())
             .unwrap_or(300); // 5 minutes default
 
+        // Bounds on the TTLs given by upstream nameservers
+        let dns_cache_min_ttl_secs = std::env::var("DNS_CACHE_MIN_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let dns_cache_max_ttl_secs = std::env::var("DNS_CACHE_MAX_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(86400); // 1 day default
+
+        let dns_cache_negative_ttl_secs = std::env::var("DNS_CACHE_NEGATIVE_TTL_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(60);
+
+        let dns_cache_max_entries = std::env::var("DNS_CACHE_MAX_ENTRIES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&entries| entries > 0)
+            .unwrap_or(10_000);
+
+        // Serve-stale window and prefetching (both off by default)
+        let dns_cache_stale_secs = std::env::var("DNS_CACHE_STALE_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let dns_cache_prefetch_secs = std::env::var("DNS_CACHE_PREFETCH_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(0);
+
+        let dns_cache_prefetch_min_hits = std::env::var("DNS_CACHE_PREFETCH_MIN_HITS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(3);
+
+        // Shared second-level cache
+        let dns_cache_redis_url = std::env::var("DNS_CACHE_REDIS_URL")
+            .ok()
+            .filter(|url| !url.is_empty());
+
+        let dns_cache_redis_prefix =
+            std::env::var("DNS_CACHE_REDIS_PREFIX").unwrap_or_else(|_| "ip-api:dns".into());
+
+        let dns_cache_redis_timeout_ms = std::env::var("DNS_CACHE_REDIS_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(250);
+
+        // Upstream nameservers and query options (resolv.conf if unset)
+        let dns_servers =
+            dns::parse_server_list(&std::env::var("DNS_SERVERS").unwrap_or_default())?;
+
+        let dns_protocol = match std::env::var("DNS_PROTOCOL") {
+            Ok(v) if !v.is_empty() => v.parse()?,
+            _ => DnsProtocol::Udp,
+        };
+
+        let dns_timeout_ms = std::env::var("DNS_TIMEOUT_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&ms| ms > 0);
+
+        let dns_attempts = std::env::var("DNS_ATTEMPTS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .filter(|&attempts| attempts > 0);
+
+        let dns_edns = std::env::var("DNS_EDNS")
+            .ok()
+            .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "on"));
+
+        // Lookup deadline and circuit breaker, so an upstream outage never
+        // holds requests up for long
+        let dns_deadline_ms = std::env::var("DNS_DEADLINE_MS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(2000);
+
+        let dns_breaker_failures = std::env::var("DNS_BREAKER_FAILURES")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(5);
+
+        let dns_breaker_cooldown_secs = std::env::var("DNS_BREAKER_COOLDOWN_SECS")
+            .ok()
+            .and_then(|v| v.parse().ok())
+            .unwrap_or(30);
+
+        // DNSSEC validation, done here against the configured trust anchors
+        let dns_trust_anchor_file = std::env::var("DNS_TRUST_ANCHOR_FILE")
+            .ok()
+            .filter(|path| !path.is_empty())
+            .map(PathBuf::from);
+
         // Request timeout
         let request_timeout_secs = std::env::var("REQUEST_TIMEOUT_SECS")
             .ok()
             .and_then(|v| v.parse().ok())
             .unwrap_or(30);
 
-        Ok(Config {
+        // Trusted proxies (defaults to a reverse proxy on the same host)
+        let trusted_proxies = network::parse_cidr_list(
+            &std::env::var("TRUSTED_PROXIES").unwrap_or_else(|_| "127.0.0.1/32,::1/128".into()),
+        )?;
+
+        // PROXY protocol from command line or environment
+        let proxy_protocol = crate::utils::cli::parse_proxy_protocol()
+            || std::env::var("PROXY_PROTOCOL")
+                .map(|v| matches!(v.to_lowercase().as_str(), "1" | "true" | "on"))
+                .unwrap_or(false);
+
+        // CDN presets
+        let cdn_presets = std::env::var("CDN_PRESETS")
+            .unwrap_or_default()
+            .split(',')
+            .filter(|name| !name.trim().is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<CdnPreset>, _>>()?;
+
+        let cdn_ranges_dir = std::env::var("CDN_RANGES_DIR")
+            .unwrap_or_else(|_| "/etc/ip-api/cdn".into())
+            .into();
+
+        let mut config = Config {
             port,
             rate_limit_requests,
             rate_limit_window_secs,
+            rate_limit_algorithm,
+            rate_limit_burst,
+            rate_limit_ipv4_prefix,
+            rate_limit_ipv6_prefix,
+            rate_limit_exempt,
+            rate_limit_policies: Vec::new(),
+            rate_limit_redis_url,
+            rate_limit_redis_prefix,
+            rate_limit_redis_timeout_ms,
+            rate_limit_fail_mode,
+            ban_max_strikes,
+            ban_find_time_secs,
+            ban_time_secs,
+            ban_max_time_secs,
+            ban_file,
+            admin_token,
+            snapshot_file,
+            snapshot_interval_secs,
+            gossip_bind,
+            gossip_peers,
+            gossip_secret,
+            gossip_interval_ms,
             dns_cache_ttl_secs,
+            dns_cache_min_ttl_secs,
+            dns_cache_max_ttl_secs,
+            dns_cache_negative_ttl_secs,
+            dns_cache_max_entries,
+            dns_cache_stale_secs,
+            dns_cache_prefetch_secs,
+            dns_cache_prefetch_min_hits,
+            dns_cache_redis_url,
+            dns_cache_redis_prefix,
+            dns_cache_redis_timeout_ms,
+            dns_servers,
+            dns_protocol,
+            dns_timeout_ms,
+            dns_attempts,
+            dns_edns,
+            dns_deadline_ms,
+            dns_breaker_failures,
+            dns_breaker_cooldown_secs,
+            dns_trust_anchor_file,
             request_timeout_secs,
-        })
+            trusted_proxies,
+            proxy_protocol,
+            cdn_presets,
+            cdn_ranges_dir,
+        };
+
+        // Named per-route policies, inheriting unset limits from the default
+        let names = std::env::var("RATE_LIMIT_POLICIES").unwrap_or_default();
+        for name in names.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+            if name.eq_ignore_ascii_case("default") {
+                return Err("rate limit policy name default is reserved".into());
+            }
+            // Names that differ only in case or '-' vs '_' share variables
+            let key = |n: &str| n.to_uppercase().replace('-', "_");
+            if let Some(other) = config
+                .rate_limit_policies
+                .iter()
+                .find(|p| key(&p.name) == key(name))
+            {
+                return Err(format!(
+                    "rate limit policy {} is listed more than once (as {})",
+                    name, other.name
+                )
+                .into());
+            }
+
+            let policy = RateLimitPolicyConfig::from_env(name, &config)?;
+            for route in &policy.routes {
+                if let Some(other) = config
+                    .rate_limit_policies
+                    .iter()
+                    .find(|p| p.routes.contains(route))
+                {
+                    return Err(format!(
+                        "route {} is in rate limit policies {} and {}",
+                        route, other.name, policy.name
+                    )
+                    .into());
+                }
+            }
+            config.rate_limit_policies.push(policy);
+        }
+
+        Ok(config)
+    }
+
+    /// Build the rate limit policies: the default one and each named policy
+    ///
+    /// With a Redis URL every policy keeps its state in Redis, under its own
+    /// key prefix; otherwise each policy has its own in-memory store.
+    pub fn rate_limit_policies(&self) -> Result<RateLimitPolicies, String> {
+        let connection = match &self.rate_limit_redis_url {
+            Some(url) => Some(RedisStore::connect(
+                url,
+                Duration::from_millis(self.rate_limit_redis_timeout_ms),
+            )?),
+            None => None,
+        };
+
+        let with_store = |limiter: RateLimiter| match &connection {
+            Some(connection) => {
+                let prefix = format!("{}:{}", self.rate_limit_redis_prefix, limiter.name());
+                let store = RedisStore::new(connection.clone(), prefix);
+                limiter.with_store(Arc::new(store), self.rate_limit_fail_mode)
+            }
+            None => limiter,
+        };
+
+        let default = RateLimiter::new(self.rate_limit_requests, self.rate_limit_window())
+            .with_algorithm(self.rate_limit_algorithm, self.rate_limit_burst)
+            .with_prefixes(self.rate_limit_ipv4_prefix, self.rate_limit_ipv6_prefix)
+            .with_exemptions(self.rate_limit_exempt.clone());
+
+        Ok(self.rate_limit_policies.iter().fold(
+            RateLimitPolicies::new(with_store(default)),
+            |policies, policy| {
+                let limiter =
+                    RateLimiter::new(policy.requests, Duration::from_secs(policy.window_secs))
+                        .with_name(&policy.name)
+                        .with_algorithm(policy.algorithm, policy.burst)
+                        .with_prefixes(self.rate_limit_ipv4_prefix, self.rate_limit_ipv6_prefix)
+                        .with_exemptions(policy.exempt.clone());
+                policies.with_policy(with_store(limiter), &policy.routes)
+            },
+        ))
+    }
+
+    /// Automatic ban settings, banning clients by their rate limit prefix
+    pub fn ban_config(&self) -> BanConfig {
+        BanConfig {
+            max_strikes: self.ban_max_strikes,
+            find_time: Duration::from_secs(self.ban_find_time_secs),
+            ban_time: Duration::from_secs(self.ban_time_secs),
+            max_ban_time: Duration::from_secs(self.ban_max_time_secs),
+            ipv4_prefix: self.rate_limit_ipv4_prefix,
+            ipv6_prefix: self.rate_limit_ipv6_prefix,
+        }
     }
 
     /// Get rate limit window as Duration
@@ -64,13 +696,100 @@ impl Config {
         Duration::from_secs(self.rate_limit_window_secs)
     }
 
+    /// Build the DNS cache, backed by Redis as a second tier if configured
+    pub fn dns_cache(&self) -> Result<DnsCache, String> {
+        let cache = DnsCache::new(
+            self.dns_cache_max_entries,
+            self.dns_cache_ttl(),
+            self.dns_cache_negative_ttl(),
+        )
+        .with_ttl_bounds(self.dns_cache_min_ttl(), self.dns_cache_max_ttl())
+        .with_stale(self.dns_cache_stale())
+        .with_prefetch(self.dns_cache_prefetch(), self.dns_cache_prefetch_min_hits);
+
+        Ok(match &self.dns_cache_redis_url {
+            Some(url) => {
+                let connection = RedisStore::connect(
+                    url,
+                    Duration::from_millis(self.dns_cache_redis_timeout_ms),
+                )?;
+                let store = RedisDnsStore::new(connection, &self.dns_cache_redis_prefix);
+                cache.with_l2(Arc::new(store))
+            }
+            None => cache,
+        })
+    }
+
     /// Get DNS cache TTL as Duration
     pub fn dns_cache_ttl(&self) -> Duration {
         Duration::from_secs(self.dns_cache_ttl_secs)
     }
 
+    /// Get DNS cache negative TTL as Duration
+    pub fn dns_cache_negative_ttl(&self) -> Duration {
+        Duration::from_secs(self.dns_cache_negative_ttl_secs)
+    }
+
+    /// Get DNS cache minimum TTL as Duration
+    pub fn dns_cache_min_ttl(&self) -> Duration {
+        Duration::from_secs(self.dns_cache_min_ttl_secs)
+    }
+
+    /// Get DNS cache maximum TTL as Duration
+    pub fn dns_cache_max_ttl(&self) -> Duration {
+        Duration::from_secs(self.dns_cache_max_ttl_secs)
+    }
+
+    /// Get DNS cache serve-stale window as Duration
+    pub fn dns_cache_stale(&self) -> Duration {
+        Duration::from_secs(self.dns_cache_stale_secs)
+    }
+
+    /// Get DNS cache prefetch window as Duration
+    pub fn dns_cache_prefetch(&self) -> Duration {
+        Duration::from_secs(self.dns_cache_prefetch_secs)
+    }
+
+    /// DNS resolver settings
+    pub fn dns_resolver_config(&self) -> DnsResolverConfig {
+        DnsResolverConfig {
+            servers: self.dns_servers.clone(),
+            protocol: self.dns_protocol,
+            timeout: self.dns_timeout_ms.map(Duration::from_millis),
+            attempts: self.dns_attempts,
+            edns: self.dns_edns,
+            deadline: (self.dns_deadline_ms > 0)
+                .then(|| Duration::from_millis(self.dns_deadline_ms)),
+            breaker_failures: self.dns_breaker_failures,
+            breaker_cooldown: Duration::from_secs(self.dns_breaker_cooldown_secs),
+            trust_anchor: self.dns_trust_anchor_file.clone(),
+        }
+    }
+
+    /// Gossip settings, if gossip is enabled
+    pub fn gossip_config(&self) -> Option<GossipConfig> {
+        Some(GossipConfig {
+            bind: self.gossip_bind?,
+            peers: self.gossip_peers.clone(),
+            secret: self.gossip_secret.clone()?.into_bytes(),
+            interval: Duration::from_millis(self.gossip_interval_ms),
+        })
+    }
+
     /// Get request timeout as Duration
     pub fn request_timeout(&self) -> Duration {
         Duration::from_secs(self.request_timeout_secs)
     }
 }
+
+/// Reject rate limit windows longer than [`MAX_WINDOW`]
+fn check_window(name: &str, secs: u64) -> Result<(), String> {
+    if secs > MAX_WINDOW.as_secs() {
+        return Err(format!(
+            "{} must be at most {} (one year)",
+            name,
+            MAX_WINDOW.as_secs()
+        ));
+    }
+    Ok(())
+}
@@ -0,0 +1,95 @@
+//! Admin API handlers for managing bans
+
+use crate::utils::{bans::Ban, network::Cidr};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Json, Response},
+};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::time::Duration;
+
+/// Ban list response
+#[derive(Serialize)]
+pub struct BansResponse {
+    bans: Vec<Ban>,
+}
+
+/// Request body for a manual ban
+#[derive(Deserialize)]
+pub struct BanRequest {
+    /// Network or single address to ban
+    network: String,
+
+    /// Why the network is banned
+    reason: Option<String>,
+
+    /// Ban duration in seconds (permanent if omitted)
+    duration_secs: Option<u64>,
+}
+
+/// Query parameters for lifting a ban
+#[derive(Deserialize)]
+pub struct UnbanQuery {
+    network: String,
+}
+
+/// Handler for GET /admin/bans endpoint
+///
+/// Lists active bans with their reasons
+pub async fn list_bans(State(state): State<crate::AppState>) -> Json<BansResponse> {
+    Json(BansResponse {
+        bans: state.bans.list().await,
+    })
+}
+
+/// Handler for POST /admin/bans endpoint
+///
+/// Bans a network by hand
+pub async fn create_ban(
+    State(state): State<crate::AppState>,
+    Json(request): Json<BanRequest>,
+) -> Response {
+    let network = match request.network.parse::<Cidr>() {
+        Ok(network) => network,
+        Err(e) => return bad_request(&e),
+    };
+
+    let ban = state
+        .bans
+        .ban(
+            network,
+            request.reason.unwrap_or_else(|| "manual".to_string()),
+            request.duration_secs.map(Duration::from_secs),
+        )
+        .await;
+
+    (StatusCode::CREATED, Json(ban)).into_response()
+}
+
+/// Handler for DELETE /admin/bans?network=<network> endpoint
+///
+/// Lifts a ban and forgets the network's offenses
+pub async fn remove_ban(
+    State(state): State<crate::AppState>,
+    Query(query): Query<UnbanQuery>,
+) -> Response {
+    let network = match query.network.parse::<Cidr>() {
+        Ok(network) => network,
+        Err(e) => return bad_request(&e),
+    };
+
+    match state.bans.unban(&network).await {
+        Some(ban) => Json(ban).into_response(),
+        None => (
+            StatusCode::NOT_FOUND,
+            Json(json!({ "error": "Network is not banned" })),
+        )
+            .into_response(),
+    }
+}
+
+fn bad_request(error: &str) -> Response {
+    (StatusCode::BAD_REQUEST, Json(json!({ "error": error }))).into_response()
+}
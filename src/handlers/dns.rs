@@ -0,0 +1,95 @@
+//! DNS query endpoint handler
+
+use crate::models::{DnsRecord, DnsResponse, ResponseFormat};
+use crate::utils::{
+    bans::Offense,
+    client_ip::ClientIp,
+    dns::{self, LookupStatus},
+    security,
+};
+use axum::{
+    Extension,
+    extract::{Query, State},
+    http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
+};
+use hickory_resolver::proto::rr::RecordType;
+use serde::Deserialize;
+use std::net::IpAddr;
+
+/// Query parameters for DNS queries
+#[derive(Deserialize)]
+pub struct DnsQuery {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: Option<String>,
+    format: Option<String>,
+}
+
+/// Handler for GET /dns endpoint
+///
+/// Looks up records of one type (A by default) for a hostname through the
+/// shared resolver and DNS cache. PTR queries also accept an IP address.
+pub async fn query_dns(
+    Extension(client): Extension<ClientIp>,
+    State(state): State<crate::AppState>,
+    headers: HeaderMap,
+    Query(query): Query<DnsQuery>,
+) -> Result<Response, StatusCode> {
+    let format = ResponseFormat::negotiate(query.format.as_deref(), &headers);
+
+    // Validate the record type and name
+    let record_type = match query.record_type.as_deref() {
+        Some(record_type) => dns::parse_record_type(record_type),
+        None => Some(RecordType::A),
+    };
+    let reverse_ip = match record_type {
+        Some(RecordType::PTR) => {
+            security::sanitize_ip(&query.name).and_then(|ip| ip.parse::<IpAddr>().ok())
+        }
+        _ => None,
+    };
+    let name = match reverse_ip {
+        Some(ip) => Some(ip.to_string()),
+        None => security::sanitize_hostname(&query.name),
+    };
+    let (Some(record_type), Some(name)) = (record_type, name) else {
+        state
+            .bans
+            .record_offense(client.ip, Offense::InvalidDnsQuery)
+            .await;
+        return Err(StatusCode::BAD_REQUEST);
+    };
+
+    // An address in a PTR query shares the reverse lookups of `/` and `/lookup`
+    let answer = match reverse_ip {
+        Some(ip) => dns::reverse_lookup_cached(ip, &state.resolver, &state.dns_cache).await,
+        None => dns::query_cached(&name, record_type, &state.resolver, &state.dns_cache).await,
+    };
+
+    let response = DnsResponse {
+        name,
+        record_type: record_type.to_string(),
+        status: LookupStatus::of(&answer),
+        ttl: answer.ttl.as_secs(),
+        dnssec: state.resolver.dnssec_status(&answer),
+        records: answer
+            .value
+            .unwrap_or_default()
+            .into_iter()
+            .map(|data| DnsRecord::new(record_type, data))
+            .collect(),
+    };
+
+    Ok(match format {
+        ResponseFormat::Json => axum::Json(response).into_response(),
+        ResponseFormat::PlainText => (
+            [(
+                axum::http::header::CONTENT_TYPE,
+                "text/plain; charset=utf-8",
+            )],
+            response.to_plain_text(),
+        )
+            .into_response(),
+    })
+}
@@ -1,6 +1,7 @@
 //! Request headers endpoint handler
 
-use axum::{http::HeaderMap, response::Json};
+use crate::utils::{listener::ConnectionInfo, proxy_protocol::ProxyTlvs};
+use axum::{extract::ConnectInfo, http::HeaderMap, response::Json};
 use serde::Serialize;
 use std::collections::HashMap;
 
@@ -8,12 +9,19 @@ use std::collections::HashMap;
 #[derive(Serialize)]
 pub struct HeadersResponse {
     headers: HashMap<String, String>,
+
+    /// PROXY protocol TLVs, present on PROXY protocol listeners
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proxy_protocol: Option<ProxyTlvs>,
 }
 
 /// Handler for GET /headers endpoint
 ///
 /// Returns all request headers for debugging purposes
-pub async fn get_headers(headers: HeaderMap) -> Json<HeadersResponse> {
+pub async fn get_headers(
+    ConnectInfo(connection): ConnectInfo<ConnectionInfo>,
+    headers: HeaderMap,
+) -> Json<HeadersResponse> {
     let mut headers_map = HashMap::new();
 
     for (name, value) in headers.iter() {
@@ -24,5 +32,6 @@ pub async fn get_headers(headers: HeaderMap) -> Json<HeadersResponse> {
 
     Json(HeadersResponse {
         headers: headers_map,
+        proxy_protocol: connection.proxy.map(|header| header.tlvs),
     })
 }
@@ -1,6 +1,7 @@
 //! Health check endpoint handler
 
-use axum::{http::StatusCode, response::Json};
+use crate::utils::circuit_breaker::BreakerStatus;
+use axum::{extract::State, http::StatusCode, response::Json};
 use serde::Serialize;
 use std::time::SystemTime;
 
@@ -10,6 +11,7 @@ pub struct HealthResponse {
     status: String,
     timestamp: u64,
     uptime_seconds: u64,
+    dns_breaker: BreakerStatus,
 }
 
 lazy_static::lazy_static! {
@@ -18,11 +20,16 @@ lazy_static::lazy_static! {
 
 /// Handler for GET /health endpoint
 ///
-/// Returns basic health status and uptime information
-pub async fn health_check() -> Result<Json<HealthResponse>, StatusCode> {
+/// Returns basic health status, uptime information and the state of the
+/// reverse DNS circuit breaker
+pub async fn health_check(
+    State(state): State<crate::AppState>,
+) -> Result<Json<HealthResponse>, StatusCode> {
+    // Set on the first call, so it must be read before `now`
+    let start_time = *START_TIME;
     let now = SystemTime::now();
     let uptime = now
-        .duration_since(*START_TIME)
+        .duration_since(start_time)
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
         .as_secs();
 
@@ -35,5 +42,6 @@ pub async fn health_check() -> Result<Json<HealthResponse>, StatusCode> {
         status: "healthy".to_string(),
         timestamp,
         uptime_seconds: uptime,
+        dns_breaker: state.resolver.breaker_status(),
     }))
 }
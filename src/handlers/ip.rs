@@ -1,63 +1,116 @@
 //! IP information endpoint handler
 
-use crate::models::{IpResponse, ResponseFormat};
-use crate::utils::{dns, security, time};
+use crate::models::{IpResponse, ProxyHop, ResponseFormat};
+use crate::utils::{
+    bans::Offense,
+    cache::DnsCache,
+    client_ip::{self, ClientIp},
+    dns::{self, DnsResolver, LookupStatus},
+    security, time,
+};
 use axum::{
-    extract::{ConnectInfo, Query, State},
+    Extension,
+    extract::{Query, State},
     http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response},
 };
 use serde::Deserialize;
-use std::net::SocketAddr;
+use std::net::IpAddr;
+use std::sync::Arc;
 
 /// Query parameters for IP endpoint
 #[derive(Deserialize)]
 pub struct IpQuery {
     format: Option<String>,
+    chain: Option<String>,
+    verify: Option<String>,
 }
 
 /// Handler for GET / endpoint
 ///
-/// Extracts client IP from connection or X-Forwarded-For header,
-/// performs reverse DNS lookup, and returns comprehensive client information.
+/// Uses the client IP resolved through trusted proxies, performs reverse
+/// DNS lookup, and returns comprehensive client information. The reverse
+/// DNS is forward-confirmed with `?verify=1`.
 pub async fn get_ip_info(
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Extension(client): Extension<ClientIp>,
     State(state): State<crate::AppState>,
     headers: HeaderMap,
     Query(query): Query<IpQuery>,
 ) -> Result<Response, StatusCode> {
     // Determine response format from query param or Accept header
-    let format = determine_format(&query, &headers);
-
-    // Extract client IP from X-Forwarded-For or direct connection
-    let client_ip = extract_client_ip(&headers, addr);
+    let format = ResponseFormat::negotiate(query.format.as_deref(), &headers);
 
-    // Validate and sanitize IP address
-    let client_ip = security::sanitize_ip(&client_ip).ok_or(StatusCode::BAD_REQUEST)?;
+    // Client IP as resolved by the client IP middleware
+    let client_ip = client.ip.to_string();
 
     // Get user agent from headers
     let user_agent = extract_user_agent(&headers);
 
     // Validate user agent if present
-    if let Some(ref ua) = user_agent {
-        if !security::is_valid_user_agent(ua) {
-            return Err(StatusCode::BAD_REQUEST);
-        }
+    if let Some(ref ua) = user_agent
+        && !security::is_valid_user_agent(ua)
+    {
+        state
+            .bans
+            .record_offense(client.ip, Offense::InvalidUserAgent)
+            .await;
+        return Err(StatusCode::BAD_REQUEST);
     }
 
     // Perform reverse DNS lookup (non-blocking, with cache)
-    let rdns = dns::reverse_lookup_cached(&client_ip, state.dns_cache.clone()).await;
+    let rdns = dns::reverse_lookup_cached(client.ip, &state.resolver, &state.dns_cache).await;
+    let rdns_status = LookupStatus::of(&rdns);
+    let rdns_dnssec = state.resolver.dnssec_status(&rdns);
+
+    // Forward-confirm the PTR names only when asked to, as it costs up to
+    // one lookup per name
+    let rdns_verified = if is_enabled(query.verify.as_deref()) {
+        Some(
+            dns::verify(
+                client.ip,
+                rdns.value.as_deref(),
+                &state.resolver,
+                &state.dns_cache,
+            )
+            .await,
+        )
+    } else {
+        None
+    };
+
+    // Optionally report every hop between the client and the server
+    let chain = if is_enabled(query.chain.as_deref()) {
+        let hops = client_ip::chain(&headers, &client, &state.trusted_proxies);
+        Some(
+            lookup_chain(
+                hops,
+                client.ip,
+                state.resolver.clone(),
+                state.dns_cache.clone(),
+            )
+            .await,
+        )
+    } else {
+        None
+    };
 
     // Get current timestamps in various formats
     let (unix_timestamp, utc_time, local_time) = time::get_timestamps()?;
 
     let response = IpResponse {
         ip: client_ip,
-        rdns,
+        ip_source: Some(client.source.name().to_string()),
+        rdns: rdns.value.as_ref().and_then(|names| names.first().cloned()),
+        rdns_names: rdns.value,
+        rdns_ttl: Some(rdns.ttl.as_secs()),
+        rdns_status: Some(rdns_status),
+        rdns_dnssec,
+        rdns_verified,
         user_agent,
         unix_timestamp,
         utc_time,
         local_time,
+        chain,
     };
 
     // Return response in requested format
@@ -74,37 +127,51 @@ pub async fn get_ip_info(
     })
 }
 
-/// Determine response format from query parameter or Accept header
-fn determine_format(query: &IpQuery, headers: &HeaderMap) -> ResponseFormat {
-    // Check query parameter first
-    if let Some(ref fmt) = query.format {
-        return match fmt.to_lowercase().as_str() {
-            "text" | "plain" | "txt" => ResponseFormat::PlainText,
-            _ => ResponseFormat::Json,
-        };
-    }
+/// Check whether a boolean query flag is set
+pub(crate) fn is_enabled(flag: Option<&str>) -> bool {
+    matches!(flag, Some("1" | "true" | "yes"))
+}
 
-    // Check Accept header
-    if let Some(accept) = headers.get("accept") {
-        if let Ok(accept_str) = accept.to_str() {
-            if accept_str.contains("text/plain") {
-                return ResponseFormat::PlainText;
-            }
-        }
+/// Resolve reverse DNS for the client and every trusted hop concurrently
+///
+/// Untrusted hops are whatever the client chose to write into its forwarding
+/// headers, so they are not looked up; otherwise one request could trigger
+/// up to 16 lookups of arbitrary addresses.
+async fn lookup_chain(
+    hops: Vec<client_ip::ChainHop>,
+    client: IpAddr,
+    resolver: Arc<DnsResolver>,
+    cache: Arc<DnsCache>,
+) -> Vec<ProxyHop> {
+    let lookups: Vec<_> = hops
+        .iter()
+        .map(|hop| {
+            let ip = hop.ip.filter(|ip| hop.trusted || *ip == client);
+            let resolver = resolver.clone();
+            let cache = cache.clone();
+            tokio::spawn(async move {
+                match ip {
+                    Some(ip) => dns::reverse_lookup_cached(ip, &resolver, &cache)
+                        .await
+                        .value
+                        .and_then(|names| names.into_iter().next()),
+                    None => None,
+                }
+            })
+        })
+        .collect();
+
+    let mut chain = Vec::with_capacity(hops.len());
+    for (hop, lookup) in hops.into_iter().zip(lookups) {
+        chain.push(ProxyHop {
+            ip: hop.node,
+            trusted: hop.trusted,
+            source: hop.source.name().to_string(),
+            rdns: lookup.await.ok().flatten(),
+        });
     }
 
-    // Default to JSON
-    ResponseFormat::Json
-}
-
-/// Extract client IP from X-Forwarded-For header or direct connection
-fn extract_client_ip(headers: &HeaderMap, addr: SocketAddr) -> String {
-    headers
-        .get("x-forwarded-for")
-        .and_then(|h| h.to_str().ok())
-        .and_then(|s| s.split(',').next())
-        .map(|s| s.trim().to_string())
-        .unwrap_or_else(|| addr.ip().to_string())
+    chain
 }
 
 /// Extract user agent from headers
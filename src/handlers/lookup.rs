@@ -1,42 +1,80 @@
 //! IP lookup endpoint handler
 
+use super::ip::is_enabled;
 use crate::models::IpResponse;
-use crate::utils::{dns, security, time};
+use crate::utils::{bans::Offense, client_ip::ClientIp, dns, security, time};
 use axum::{
+    Extension,
     extract::{Query, State},
     http::StatusCode,
     response::Json,
 };
 use serde::Deserialize;
+use std::net::IpAddr;
 
 /// Query parameters for IP lookup
 #[derive(Deserialize)]
 pub struct LookupQuery {
     ip: String,
+    verify: Option<String>,
 }
 
 /// Handler for GET /lookup endpoint
 ///
-/// Looks up information for any specified IP address
+/// Looks up information for any specified IP address, forward-confirming
+/// its reverse DNS with `?verify=1`
 pub async fn lookup_ip(
+    Extension(client): Extension<ClientIp>,
     State(state): State<crate::AppState>,
     Query(query): Query<LookupQuery>,
 ) -> Result<Json<IpResponse>, StatusCode> {
     // Validate and sanitize IP address
-    let ip = security::sanitize_ip(&query.ip).ok_or(StatusCode::BAD_REQUEST)?;
+    let Some(ip) = security::sanitize_ip(&query.ip) else {
+        state
+            .bans
+            .record_offense(client.ip, Offense::InvalidIp)
+            .await;
+        return Err(StatusCode::BAD_REQUEST);
+    };
+
+    let addr: IpAddr = ip.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
 
     // Perform reverse DNS lookup (non-blocking, with cache)
-    let rdns = dns::reverse_lookup_cached(&ip, state.dns_cache.clone()).await;
+    let rdns = dns::reverse_lookup_cached(addr, &state.resolver, &state.dns_cache).await;
+    let rdns_status = dns::LookupStatus::of(&rdns);
+    let rdns_dnssec = state.resolver.dnssec_status(&rdns);
+
+    // Forward-confirm the PTR names only when asked to
+    let rdns_verified = if is_enabled(query.verify.as_deref()) {
+        Some(
+            dns::verify(
+                addr,
+                rdns.value.as_deref(),
+                &state.resolver,
+                &state.dns_cache,
+            )
+            .await,
+        )
+    } else {
+        None
+    };
 
     // Get current timestamps
     let (unix_timestamp, utc_time, local_time) = time::get_timestamps()?;
 
     Ok(Json(IpResponse {
         ip,
-        rdns,
+        ip_source: None, // Not a client address
+        rdns: rdns.value.as_ref().and_then(|names| names.first().cloned()),
+        rdns_names: rdns.value,
+        rdns_ttl: Some(rdns.ttl.as_secs()),
+        rdns_status: Some(rdns_status),
+        rdns_dnssec,
+        rdns_verified,
         user_agent: None, // No user agent for arbitrary IP lookups
         unix_timestamp,
         utc_time,
         local_time,
+        chain: None,
     }))
 }
@@ -1,5 +1,6 @@
 //! Metrics endpoint handler
 
+use crate::utils::cache::DnsCacheStats;
 use axum::{extract::State, http::StatusCode, response::Json};
 use serde::Serialize;
 use std::time::SystemTime;
@@ -12,6 +13,7 @@ pub struct MetricsResponse {
     failed_requests: u64,
     uptime_seconds: u64,
     timestamp: u64,
+    dns_cache: DnsCacheStats,
 }
 
 lazy_static::lazy_static! {
@@ -24,9 +26,11 @@ lazy_static::lazy_static! {
 pub async fn get_metrics(
     State(state): State<crate::AppState>,
 ) -> Result<Json<MetricsResponse>, StatusCode> {
+    // Set on the first call, so it must be read before `now`
+    let start_time = *START_TIME;
     let now = SystemTime::now();
     let uptime = now
-        .duration_since(*START_TIME)
+        .duration_since(start_time)
         .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?
         .as_secs();
 
@@ -41,5 +45,6 @@ pub async fn get_metrics(
         failed_requests: state.metrics.failure(),
         uptime_seconds: uptime,
         timestamp,
+        dns_cache: state.dns_cache.stats().await,
     }))
 }
@@ -1,8 +1,11 @@
 //! HTTP request handlers
 
-pub mod ip;
+pub mod admin;
+pub mod dns;
+pub mod headers;
 pub mod health;
+pub mod ip;
+pub mod lookup;
 pub mod metrics;
-pub mod headers;
+pub mod quota;
 pub mod version;
-pub mod lookup;
\ No newline at end of file
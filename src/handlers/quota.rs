@@ -0,0 +1,90 @@
+//! Rate limit quota endpoint handler
+
+use crate::utils::client_ip::ClientIp;
+use axum::{
+    Extension,
+    extract::{Query, State},
+    response::Json,
+};
+use serde::{Deserialize, Serialize};
+
+/// Query parameters for quota endpoint
+#[derive(Deserialize)]
+pub struct QuotaQuery {
+    route: Option<String>,
+}
+
+/// Rate limit quota response
+#[derive(Serialize)]
+pub struct QuotaResponse {
+    /// Route the quota applies to
+    route: String,
+
+    /// Name of the policy applied to the route
+    policy_name: String,
+
+    /// Whether the client is exempt from the policy
+    exempt: bool,
+
+    /// Current usage, absent for exempt clients
+    #[serde(flatten)]
+    usage: Option<QuotaUsage>,
+}
+
+/// Usage of a rate limit policy
+#[derive(Serialize)]
+pub struct QuotaUsage {
+    /// Requests the client may send at once
+    limit: usize,
+
+    /// Requests counted against the current quota
+    used: usize,
+
+    /// Requests left before the client is limited
+    remaining: usize,
+
+    /// Seconds until the quota is fully restored
+    reset: u64,
+
+    /// Rate limiting algorithm
+    algorithm: &'static str,
+
+    /// Policy in `RateLimit-Policy` syntax
+    policy: String,
+}
+
+/// Handler for GET /quota endpoint
+///
+/// Reports the caller's current rate limit usage for `route` (defaults to
+/// `/quota` itself, whose request has already been counted). Looking up
+/// another route does not count against that route's quota.
+pub async fn get_quota(
+    Extension(client): Extension<ClientIp>,
+    State(state): State<crate::AppState>,
+    Query(query): Query<QuotaQuery>,
+) -> Json<QuotaResponse> {
+    let route = query.route.unwrap_or_else(|| "/quota".to_string());
+    let limiter = state.rate_limits.for_path(&route);
+    let exempt = limiter.is_exempt(&client.ip);
+
+    let usage = if exempt {
+        None
+    } else {
+        let status = limiter.peek(client.ip).await;
+        Some(QuotaUsage {
+            limit: status.limit,
+            used: status.limit.saturating_sub(status.remaining),
+            remaining: status.remaining,
+            reset: status.reset_secs(),
+            algorithm: limiter.algorithm_name(),
+            policy: limiter.policy(),
+        })
+    };
+
+    Json(QuotaResponse {
+        route,
+        policy_name: limiter.name().to_string(),
+        exempt,
+        usage,
+    })
+}
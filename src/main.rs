@@ -10,18 +10,29 @@ mod middleware;
 mod models;
 mod utils;
 
-use axum::{middleware as axum_middleware, routing::get, Router};
+use axum::{Router, middleware as axum_middleware, routing::get};
 use config::Config;
-use std::net::SocketAddr;
+use middleware::{admin_auth::AdminAuth, rate_limit::RateLimitPolicies};
 use std::sync::Arc;
-use tokio::net::TcpListener;
-use utils::{cache::DnsCache, metrics::Metrics};
+use utils::{
+    bans::BanList,
+    cache::DnsCache,
+    client_ip::TrustedProxies,
+    dns::DnsResolver,
+    gossip::Gossip,
+    listener::{ConnectionInfo, ConnectionListener},
+    metrics::Metrics,
+};
 
 /// Application state shared across handlers
 #[derive(Clone)]
 pub struct AppState {
     pub metrics: Arc<Metrics>,
     pub dns_cache: Arc<DnsCache>,
+    pub resolver: Arc<DnsResolver>,
+    pub trusted_proxies: Arc<TrustedProxies>,
+    pub rate_limits: Arc<RateLimitPolicies>,
+    pub bans: Arc<BanList>,
 }
 
 #[tokio::main]
@@ -37,21 +48,70 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tracing::info!(
         port = config.port,
         rate_limit = config.rate_limit_requests,
+        rate_limit_algorithm = ?config.rate_limit_algorithm,
+        rate_limit_burst = config.rate_limit_burst,
+        rate_limit_policies = config.rate_limit_policies.len(),
+        rate_limit_redis = config.rate_limit_redis_url.is_some(),
+        dns_cache_redis = config.dns_cache_redis_url.is_some(),
+        ban_max_strikes = config.ban_max_strikes,
+        ban_time = config.ban_time_secs,
+        admin_api = config.admin_token.is_some(),
+        gossip = ?config.gossip_bind,
+        dns_servers = ?config.dns_servers,
+        dns_protocol = ?config.dns_protocol,
+        dns_deadline_ms = config.dns_deadline_ms,
+        dns_breaker_failures = config.dns_breaker_failures,
+        dns_dnssec = config.dns_trust_anchor_file.is_some(),
         request_timeout = config.request_timeout_secs,
+        trusted_proxies = config.trusted_proxies.len(),
+        proxy_protocol = config.proxy_protocol,
+        cdn_presets = ?config.cdn_presets,
         "Configuration loaded"
     );
 
     // Determine bind address based on port
     let bind_addr = utils::network::get_bind_address(config.port);
 
-    // Create rate limiter
-    let rate_limiter = Arc::new(middleware::rate_limit::RateLimiter::new(
-        config.rate_limit_requests,
-        config.rate_limit_window(),
-    ));
+    // Create rate limit policies
+    let rate_limits = Arc::new(config.rate_limit_policies()?);
+
+    // Create ban list, restoring bans saved by a previous run
+    let bans = Arc::new(BanList::new(config.ban_config(), config.ban_file.clone()));
+    match bans.load().await {
+        Ok(restored) => tracing::info!(bans = restored, "Ban list loaded"),
+        // Like a stale snapshot, a bad ban file only costs the saved bans
+        Err(e) => tracing::warn!(error = %e, "Ignoring ban file"),
+    }
 
     // Create DNS cache
-    let dns_cache = Arc::new(DnsCache::new(config.dns_cache_ttl()));
+    let dns_cache = Arc::new(config.dns_cache()?);
+
+    // Restore rate limiter, ban and DNS cache state saved on the last shutdown
+    if let Some(path) = &config.snapshot_file {
+        match utils::snapshot::load(path).await {
+            Ok(Some(snapshot)) => {
+                let restored =
+                    utils::snapshot::restore(snapshot, &rate_limits, &bans, &dns_cache).await;
+                tracing::info!(
+                    clients = restored.clients,
+                    bans = restored.bans,
+                    dns_entries = restored.dns_entries,
+                    "State snapshot restored"
+                );
+            }
+            Ok(None) => {}
+            // A stale snapshot only costs the saved state, so start anyway
+            Err(e) => tracing::warn!(error = %e, "Ignoring state snapshot"),
+        }
+    }
+
+    // Create trusted proxy list for client IP resolution
+    let cdn_ranges = utils::cdn::load_ranges(&config.cdn_ranges_dir, &config.cdn_presets)?;
+    let trusted_proxies =
+        Arc::new(TrustedProxies::new(config.trusted_proxies.clone()).with_cdn_presets(cdn_ranges));
+
+    // Create DNS resolver
+    let resolver = Arc::new(DnsResolver::new(&config.dns_resolver_config())?);
 
     // Create metrics collector
     let metrics = Arc::new(Metrics::new());
@@ -60,6 +120,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let app_state = AppState {
         metrics: metrics.clone(),
         dns_cache: dns_cache.clone(),
+        resolver,
+        trusted_proxies: trusted_proxies.clone(),
+        rate_limits: rate_limits.clone(),
+        bans: bans.clone(),
     };
 
     // Clone config for middleware
@@ -67,17 +131,78 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
 
     // Spawn cleanup task for rate limiter
     {
-        let cleanup_limiter = rate_limiter.clone();
+        let cleanup_limits = rate_limits.clone();
         tokio::spawn(async move {
             let mut interval = tokio::time::interval(std::time::Duration::from_secs(300));
             loop {
                 interval.tick().await;
-                cleanup_limiter.cleanup().await;
+                cleanup_limits.cleanup().await;
                 tracing::debug!("Rate limiter cleanup completed");
             }
         });
     }
 
+    // Spawn cleanup task for expired bans
+    {
+        let cleanup_bans = bans.clone();
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+            loop {
+                interval.tick().await;
+                cleanup_bans.cleanup().await;
+                tracing::debug!("Ban list cleanup completed");
+            }
+        });
+    }
+
+    // Spawn periodic snapshot task, so a crash loses less state
+    if let Some(path) = config.snapshot_file.clone()
+        && config.snapshot_interval_secs > 0
+    {
+        let snapshot_limits = rate_limits.clone();
+        let snapshot_bans = bans.clone();
+        let snapshot_dns = dns_cache.clone();
+        let period = std::time::Duration::from_secs(config.snapshot_interval_secs);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            interval.tick().await;
+            loop {
+                interval.tick().await;
+                save_snapshot(&path, &snapshot_limits, &snapshot_bans, &snapshot_dns).await;
+            }
+        });
+    }
+
+    // Admin API, only reachable with the configured bearer token
+    let admin = Router::new()
+        .route(
+            "/bans",
+            get(handlers::admin::list_bans)
+                .post(handlers::admin::create_ban)
+                .delete(handlers::admin::remove_ban),
+        )
+        .route_layer(axum_middleware::from_fn_with_state(
+            AdminAuth {
+                token: config.admin_token.as_deref().map(Arc::from),
+                bans: bans.clone(),
+            },
+            middleware::admin_auth::require_admin_token,
+        ));
+
+    // Share rate limits, bans and DNS results with peers
+    if let Some(gossip_config) = config.gossip_config() {
+        let peers = gossip_config.peers.len();
+        let gossip = Gossip::bind(
+            gossip_config,
+            rate_limits.clone(),
+            bans.clone(),
+            dns_cache.clone(),
+        )
+        .await?;
+        tracing::info!(bind = %gossip.local_addr()?, peers, "Gossip enabled");
+        tokio::spawn(Arc::new(gossip).run());
+    }
+
     // Spawn cleanup task for DNS cache
     {
         let cleanup_cache = dns_cache.clone();
@@ -100,6 +225,9 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/headers", get(handlers::headers::get_headers))
         .route("/version", get(handlers::version::get_version))
         .route("/lookup", get(handlers::lookup::lookup_ip))
+        .route("/dns", get(handlers::dns::query_dns))
+        .route("/quota", get(handlers::quota::get_quota))
+        .nest("/admin", admin)
         .with_state(app_state)
         .layer(axum_middleware::from_fn_with_state(
             metrics.clone(),
@@ -112,14 +240,27 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .layer(axum_middleware::from_fn(
             middleware::security_headers::add_security_headers,
         ))
-        .layer(axum_middleware::from_fn(move |req, next| {
-            let limiter = rate_limiter.clone();
-            middleware::rate_limit::rate_limit_middleware(limiter, req, next)
+        .layer(axum_middleware::from_fn({
+            let rate_limits = rate_limits.clone();
+            let bans = bans.clone();
+            move |req, next| {
+                let policies = rate_limits.clone();
+                let bans = bans.clone();
+                middleware::rate_limit::rate_limit_middleware(policies, bans, req, next)
+            }
         }))
-        .into_make_service_with_connect_info::<SocketAddr>();
+        .layer(axum_middleware::from_fn_with_state(
+            trusted_proxies.clone(),
+            middleware::client_ip::resolve_client_ip,
+        ))
+        .into_make_service_with_connect_info::<ConnectionInfo>();
 
     // Start server
-    let listener = TcpListener::bind(&bind_addr).await?;
+    let listener = ConnectionListener::bind(
+        &bind_addr,
+        config.proxy_protocol.then(|| trusted_proxies.clone()),
+    )
+    .await?;
 
     tracing::info!(
         bind_addr = %bind_addr,
@@ -134,8 +275,64 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("  GET /headers    - Request headers");
     println!("  GET /version    - API version");
     println!("  GET /lookup?ip= - Lookup any IP address");
+    println!("  GET /dns?name=  - Query DNS records");
+    println!("  GET /quota      - Rate limit usage");
+    if config.admin_token.is_some() {
+        println!("  *   /admin/bans - Ban list management");
+    }
+
+    axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown_signal())
+        .await?;
+
+    tracing::info!("Server stopped");
 
-    axum::serve(listener, app).await?;
+    if let Some(path) = &config.snapshot_file {
+        save_snapshot(path, &rate_limits, &bans, &dns_cache).await;
+    }
 
     Ok(())
 }
+
+/// Write a snapshot of the rate limiter, ban and DNS cache state
+async fn save_snapshot(
+    path: &std::path::Path,
+    rate_limits: &RateLimitPolicies,
+    bans: &BanList,
+    dns_cache: &DnsCache,
+) {
+    let snapshot = utils::snapshot::capture(rate_limits, bans, dns_cache).await;
+    match utils::snapshot::save(path, &snapshot).await {
+        Ok(()) => tracing::info!(path = %path.display(), "State snapshot saved"),
+        Err(e) => {
+            tracing::error!(path = %path.display(), error = %e, "Failed to save state snapshot")
+        }
+    }
+}
+
+/// Resolve on Ctrl+C or SIGTERM
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut signal) => {
+                signal.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+
+    tracing::info!("Shutdown signal received");
+}
@@ -0,0 +1,67 @@
+//! Admin API authentication middleware
+
+use crate::utils::{
+    bans::{BanList, Offense},
+    client_ip::ClientIp,
+    security,
+};
+use axum::{
+    Json,
+    body::Body,
+    extract::State,
+    http::{Request, StatusCode, header},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use serde_json::json;
+use std::sync::Arc;
+
+/// Admin API credentials
+#[derive(Clone)]
+pub struct AdminAuth {
+    /// Bearer token required by the admin API (disabled if `None`)
+    pub token: Option<Arc<str>>,
+
+    /// Ban list that failed attempts count against
+    pub bans: Arc<BanList>,
+}
+
+/// Middleware requiring `Authorization: Bearer <ADMIN_TOKEN>`
+///
+/// Without a configured token the admin API does not exist. Every request
+/// with a missing or wrong token counts as an offense towards a ban.
+pub async fn require_admin_token(
+    State(auth): State<AdminAuth>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let Some(token) = &auth.token else {
+        return StatusCode::NOT_FOUND.into_response();
+    };
+
+    let provided = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    if provided.is_some_and(|provided| {
+        security::constant_time_eq(provided.trim().as_bytes(), token.as_bytes())
+    }) {
+        return next.run(request).await;
+    }
+
+    if let Some(client) = request.extensions().get::<ClientIp>() {
+        tracing::warn!(client_ip = %client.ip, "Rejected admin API request");
+        auth.bans
+            .record_offense(client.ip, Offense::InvalidAdminToken)
+            .await;
+    }
+
+    (
+        StatusCode::UNAUTHORIZED,
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        Json(json!({ "error": "Invalid or missing admin token" })),
+    )
+        .into_response()
+}
@@ -0,0 +1,41 @@
+//! Client address resolution middleware
+
+use crate::utils::{
+    client_ip::{self, IpSource, TrustedProxies},
+    listener::ConnectionInfo,
+};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, State},
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+use std::sync::Arc;
+
+/// Middleware to resolve the client address once per request
+///
+/// The resolved [`client_ip::ClientIp`] is stored in the request extensions
+/// so that every later middleware and handler sees the same address. On
+/// PROXY protocol listeners the address from the PROXY header stands in for
+/// the TCP peer.
+pub async fn resolve_client_ip(
+    State(trusted): State<Arc<TrustedProxies>>,
+    ConnectInfo(connection): ConnectInfo<ConnectionInfo>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let peer = connection.source().ip();
+    let mut client = client_ip::resolve(request.headers(), peer, &trusted);
+
+    if connection.proxy.is_some() {
+        client.peer_source = IpSource::ProxyProtocol;
+        if client.source == IpSource::Direct {
+            client.source = IpSource::ProxyProtocol;
+        }
+    }
+
+    request.extensions_mut().insert(client);
+
+    next.run(request).await
+}
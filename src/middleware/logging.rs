@@ -1,21 +1,16 @@
 //! Request logging middleware
 
-use axum::{
-    body::Body,
-    extract::ConnectInfo,
-    http::Request,
-    middleware::Next,
-    response::Response,
-};
-use std::net::SocketAddr;
+use crate::utils::client_ip::ClientIp;
+use axum::{body::Body, http::Request, middleware::Next, response::Response};
 use std::time::Instant;
 
 /// Middleware to log requests
-pub async fn log_request(
-    ConnectInfo(addr): ConnectInfo<SocketAddr>,
-    request: Request<Body>,
-    next: Next,
-) -> Response {
+pub async fn log_request(request: Request<Body>, next: Next) -> Response {
+    let (client_ip, peer_ip) = request
+        .extensions()
+        .get::<ClientIp>()
+        .map(|client| (client.ip.to_string(), client.peer.to_string()))
+        .unwrap_or_default();
     let method = request.method().clone();
     let uri = request.uri().clone();
     let start = Instant::now();
@@ -30,7 +25,8 @@ pub async fn log_request(
         uri = %uri,
         status = %status.as_u16(),
         duration_ms = %duration.as_millis(),
-        client_ip = %addr.ip(),
+        client_ip = %client_ip,
+        peer_ip = %peer_ip,
         "request completed"
     );
 
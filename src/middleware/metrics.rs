@@ -1,13 +1,7 @@
 //! Metrics collection middleware
 
 use crate::utils::metrics::Metrics;
-use axum::{
-    body::Body,
-    extract::State,
-    http::Request,
-    middleware::Next,
-    response::Response,
-};
+use axum::{body::Body, extract::State, http::Request, middleware::Next, response::Response};
 use std::sync::Arc;
 
 /// Middleware to track request metrics
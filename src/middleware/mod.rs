@@ -1,7 +1,10 @@
 //! Middleware for request processing
 
+pub mod admin_auth;
+pub mod client_ip;
 pub mod logging;
+pub mod metrics;
 pub mod rate_limit;
+pub mod rate_limit_redis;
 pub mod security_headers;
 pub mod timeout;
-pub mod metrics;
@@ -1,109 +1,1319 @@
-//! Simple rate limiting middleware
+//! Rate limiting middleware
 
+use crate::models::ResponseFormat;
+use crate::utils::{
+    bans::{BanList, Offense},
+    client_ip::ClientIp,
+    listener::ConnectionInfo,
+    network::{self, Cidr},
+    snapshot::Clock,
+};
+use async_trait::async_trait;
 use axum::{
+    Json,
     body::Body,
-    http::{Request, StatusCode},
+    extract::{ConnectInfo, Query},
+    http::{HeaderMap, HeaderValue, Request, StatusCode, header},
     middleware::Next,
     response::{IntoResponse, Response},
 };
-use std::collections::HashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::{BuildHasher, RandomState};
 use std::net::IpAddr;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::sync::{Mutex, MutexGuard, PoisonError};
 use std::time::{Duration, Instant};
-use tokio::sync::Mutex;
 
-/// Rate limit state
+/// Rate limiting algorithm
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RateLimitAlgorithm {
+    /// Count requests in fixed windows (allows up to twice the limit across
+    /// a window boundary)
+    FixedWindow,
+
+    /// Refill tokens continuously at the sustained rate, up to `burst` tokens
+    TokenBucket,
+
+    /// Generic cell rate algorithm: space requests at the sustained rate,
+    /// tolerating up to `burst` requests at once
+    Gcra,
+}
+
+impl RateLimitAlgorithm {
+    /// Algorithm name as used in configuration
+    pub fn name(&self) -> &'static str {
+        match self {
+            RateLimitAlgorithm::FixedWindow => "fixed_window",
+            RateLimitAlgorithm::TokenBucket => "token_bucket",
+            RateLimitAlgorithm::Gcra => "gcra",
+        }
+    }
+}
+
+impl FromStr for RateLimitAlgorithm {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().replace('-', "_").as_str() {
+            "fixed_window" | "fixed" => Ok(RateLimitAlgorithm::FixedWindow),
+            "token_bucket" => Ok(RateLimitAlgorithm::TokenBucket),
+            "gcra" => Ok(RateLimitAlgorithm::Gcra),
+            other => Err(format!("unknown rate limit algorithm: {}", other)),
+        }
+    }
+}
+
+/// What to do when the rate limit store cannot be reached
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FailMode {
+    /// Allow every request until the store is back
+    Open,
+
+    /// Reject every request until the store is back
+    Closed,
+}
+
+impl FromStr for FailMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "open" => Ok(FailMode::Open),
+            "closed" | "close" => Ok(FailMode::Closed),
+            other => Err(format!("unknown rate limit fail mode: {}", other)),
+        }
+    }
+}
+
+/// Limits enforced by a policy
+#[derive(Clone, Copy, Debug)]
+pub struct Quota {
+    /// Requests per window at the sustained rate
+    pub max_requests: usize,
+
+    /// Time window duration
+    pub window: Duration,
+
+    /// Rate limiting algorithm
+    pub algorithm: RateLimitAlgorithm,
+
+    /// Requests allowed at once by token_bucket and gcra
+    pub burst: usize,
+}
+
+impl Quota {
+    /// Time between requests at the sustained rate
+    pub fn emission_interval(&self) -> Duration {
+        self.window / u32::try_from(self.max_requests.max(1)).unwrap_or(u32::MAX)
+    }
+
+    /// How far ahead of the sustained rate gcra lets a client run, at most
+    /// [`MAX_WINDOW`]
+    fn tolerance(&self) -> Duration {
+        self.emission_interval()
+            .saturating_mul(u32::try_from(self.burst).unwrap_or(u32::MAX))
+            .min(MAX_WINDOW)
+    }
+
+    /// Requests a client may send at once
+    pub fn limit(&self) -> usize {
+        match self.algorithm {
+            RateLimitAlgorithm::FixedWindow => self.max_requests,
+            _ => self.burst,
+        }
+    }
+
+    /// State of a client that has not sent any requests
+    fn new_entry(&self, now: Instant) -> RateLimitEntry {
+        match self.algorithm {
+            RateLimitAlgorithm::FixedWindow => RateLimitEntry::FixedWindow {
+                count: 0,
+                window_start: now,
+            },
+            RateLimitAlgorithm::TokenBucket => RateLimitEntry::TokenBucket {
+                tokens: self.burst as f64,
+                updated: now,
+            },
+            RateLimitAlgorithm::Gcra => RateLimitEntry::Gcra { tat: now },
+        }
+    }
+
+    /// Bring an entry up to date and, if `consume` is set, count a request
+    ///
+    /// Without `consume` the status describes the next request.
+    fn apply(&self, entry: &mut RateLimitEntry, now: Instant, consume: bool) -> RateLimitStatus {
+        match entry {
+            RateLimitEntry::FixedWindow {
+                count,
+                window_start,
+            } => {
+                // Reset window if expired
+                if now.duration_since(*window_start) > self.window {
+                    *count = 0;
+                    *window_start = now;
+                }
+
+                let allowed = *count < self.max_requests;
+                if consume {
+                    *count += 1;
+                }
+
+                let reset = self
+                    .window
+                    .saturating_sub(now.duration_since(*window_start));
+                RateLimitStatus {
+                    allowed,
+                    limit: self.max_requests,
+                    remaining: self.max_requests.saturating_sub(*count),
+                    reset,
+                    retry_after: (!allowed).then_some(reset),
+                }
+            }
+            RateLimitEntry::TokenBucket { tokens, updated } => {
+                let capacity = self.burst as f64;
+                let interval = self.emission_interval().as_secs_f64();
+
+                // Refill at the sustained rate since the last request
+                let refill = now.duration_since(*updated).as_secs_f64() / interval;
+                *tokens = (*tokens + refill).min(capacity);
+                *updated = now;
+
+                let allowed = *tokens >= 1.0;
+                if allowed && consume {
+                    *tokens -= 1.0;
+                }
+
+                RateLimitStatus {
+                    allowed,
+                    limit: self.burst,
+                    remaining: *tokens as usize,
+                    reset: secs_f64((capacity - *tokens) * interval),
+                    retry_after: (!allowed).then(|| secs_f64((1.0 - *tokens) * interval)),
+                }
+            }
+            RateLimitEntry::Gcra { tat } => {
+                let interval = self.emission_interval();
+                let tolerance = self.tolerance();
+
+                // Theoretical arrival time if this request is accepted
+                let new_tat = (*tat).max(now) + interval;
+
+                let allowed = new_tat.duration_since(now) <= tolerance;
+                if allowed && consume {
+                    *tat = new_tat;
+                }
+
+                // Requests still fit in the tolerance left after the backlog
+                let backlog = tat.saturating_duration_since(now);
+                let remaining =
+                    tolerance.saturating_sub(backlog).as_nanos() / interval.as_nanos().max(1);
+                RateLimitStatus {
+                    allowed,
+                    limit: self.burst,
+                    remaining: remaining as usize,
+                    reset: backlog,
+                    retry_after: (!allowed)
+                        .then(|| new_tat.duration_since(now).saturating_sub(tolerance)),
+                }
+            }
+        }
+    }
+
+    /// Count requests a peer accepted for the same client
+    ///
+    /// The entry is charged as if the requests had been made here, without
+    /// running further ahead than a fully drained quota.
+    fn absorb(&self, entry: &mut RateLimitEntry, now: Instant, requests: u32) {
+        match entry {
+            RateLimitEntry::FixedWindow {
+                count,
+                window_start,
+            } => {
+                if now.duration_since(*window_start) > self.window {
+                    *count = 0;
+                    *window_start = now;
+                }
+                *count = count.saturating_add(requests as usize);
+            }
+            RateLimitEntry::TokenBucket { tokens, updated } => {
+                let interval = self.emission_interval().as_secs_f64();
+                let refill = now.duration_since(*updated).as_secs_f64() / interval;
+                *tokens = ((*tokens + refill).min(self.burst as f64) - requests as f64).max(0.0);
+                *updated = now;
+            }
+            RateLimitEntry::Gcra { tat } => {
+                let backlog = tat
+                    .saturating_duration_since(now)
+                    .saturating_add(self.emission_interval().saturating_mul(requests));
+                *tat = now + backlog.min(self.tolerance());
+            }
+        }
+    }
+
+    /// Whether an entry holds state for this quota's algorithm
+    fn matches(&self, entry: &RateLimitEntry) -> bool {
+        matches!(
+            (self.algorithm, entry),
+            (
+                RateLimitAlgorithm::FixedWindow,
+                RateLimitEntry::FixedWindow { .. }
+            ) | (
+                RateLimitAlgorithm::TokenBucket,
+                RateLimitEntry::TokenBucket { .. }
+            ) | (RateLimitAlgorithm::Gcra, RateLimitEntry::Gcra { .. })
+        )
+    }
+
+    /// Whether an entry carries no more state than a fresh one
+    fn is_expired(&self, entry: &RateLimitEntry, now: Instant) -> bool {
+        match entry {
+            RateLimitEntry::FixedWindow { window_start, .. } => {
+                now.duration_since(*window_start) > self.window
+            }
+            RateLimitEntry::TokenBucket { tokens, updated } => {
+                let missing = self.burst as f64 - tokens;
+                now.duration_since(*updated).as_secs_f64()
+                    >= missing * self.emission_interval().as_secs_f64()
+            }
+            RateLimitEntry::Gcra { tat } => *tat <= now,
+        }
+    }
+}
+
+/// Storage for per-client rate limit state
+///
+/// Implementations must update a client's state atomically, so that
+/// concurrent requests (from any number of replicas sharing the store) are
+/// each counted exactly once.
+#[async_trait]
+pub trait RateLimitStore: Send + Sync {
+    /// Bring a client's state up to date and, if `consume` is set, count a
+    /// request
+    async fn check(
+        &self,
+        quota: &Quota,
+        client: IpAddr,
+        consume: bool,
+    ) -> Result<RateLimitStatus, String>;
+
+    /// Drop state that no longer limits anyone
+    async fn cleanup(&self, quota: &Quota);
+
+    /// Entries still limiting someone, to carry over a restart
+    ///
+    /// Stores shared with other processes keep their state themselves and
+    /// return nothing.
+    async fn snapshot(&self, _quota: &Quota) -> Vec<(IpAddr, RateLimitEntry)> {
+        Vec::new()
+    }
+
+    /// Restore entries taken by `snapshot`, returning how many were kept
+    async fn restore(&self, _quota: &Quota, _entries: Vec<(IpAddr, RateLimitEntry)>) -> usize {
+        0
+    }
+
+    /// Requests accepted here since the last call, by client, for gossip
+    /// peers to count as well
+    ///
+    /// Stores shared with other processes return nothing.
+    async fn take_unshared(&self, _quota: &Quota) -> Vec<(IpAddr, u32)> {
+        Vec::new()
+    }
+
+    /// Count requests a gossip peer accepted for a client
+    async fn absorb(&self, _quota: &Quota, _client: IpAddr, _requests: u32) {}
+}
+
+/// A client's state in a `MemoryStore`
+struct Slot {
+    entry: RateLimitEntry,
+
+    /// Requests accepted since the last `take_unshared`
+    unshared: u32,
+}
+
+/// In-memory rate limit state, local to this process
+///
+/// Clients are spread over independently locked shards, so concurrent
+/// requests rarely wait for each other and cleanup only ever blocks one shard.
+pub struct MemoryStore {
+    shards: Box<[Mutex<HashMap<IpAddr, Slot>>]>,
+    hasher: RandomState,
+}
+
+impl MemoryStore {
+    /// Create an empty store
+    pub fn new() -> Self {
+        Self {
+            shards: (0..shard_count())
+                .map(|_| Mutex::new(HashMap::new()))
+                .collect(),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Lock the shard holding a client's state
+    fn shard(&self, ip: &IpAddr) -> MutexGuard<'_, HashMap<IpAddr, Slot>> {
+        let index = self.hasher.hash_one(ip) as usize % self.shards.len();
+        // Entries stay consistent even if a holder panicked
+        self.shards[index]
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for MemoryStore {
+    async fn check(
+        &self,
+        quota: &Quota,
+        client: IpAddr,
+        consume: bool,
+    ) -> Result<RateLimitStatus, String> {
+        let mut shard = self.shard(&client);
+        let now = Instant::now();
+
+        if consume {
+            let slot = shard.entry(client).or_insert_with(|| Slot {
+                entry: quota.new_entry(now),
+                unshared: 0,
+            });
+            let status = quota.apply(&mut slot.entry, now, true);
+            if status.allowed {
+                slot.unshared = slot.unshared.saturating_add(1);
+            }
+            return Ok(status);
+        }
+
+        let mut entry = shard
+            .get(&client)
+            .map(|slot| slot.entry.clone())
+            .unwrap_or_else(|| quota.new_entry(now));
+        Ok(quota.apply(&mut entry, now, false))
+    }
+
+    /// Shards are swept one at a time, yielding in between, so requests are
+    /// never blocked for longer than one shard's scan.
+    async fn cleanup(&self, quota: &Quota) {
+        for shard in self.shards.iter() {
+            {
+                let mut shard = shard.lock().unwrap_or_else(PoisonError::into_inner);
+                let now = Instant::now();
+                shard.retain(|_, slot| !quota.is_expired(&slot.entry, now));
+            }
+            tokio::task::yield_now().await;
+        }
+    }
+
+    async fn snapshot(&self, quota: &Quota) -> Vec<(IpAddr, RateLimitEntry)> {
+        let mut entries = Vec::new();
+        for shard in self.shards.iter() {
+            {
+                let shard = shard.lock().unwrap_or_else(PoisonError::into_inner);
+                let now = Instant::now();
+                entries.extend(
+                    shard
+                        .iter()
+                        .filter(|(_, slot)| !quota.is_expired(&slot.entry, now))
+                        .map(|(ip, slot)| (*ip, slot.entry.clone())),
+                );
+            }
+            tokio::task::yield_now().await;
+        }
+        entries
+    }
+
+    /// Entries for another algorithm, or that expired while the server was
+    /// down, are dropped.
+    async fn restore(&self, quota: &Quota, entries: Vec<(IpAddr, RateLimitEntry)>) -> usize {
+        let now = Instant::now();
+        let mut restored = 0;
+        for (ip, entry) in entries {
+            if quota.matches(&entry) && !quota.is_expired(&entry, now) {
+                self.shard(&ip).insert(ip, Slot { entry, unshared: 0 });
+                restored += 1;
+            }
+        }
+        restored
+    }
+
+    async fn take_unshared(&self, _quota: &Quota) -> Vec<(IpAddr, u32)> {
+        let mut unshared = Vec::new();
+        for shard in self.shards.iter() {
+            {
+                let mut shard = shard.lock().unwrap_or_else(PoisonError::into_inner);
+                for (ip, slot) in shard.iter_mut().filter(|(_, slot)| slot.unshared > 0) {
+                    unshared.push((*ip, std::mem::take(&mut slot.unshared)));
+                }
+            }
+            tokio::task::yield_now().await;
+        }
+        unshared
+    }
+
+    async fn absorb(&self, quota: &Quota, client: IpAddr, requests: u32) {
+        let mut shard = self.shard(&client);
+        let now = Instant::now();
+        let slot = shard.entry(client).or_insert_with(|| Slot {
+            entry: quota.new_entry(now),
+            unshared: 0,
+        });
+        quota.absorb(&mut slot.entry, now, requests);
+    }
+}
+
+/// Number of shards: a few per core, so contention stays flat as cores grow
+fn shard_count() -> usize {
+    std::thread::available_parallelism()
+        .map(|cores| cores.get() * 4)
+        .unwrap_or(16)
+        .next_power_of_two()
+}
+
+/// Rate limiter enforcing one policy
 #[derive(Clone)]
 pub struct RateLimiter {
-    state: Arc<Mutex<HashMap<IpAddr, RateLimitEntry>>>,
-    max_requests: usize,
-    window: Duration,
+    name: String,
+    quota: Quota,
+    store: Arc<dyn RateLimitStore>,
+    fail_mode: FailMode,
+    ipv4_prefix: u8,
+    ipv6_prefix: u8,
+    exempt: Vec<Cidr>,
+}
+
+/// Outcome of a rate limit check for one request
+#[derive(Clone, Debug)]
+pub struct RateLimitStatus {
+    /// Whether the request was allowed
+    pub allowed: bool,
+
+    /// Requests the client may send at once
+    pub limit: usize,
+
+    /// Requests left before the client is limited
+    pub remaining: usize,
+
+    /// Time until the quota is fully restored
+    pub reset: Duration,
+
+    /// Time until the next request is allowed, when limited
+    pub retry_after: Option<Duration>,
+}
+
+impl RateLimitStatus {
+    /// Seconds until the quota is fully restored, rounded up
+    pub fn reset_secs(&self) -> u64 {
+        ceil_secs(self.reset)
+    }
+
+    /// Seconds until the next request is allowed, rounded up
+    pub fn retry_after_secs(&self) -> Option<u64> {
+        self.retry_after.map(ceil_secs)
+    }
 }
 
-struct RateLimitEntry {
-    count: usize,
-    window_start: Instant,
+/// Query parameters used to negotiate the 429 body
+#[derive(Deserialize)]
+struct FormatQuery {
+    format: Option<String>,
+}
+
+const RATE_LIMITED_MESSAGE: &str = "Rate limit exceeded. Please try again later.";
+
+const BANNED_MESSAGE: &str = "Client banned.";
+
+/// Retry-After sent while failing closed
+const STORE_RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Path of the admin API, which bans never lock out
+const ADMIN_PATH: &str = "/admin";
+
+/// Longest rate limit window, so that every deadline fits in an `Instant`
+pub const MAX_WINDOW: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+/// Per-client state, depending on the algorithm
+#[derive(Clone)]
+pub enum RateLimitEntry {
+    FixedWindow { count: usize, window_start: Instant },
+    TokenBucket { tokens: f64, updated: Instant },
+    Gcra { tat: Instant },
 }
 
 impl RateLimiter {
-    /// Create a new rate limiter
+    /// Create a new fixed-window rate limiter with in-memory state
     ///
     /// # Arguments
     /// * `max_requests` - Maximum requests per window
-    /// * `window` - Time window duration
+    /// * `window` - Time window duration, capped at [`MAX_WINDOW`]
     pub fn new(max_requests: usize, window: Duration) -> Self {
         Self {
-            state: Arc::new(Mutex::new(HashMap::new())),
-            max_requests,
-            window,
+            name: "default".to_string(),
+            quota: Quota {
+                max_requests,
+                window: window.min(MAX_WINDOW),
+                algorithm: RateLimitAlgorithm::FixedWindow,
+                burst: max_requests,
+            },
+            store: Arc::new(MemoryStore::new()),
+            fail_mode: FailMode::Open,
+            ipv4_prefix: 32,
+            ipv6_prefix: 128,
+            exempt: Vec::new(),
         }
     }
 
-    /// Check if request is allowed
-    async fn check_rate_limit(&self, ip: IpAddr) -> bool {
-        let mut state = self.state.lock().await;
-        let now = Instant::now();
+    /// Name the policy this limiter enforces
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
 
-        let entry = state.entry(ip).or_insert(RateLimitEntry {
-            count: 0,
-            window_start: now,
-        });
+    /// Never limit clients from these networks
+    pub fn with_exemptions(mut self, exempt: Vec<Cidr>) -> Self {
+        self.exempt = exempt;
+        self
+    }
+
+    /// Keep client state in a different store, e.g. one shared by replicas
+    pub fn with_store(mut self, store: Arc<dyn RateLimitStore>, fail_mode: FailMode) -> Self {
+        self.store = store;
+        self.fail_mode = fail_mode;
+        self
+    }
+
+    /// Use a different algorithm
+    ///
+    /// The sustained rate stays `max_requests` per `window`; `burst` is the
+    /// number of requests a client may send at once. It is ignored by the
+    /// fixed-window algorithm.
+    pub fn with_algorithm(mut self, algorithm: RateLimitAlgorithm, burst: usize) -> Self {
+        self.quota.algorithm = algorithm;
+        self.quota.burst = burst.max(1);
+        self
+    }
+
+    /// Count clients per network prefix instead of per address
+    ///
+    /// A single IPv6 host usually controls a whole /64 (or more), so limiting
+    /// individual addresses is trivially bypassed.
+    pub fn with_prefixes(mut self, ipv4_prefix: u8, ipv6_prefix: u8) -> Self {
+        self.ipv4_prefix = ipv4_prefix;
+        self.ipv6_prefix = ipv6_prefix;
+        self
+    }
 
-        // Reset window if expired
-        if now.duration_since(entry.window_start) > self.window {
-            entry.count = 0;
-            entry.window_start = now;
+    /// Policy in `RateLimit-Policy` syntax, e.g. `60;w=60;burst=10`
+    pub fn policy(&self) -> String {
+        let quota = &self.quota;
+        match quota.algorithm {
+            RateLimitAlgorithm::FixedWindow => {
+                format!("{};w={}", quota.max_requests, quota.window.as_secs())
+            }
+            _ => format!(
+                "{};w={};burst={}",
+                quota.max_requests,
+                quota.window.as_secs(),
+                quota.burst
+            ),
         }
+    }
+
+    /// Name of the configured algorithm
+    pub fn algorithm_name(&self) -> &'static str {
+        self.quota.algorithm.name()
+    }
 
-        entry.count += 1;
+    /// Name of the policy this limiter enforces
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 
-        entry.count <= self.max_requests
+    /// Check whether a client is exempt from this policy
+    pub fn is_exempt(&self, ip: &IpAddr) -> bool {
+        self.exempt.iter().any(|net| net.contains(ip))
+    }
+
+    /// Check if request is allowed, counting it against the client's quota
+    pub async fn check_rate_limit(&self, ip: IpAddr) -> RateLimitStatus {
+        self.check(ip, true).await
+    }
+
+    /// Report a client's quota without counting a request
+    pub async fn peek(&self, ip: IpAddr) -> RateLimitStatus {
+        self.check(ip, false).await
+    }
+
+    async fn check(&self, ip: IpAddr, consume: bool) -> RateLimitStatus {
+        let ip = network::truncate_to_prefix(ip, self.ipv4_prefix, self.ipv6_prefix);
+
+        match self.store.check(&self.quota, ip, consume).await {
+            Ok(status) => status,
+            Err(e) => {
+                tracing::warn!(
+                    policy = %self.name,
+                    error = %e,
+                    fail_mode = ?self.fail_mode,
+                    "Rate limit store unavailable"
+                );
+                self.unavailable_status()
+            }
+        }
+    }
+
+    /// Status reported while the store cannot be reached
+    fn unavailable_status(&self) -> RateLimitStatus {
+        let limit = self.quota.limit();
+        match self.fail_mode {
+            FailMode::Open => RateLimitStatus {
+                allowed: true,
+                limit,
+                remaining: limit,
+                reset: Duration::ZERO,
+                retry_after: None,
+            },
+            FailMode::Closed => RateLimitStatus {
+                allowed: false,
+                limit,
+                remaining: 0,
+                reset: STORE_RETRY_AFTER,
+                retry_after: Some(STORE_RETRY_AFTER),
+            },
+        }
     }
 
     /// Cleanup old entries periodically
     pub async fn cleanup(&self) {
-        let mut state = self.state.lock().await;
-        let now = Instant::now();
+        self.store.cleanup(&self.quota).await;
+    }
+
+    /// Requests accepted since the last call, by client, for gossip peers
+    pub async fn take_unshared(&self) -> Vec<(IpAddr, u32)> {
+        self.store.take_unshared(&self.quota).await
+    }
+
+    /// Count requests a gossip peer accepted for a client
+    pub async fn absorb(&self, ip: IpAddr, requests: u32) {
+        let ip = network::truncate_to_prefix(ip, self.ipv4_prefix, self.ipv6_prefix);
+        self.store.absorb(&self.quota, ip, requests).await;
+    }
+
+    /// Client state worth keeping across a restart
+    pub async fn snapshot(&self, clock: &Clock) -> Vec<(IpAddr, SavedEntry)> {
+        self.store
+            .snapshot(&self.quota)
+            .await
+            .into_iter()
+            .map(|(ip, entry)| (ip, SavedEntry::new(&entry, clock)))
+            .collect()
+    }
+
+    /// Restore client state saved by `snapshot`, returning how many clients
+    /// are still limited
+    ///
+    /// Clients are regrouped by the current prefix lengths, in case those
+    /// changed since the snapshot was taken.
+    pub async fn restore(&self, entries: Vec<(IpAddr, SavedEntry)>, clock: &Clock) -> usize {
+        let entries = entries
+            .into_iter()
+            .filter_map(|(ip, saved)| {
+                let ip = network::truncate_to_prefix(ip, self.ipv4_prefix, self.ipv6_prefix);
+                Some((ip, saved.restore(clock)?))
+            })
+            .collect();
+        self.store.restore(&self.quota, entries).await
+    }
+}
+
+/// Client state in a snapshot, with times as Unix milliseconds
+///
+/// Serialized as e.g. `{"f":[3,1729252800000]}` to keep snapshots small.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SavedEntry {
+    /// Request count and window start
+    #[serde(rename = "f")]
+    FixedWindow(usize, u64),
+
+    /// Tokens left and last refill
+    #[serde(rename = "t")]
+    TokenBucket(f64, u64),
+
+    /// Theoretical arrival time
+    #[serde(rename = "g")]
+    Gcra(u64),
+}
+
+impl SavedEntry {
+    fn new(entry: &RateLimitEntry, clock: &Clock) -> Self {
+        match *entry {
+            RateLimitEntry::FixedWindow {
+                count,
+                window_start,
+            } => SavedEntry::FixedWindow(count, clock.to_unix_millis(window_start)),
+            RateLimitEntry::TokenBucket { tokens, updated } => {
+                SavedEntry::TokenBucket(tokens, clock.to_unix_millis(updated))
+            }
+            RateLimitEntry::Gcra { tat } => SavedEntry::Gcra(clock.to_unix_millis(tat)),
+        }
+    }
+
+    /// The entry in this process's clock, if it can be represented
+    fn restore(self, clock: &Clock) -> Option<RateLimitEntry> {
+        Some(match self {
+            SavedEntry::FixedWindow(count, window_start) => RateLimitEntry::FixedWindow {
+                count,
+                window_start: clock.to_instant(window_start)?,
+            },
+            SavedEntry::TokenBucket(tokens, updated) => RateLimitEntry::TokenBucket {
+                tokens,
+                updated: clock.to_instant(updated)?,
+            },
+            SavedEntry::Gcra(tat) => RateLimitEntry::Gcra {
+                tat: clock.to_instant(tat)?,
+            },
+        })
+    }
+}
+
+/// Named rate limit policies, selected by request path
+///
+/// Routes without a policy of their own share the default one.
+pub struct RateLimitPolicies {
+    default: Arc<RateLimiter>,
+    routes: HashMap<String, Arc<RateLimiter>>,
+    policies: Vec<Arc<RateLimiter>>,
+}
+
+impl RateLimitPolicies {
+    /// Create a policy set where every route uses `default`
+    pub fn new(default: RateLimiter) -> Self {
+        let default = Arc::new(default);
+        Self {
+            default: default.clone(),
+            routes: HashMap::new(),
+            policies: vec![default],
+        }
+    }
 
-        state.retain(|_, entry| now.duration_since(entry.window_start) <= self.window);
+    /// Apply a policy to the given routes (exact paths)
+    pub fn with_policy(mut self, limiter: RateLimiter, routes: &[String]) -> Self {
+        let limiter = Arc::new(limiter);
+        for route in routes {
+            self.routes.insert(route.clone(), limiter.clone());
+        }
+        self.policies.push(limiter);
+        self
+    }
+
+    /// Policy applied to a request path
+    pub fn for_path(&self, path: &str) -> &RateLimiter {
+        self.routes.get(path).unwrap_or(&self.default)
+    }
+
+    /// Cleanup old entries of every policy
+    pub async fn cleanup(&self) {
+        for limiter in &self.policies {
+            limiter.cleanup().await;
+        }
+    }
+
+    /// Policy with the given name
+    pub fn by_name(&self, name: &str) -> Option<&RateLimiter> {
+        self.policies
+            .iter()
+            .find(|limiter| limiter.name == name)
+            .map(|limiter| limiter.as_ref())
+    }
+
+    /// Requests accepted since the last call, by policy name and client
+    pub async fn take_unshared(&self) -> Vec<(String, IpAddr, u32)> {
+        let mut unshared = Vec::new();
+        for limiter in &self.policies {
+            unshared.extend(
+                limiter
+                    .take_unshared()
+                    .await
+                    .into_iter()
+                    .map(|(ip, requests)| (limiter.name.clone(), ip, requests)),
+            );
+        }
+        unshared
+    }
+
+    /// Client state of every policy, by policy name
+    pub async fn snapshot(&self, clock: &Clock) -> BTreeMap<String, Vec<(IpAddr, SavedEntry)>> {
+        let mut limits = BTreeMap::new();
+        for limiter in &self.policies {
+            let entries = limiter.snapshot(clock).await;
+            if !entries.is_empty() {
+                limits.insert(limiter.name.clone(), entries);
+            }
+        }
+        limits
+    }
+
+    /// Restore client state saved by `snapshot`, returning how many clients
+    /// are still limited
+    ///
+    /// State of policies that no longer exist is dropped.
+    pub async fn restore(
+        &self,
+        mut limits: BTreeMap<String, Vec<(IpAddr, SavedEntry)>>,
+        clock: &Clock,
+    ) -> usize {
+        let mut restored = 0;
+        for limiter in &self.policies {
+            if let Some(entries) = limits.remove(&limiter.name) {
+                restored += limiter.restore(entries, clock).await;
+            }
+        }
+        restored
     }
 }
 
 /// Middleware function for rate limiting
+///
+/// Banned clients get a 403 before being counted, and clients that hit the
+/// limit collect a strike towards a ban. Every limited response carries the
+/// client's quota for the route's policy in `RateLimit-*` headers.
 pub async fn rate_limit_middleware(
-    limiter: Arc<RateLimiter>,
+    policies: Arc<RateLimitPolicies>,
+    bans: Arc<BanList>,
     request: Request<Body>,
     next: Next,
 ) -> Response {
-    // Extract IP from connection or X-Forwarded-For
-    let ip = extract_ip(&request);
+    // Client IP as resolved by the client IP middleware, falling back to the
+    // connection itself so that every request is counted
+    let ip = request
+        .extensions()
+        .get::<ClientIp>()
+        .map(|client| client.ip)
+        .or_else(|| {
+            request
+                .extensions()
+                .get::<ConnectInfo<ConnectionInfo>>()
+                .map(|ConnectInfo(connection)| connection.source().ip())
+        });
+
+    let limiter = policies.for_path(request.uri().path());
+    let policy = limiter.policy();
 
-    if let Some(ip_addr) = ip {
-        if !limiter.check_rate_limit(ip_addr).await {
-            return (
-                StatusCode::TOO_MANY_REQUESTS,
-                "Rate limit exceeded. Please try again later.",
-            )
-                .into_response();
+    // Banned clients are turned away before being counted. The admin API,
+    // still behind its token, stays reachable so that an operator who banned
+    // their own network can lift the ban.
+    if let Some(ip_addr) = ip
+        && !is_admin_path(request.uri().path())
+        && let Some(ban) = bans.check(ip_addr).await
+    {
+        let retry_after = ban.remaining_secs();
+        let mut response =
+            error_response(&request, StatusCode::FORBIDDEN, BANNED_MESSAGE, retry_after);
+        let headers = response.headers_mut();
+        if limiter.is_exempt(&ip_addr) {
+            add_policy_header(headers, &policy);
+        } else {
+            add_rate_limit_headers(headers, &limiter.peek(ip_addr).await, &policy);
         }
+
+        // The ban decides when to come back, not the rate limit
+        headers.remove(header::RETRY_AFTER);
+        if let Some(retry_after) = retry_after {
+            headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+        }
+        return response;
     }
 
-    next.run(request).await
+    // Exempt clients are not counted, so only the policy is reported
+    let Some(ip_addr) = ip.filter(|ip| !limiter.is_exempt(ip)) else {
+        let mut response = next.run(request).await;
+        add_policy_header(response.headers_mut(), &policy);
+        return response;
+    };
+
+    let status = limiter.check_rate_limit(ip_addr).await;
+
+    let mut response = if status.allowed {
+        next.run(request).await
+    } else {
+        bans.record_offense(ip_addr, Offense::RateLimited).await;
+        error_response(
+            &request,
+            StatusCode::TOO_MANY_REQUESTS,
+            RATE_LIMITED_MESSAGE,
+            Some(status.retry_after_secs().unwrap_or(0)),
+        )
+    };
+
+    add_rate_limit_headers(response.headers_mut(), &status, &policy);
+    response
 }
 
-/// Extract IP address from request
-fn extract_ip(request: &Request<Body>) -> Option<IpAddr> {
-    // Try X-Forwarded-For first
-    if let Some(forwarded) = request.headers().get("x-forwarded-for") {
-        if let Ok(forwarded_str) = forwarded.to_str() {
-            if let Some(first_ip) = forwarded_str.split(',').next() {
-                if let Ok(ip) = first_ip.trim().parse() {
-                    return Some(ip);
-                }
+/// Build an error response in the format the client asked for
+fn error_response(
+    request: &Request<Body>,
+    status: StatusCode,
+    message: &str,
+    retry_after: Option<u64>,
+) -> Response {
+    let format = Query::<FormatQuery>::try_from_uri(request.uri())
+        .map(|Query(query)| query.format)
+        .unwrap_or_default();
+
+    match ResponseFormat::negotiate(format.as_deref(), request.headers()) {
+        ResponseFormat::Json => {
+            let mut body = json!({ "error": message });
+            if let Some(retry_after) = retry_after {
+                body["retry_after"] = retry_after.into();
+            }
+            (status, Json(body)).into_response()
+        }
+        ResponseFormat::PlainText => (
+            status,
+            [(header::CONTENT_TYPE, "text/plain; charset=utf-8")],
+            message.to_string(),
+        )
+            .into_response(),
+    }
+}
+
+/// Add the IETF `RateLimit-*` headers, and `Retry-After` when limited
+fn add_rate_limit_headers(headers: &mut HeaderMap, status: &RateLimitStatus, policy: &str) {
+    headers.insert("ratelimit-limit", HeaderValue::from(status.limit));
+    headers.insert("ratelimit-remaining", HeaderValue::from(status.remaining));
+    headers.insert("ratelimit-reset", HeaderValue::from(status.reset_secs()));
+    add_policy_header(headers, policy);
+    if let Some(retry_after) = status.retry_after_secs() {
+        headers.insert(header::RETRY_AFTER, HeaderValue::from(retry_after));
+    }
+}
+
+/// Add the `RateLimit-Policy` header describing the quota
+fn add_policy_header(headers: &mut HeaderMap, policy: &str) {
+    if let Ok(value) = HeaderValue::from_str(policy) {
+        headers.insert("ratelimit-policy", value);
+    }
+}
+
+/// Whether a path belongs to the admin API
+fn is_admin_path(path: &str) -> bool {
+    path.strip_prefix(ADMIN_PATH)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+}
+
+/// Round up to whole seconds
+fn ceil_secs(duration: Duration) -> u64 {
+    duration
+        .as_secs()
+        .saturating_add(u64::from(duration.subsec_nanos() > 0))
+}
+
+/// Duration from seconds, saturating instead of panicking when too large
+fn secs_f64(secs: f64) -> Duration {
+    Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip() -> IpAddr {
+        "192.0.2.1".parse().unwrap()
+    }
+
+    async fn allowed(limiter: &RateLimiter, n: usize) -> usize {
+        let mut allowed = 0;
+        for _ in 0..n {
+            if limiter.check_rate_limit(ip()).await.allowed {
+                allowed += 1;
             }
         }
+        allowed
+    }
+
+    #[tokio::test]
+    async fn test_fixed_window() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(60));
+        assert_eq!(allowed(&limiter, 5).await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_token_bucket_burst() {
+        let limiter = RateLimiter::new(60, Duration::from_secs(60))
+            .with_algorithm(RateLimitAlgorithm::TokenBucket, 5);
+        assert_eq!(allowed(&limiter, 10).await, 5);
+    }
+
+    #[tokio::test]
+    async fn test_gcra_burst_and_refill() {
+        let limiter = RateLimiter::new(1000, Duration::from_secs(1))
+            .with_algorithm(RateLimitAlgorithm::Gcra, 4);
+        assert_eq!(allowed(&limiter, 10).await, 4);
+
+        // One emission interval later a single request fits again
+        tokio::time::sleep(Duration::from_millis(2)).await;
+        assert!(limiter.check_rate_limit(ip()).await.allowed);
+    }
+
+    #[tokio::test]
+    async fn test_huge_limits_do_not_overflow() {
+        // Both would truncate to tiny values as u32
+        let limiter = RateLimiter::new(1 << 32, Duration::from_secs(60))
+            .with_algorithm(RateLimitAlgorithm::Gcra, (1 << 32) + 1);
+        assert_eq!(allowed(&limiter, 10).await, 10);
+
+        let quota = &limiter.quota;
+        assert_eq!(
+            quota.emission_interval(),
+            Duration::from_secs(60) / u32::MAX
+        );
+        assert_eq!(quota.tolerance(), quota.emission_interval() * u32::MAX);
+
+        // Gossiped requests never push a client past a drained quota
+        let now = Instant::now();
+        let mut entry = RateLimitEntry::Gcra { tat: now };
+        quota.absorb(&mut entry, now, u32::MAX);
+        let RateLimitEntry::Gcra { tat } = entry else {
+            unreachable!()
+        };
+        assert!(tat <= now + quota.tolerance());
+    }
+
+    #[tokio::test]
+    async fn test_huge_window_does_not_overflow() {
+        for algorithm in [
+            RateLimitAlgorithm::FixedWindow,
+            RateLimitAlgorithm::TokenBucket,
+            RateLimitAlgorithm::Gcra,
+        ] {
+            let limiter = RateLimiter::new(1, Duration::MAX).with_algorithm(algorithm, usize::MAX);
+            assert_eq!(limiter.quota.window, MAX_WINDOW);
+            assert!(limiter.check_rate_limit(ip()).await.allowed);
+
+            let now = Instant::now();
+            let mut entry = limiter.quota.new_entry(now);
+            limiter.quota.absorb(&mut entry, now, u32::MAX);
+            limiter
+                .quota
+                .apply(&mut entry, now, true)
+                .retry_after_secs();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_keeps_active_entries() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60))
+            .with_algorithm(RateLimitAlgorithm::Gcra, 1);
+        assert!(limiter.check_rate_limit(ip()).await.allowed);
+
+        limiter.cleanup().await;
+        assert!(!limiter.check_rate_limit(ip()).await.allowed);
+    }
+
+    #[test]
+    fn test_parse_algorithm() {
+        assert_eq!("GCRA".parse(), Ok(RateLimitAlgorithm::Gcra));
+        assert_eq!("token-bucket".parse(), Ok(RateLimitAlgorithm::TokenBucket));
+        assert_eq!("fixed_window".parse(), Ok(RateLimitAlgorithm::FixedWindow));
+        assert!("leaky".parse::<RateLimitAlgorithm>().is_err());
+    }
+
+    async fn check(limiter: &RateLimiter, ip: &str) -> bool {
+        limiter.check_rate_limit(ip.parse().unwrap()).await.allowed
+    }
+
+    #[tokio::test]
+    async fn test_ipv6_prefix_aggregation() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60)).with_prefixes(32, 64);
+
+        assert!(check(&limiter, "2001:db8::1").await);
+        assert!(check(&limiter, "2001:db8::ffff:2").await);
+        assert!(!check(&limiter, "2001:db8::3").await);
+
+        // A different /64 has its own budget
+        assert!(check(&limiter, "2001:db8:0:1::1").await);
+    }
+
+    #[tokio::test]
+    async fn test_ipv4_mapped_shares_ipv4_bucket() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+
+        assert!(check(&limiter, "192.0.2.1").await);
+        assert!(!check(&limiter, "::ffff:192.0.2.1").await);
+    }
+
+    #[tokio::test]
+    async fn test_fixed_window_status() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+
+        let status = limiter.check_rate_limit(ip()).await;
+        assert_eq!((status.limit, status.remaining), (2, 1));
+        assert_eq!(status.reset_secs(), 60);
+        assert!(status.retry_after.is_none());
+
+        limiter.check_rate_limit(ip()).await;
+        let status = limiter.check_rate_limit(ip()).await;
+        assert!(!status.allowed);
+        assert_eq!(status.remaining, 0);
+        assert_eq!(status.retry_after_secs(), Some(60));
+    }
+
+    #[tokio::test]
+    async fn test_gcra_status() {
+        let limiter = RateLimiter::new(60, Duration::from_secs(60))
+            .with_algorithm(RateLimitAlgorithm::Gcra, 3);
+
+        let status = limiter.check_rate_limit(ip()).await;
+        assert_eq!((status.limit, status.remaining), (3, 2));
+        assert_eq!(status.reset_secs(), 1);
+
+        limiter.check_rate_limit(ip()).await;
+        limiter.check_rate_limit(ip()).await;
+        let status = limiter.check_rate_limit(ip()).await;
+        assert!(!status.allowed);
+        assert_eq!(status.retry_after_secs(), Some(1));
+    }
+
+    #[test]
+    fn test_policy() {
+        let limiter = RateLimiter::new(60, Duration::from_secs(60));
+        assert_eq!(limiter.policy(), "60;w=60");
+
+        let limiter = limiter.with_algorithm(RateLimitAlgorithm::TokenBucket, 10);
+        assert_eq!(limiter.policy(), "60;w=60;burst=10");
+    }
+
+    #[tokio::test]
+    async fn test_peek_does_not_count() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        limiter.check_rate_limit(ip()).await;
+
+        for _ in 0..3 {
+            assert_eq!(limiter.peek(ip()).await.remaining, 1);
+        }
+        assert!(limiter.check_rate_limit(ip()).await.allowed);
+        assert!(!limiter.peek(ip()).await.allowed);
     }
 
-    // Fall back to connection info if available
-    None
+    #[test]
+    fn test_policy_selection_and_exemptions() {
+        let policies = RateLimitPolicies::new(RateLimiter::new(60, Duration::from_secs(60)))
+            .with_policy(
+                RateLimiter::new(10, Duration::from_secs(60))
+                    .with_name("lookup")
+                    .with_exemptions(vec!["10.0.0.0/8".parse().unwrap()]),
+                &["/lookup".to_string()],
+            );
+
+        assert_eq!(policies.for_path("/").name(), "default");
+        assert_eq!(policies.for_path("/lookup/").name(), "default");
+
+        let lookup = policies.for_path("/lookup");
+        assert_eq!(lookup.name(), "lookup");
+        assert!(lookup.is_exempt(&"10.1.2.3".parse().unwrap()));
+        assert!(!lookup.is_exempt(&ip()));
+    }
+
+    fn tracked(store: &MemoryStore) -> usize {
+        store
+            .shards
+            .iter()
+            .map(|shard| shard.lock().unwrap().len())
+            .sum()
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_removes_expired_entries() {
+        let store = Arc::new(MemoryStore::new());
+        let limiter = RateLimiter::new(1000, Duration::from_millis(10))
+            .with_store(store.clone(), FailMode::Open);
+        for i in 0..100u32 {
+            limiter
+                .check_rate_limit(IpAddr::from(i.to_be_bytes()))
+                .await;
+        }
+        assert_eq!(tracked(&store), 100);
+
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        limiter.cleanup().await;
+        assert_eq!(tracked(&store), 0);
+    }
+
+    /// Run `clients` concurrent tasks, each sending `requests` checks from
+    /// its own address, and return the store and the time taken
+    ///
+    /// Each client's limit is half its requests, and every task asserts that
+    /// exactly that many were allowed.
+    async fn hammer(clients: u32, requests: usize) -> (Arc<MemoryStore>, Duration) {
+        let store = Arc::new(MemoryStore::new());
+        let limiter = Arc::new(
+            RateLimiter::new(requests / 2, Duration::from_secs(60))
+                .with_store(store.clone(), FailMode::Open),
+        );
+        let started = Instant::now();
+
+        let tasks: Vec<_> = (0..clients)
+            .map(|client| {
+                let limiter = limiter.clone();
+                tokio::spawn(async move {
+                    let ip = IpAddr::from((0xc000_0200 + client).to_be_bytes());
+                    let mut allowed = 0;
+                    for _ in 0..requests {
+                        if limiter.check_rate_limit(ip).await.allowed {
+                            allowed += 1;
+                        }
+                    }
+                    allowed
+                })
+            })
+            .collect();
+
+        for task in tasks {
+            assert_eq!(task.await.unwrap(), requests / 2);
+        }
+        (store, started.elapsed())
+    }
+
+    /// Many concurrent clients, each hammering its own address, are counted
+    /// exactly and independently
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    async fn test_concurrent_clients() {
+        let (store, _) = hammer(32, 200).await;
+        assert_eq!(tracked(&store), 32);
+    }
+
+    /// Throughput of the sharded store with many concurrent clients. Run with
+    /// `cargo test --release -- --ignored --nocapture` to see the rate.
+    #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+    #[ignore = "benchmark"]
+    async fn test_concurrent_throughput() {
+        const CLIENTS: u32 = 32;
+        const REQUESTS: usize = 5_000;
+
+        let (store, elapsed) = hammer(CLIENTS, REQUESTS).await;
+        let total = CLIENTS as usize * REQUESTS;
+        println!(
+            "{} clients, {} checks in {:?} ({:.0} checks/s)",
+            CLIENTS,
+            total,
+            elapsed,
+            total as f64 / elapsed.as_secs_f64()
+        );
+        assert_eq!(tracked(&store), CLIENTS as usize);
+    }
+
+    #[test]
+    fn test_parse_fail_mode() {
+        assert_eq!("Closed".parse(), Ok(FailMode::Closed));
+        assert_eq!("open".parse(), Ok(FailMode::Open));
+        assert!("maybe".parse::<FailMode>().is_err());
+    }
 }
@@ -0,0 +1,258 @@
+//! Redis-backed rate limit store
+//!
+//! Lets replicas behind one balancer share client counters. Each algorithm
+//! runs as a Lua script, so a client's state is read and updated atomically
+//! on the Redis server, using the server's clock.
+
+use crate::middleware::rate_limit::{Quota, RateLimitAlgorithm, RateLimitStatus, RateLimitStore};
+use async_trait::async_trait;
+use redis::Script;
+use redis::aio::{ConnectionManager, ConnectionManagerConfig};
+use std::net::IpAddr;
+use std::time::Duration;
+
+/// Fixed window: a counter that expires with the window
+///
+/// ARGV: limit, window (ms), consume
+const FIXED_WINDOW_SCRIPT: &str = r"
+local limit = tonumber(ARGV[1])
+local window = tonumber(ARGV[2])
+local consume = ARGV[3] == '1'
+
+local count = tonumber(redis.call('GET', KEYS[1]) or '0')
+local ttl = redis.call('PTTL', KEYS[1])
+if ttl < 0 then ttl = window end
+
+local allowed = count < limit
+if consume then
+  count = redis.call('INCR', KEYS[1])
+  redis.call('PEXPIRE', KEYS[1], ttl)
+end
+
+local retry = 0
+if not allowed then retry = ttl end
+return {allowed and 1 or 0, math.max(limit - count, 0), ttl, retry}
+";
+
+/// Token bucket: token count and last refill time (us)
+///
+/// ARGV: burst, emission interval (us), consume
+const TOKEN_BUCKET_SCRIPT: &str = r"
+local burst = tonumber(ARGV[1])
+local interval = tonumber(ARGV[2])
+local consume = ARGV[3] == '1'
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000000 + tonumber(time[2])
+
+local state = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+local tokens = tonumber(state[1]) or burst
+local updated = tonumber(state[2]) or now
+tokens = math.min(burst, tokens + math.max(now - updated, 0) / interval)
+
+local allowed = tokens >= 1
+if allowed and consume then tokens = tokens - 1 end
+
+local reset = math.ceil((burst - tokens) * interval / 1000)
+if consume then
+  redis.call('HSET', KEYS[1], 'tokens', string.format('%.17g', tokens),
+    'updated', string.format('%.0f', now))
+  redis.call('PEXPIRE', KEYS[1], reset + 1)
+end
+
+local retry = 0
+if not allowed then retry = math.ceil((1 - tokens) * interval / 1000) end
+return {allowed and 1 or 0, math.floor(tokens), reset, retry}
+";
+
+/// GCRA: theoretical arrival time (us)
+///
+/// ARGV: burst, emission interval (us), consume
+const GCRA_SCRIPT: &str = r"
+local burst = tonumber(ARGV[1])
+local interval = tonumber(ARGV[2])
+local consume = ARGV[3] == '1'
+local time = redis.call('TIME')
+local now = tonumber(time[1]) * 1000000 + tonumber(time[2])
+local tolerance = interval * burst
+
+local tat = math.max(tonumber(redis.call('GET', KEYS[1]) or '0'), now)
+local new_tat = tat + interval
+
+local allowed = new_tat - now <= tolerance
+if allowed and consume then
+  tat = new_tat
+  redis.call('SET', KEYS[1], string.format('%.0f', tat),
+    'PX', math.ceil((tat - now) / 1000) + 1)
+end
+
+local retry = 0
+if not allowed then retry = math.ceil((new_tat - now - tolerance) / 1000) end
+local remaining = math.floor((tolerance - (tat - now)) / interval)
+return {allowed and 1 or 0, math.max(remaining, 0), math.ceil((tat - now) / 1000), retry}
+";
+
+/// Rate limit state stored in Redis
+pub struct RedisStore {
+    connection: ConnectionManager,
+    prefix: String,
+    fixed_window: Script,
+    token_bucket: Script,
+    gcra: Script,
+}
+
+impl RedisStore {
+    /// Create a connection to `url`, established on first use
+    ///
+    /// Commands fail after `timeout`, so an unreachable server only delays
+    /// requests briefly before the configured fail mode applies.
+    pub fn connect(url: &str, timeout: Duration) -> Result<ConnectionManager, String> {
+        let client = redis::Client::open(url).map_err(|e| format!("invalid Redis URL: {}", e))?;
+        let config = ConnectionManagerConfig::new()
+            .set_connection_timeout(Some(timeout))
+            .set_response_timeout(Some(timeout))
+            .set_number_of_retries(1);
+
+        ConnectionManager::new_lazy_with_config(client, config)
+            .map_err(|e| format!("failed to create Redis connection: {}", e))
+    }
+
+    /// Create a store keeping its keys under `prefix`
+    pub fn new(connection: ConnectionManager, prefix: impl Into<String>) -> Self {
+        Self {
+            connection,
+            prefix: prefix.into(),
+            fixed_window: Script::new(FIXED_WINDOW_SCRIPT),
+            token_bucket: Script::new(TOKEN_BUCKET_SCRIPT),
+            gcra: Script::new(GCRA_SCRIPT),
+        }
+    }
+}
+
+#[async_trait]
+impl RateLimitStore for RedisStore {
+    async fn check(
+        &self,
+        quota: &Quota,
+        client: IpAddr,
+        consume: bool,
+    ) -> Result<RateLimitStatus, String> {
+        // The algorithm is part of the key, as each one stores a different type
+        let key = format!("{}:{}:{}", self.prefix, quota.algorithm.name(), client);
+        let interval = quota.emission_interval().as_micros().max(1) as u64;
+
+        let mut invocation = match quota.algorithm {
+            RateLimitAlgorithm::FixedWindow => {
+                let mut invocation = self.fixed_window.prepare_invoke();
+                invocation
+                    .arg(quota.max_requests)
+                    .arg(quota.window.as_millis() as u64);
+                invocation
+            }
+            RateLimitAlgorithm::TokenBucket => {
+                let mut invocation = self.token_bucket.prepare_invoke();
+                invocation.arg(quota.burst).arg(interval);
+                invocation
+            }
+            RateLimitAlgorithm::Gcra => {
+                let mut invocation = self.gcra.prepare_invoke();
+                invocation.arg(quota.burst).arg(interval);
+                invocation
+            }
+        };
+        invocation.key(&key).arg(u8::from(consume));
+
+        let mut connection = self.connection.clone();
+        let (allowed, remaining, reset_ms, retry_ms): (u8, u64, u64, u64) = invocation
+            .invoke_async(&mut connection)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        let allowed = allowed == 1;
+        Ok(RateLimitStatus {
+            allowed,
+            limit: quota.limit(),
+            remaining: remaining as usize,
+            reset: Duration::from_millis(reset_ms),
+            retry_after: (!allowed).then(|| Duration::from_millis(retry_ms)),
+        })
+    }
+
+    /// Keys expire on their own in Redis
+    async fn cleanup(&self, _quota: &Quota) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::rate_limit::{FailMode, RateLimiter};
+    use std::sync::Arc;
+
+    /// Store for the server in `TEST_REDIS_URL` (e.g. `redis://127.0.0.1:6379`)
+    fn test_store(prefix: &str) -> Arc<RedisStore> {
+        let url = std::env::var("TEST_REDIS_URL").expect("TEST_REDIS_URL is not set");
+        let connection = RedisStore::connect(&url, Duration::from_secs(1)).unwrap();
+        let prefix = format!("ip-api-test:{}:{}", prefix, std::process::id());
+        Arc::new(RedisStore::new(connection, prefix))
+    }
+
+    fn limiter(store: Arc<RedisStore>, algorithm: RateLimitAlgorithm) -> RateLimiter {
+        RateLimiter::new(60, Duration::from_secs(60))
+            .with_algorithm(algorithm, 3)
+            .with_store(store, FailMode::Closed)
+    }
+
+    async fn allowed(limiter: &RateLimiter, n: usize) -> usize {
+        let ip = "192.0.2.1".parse().unwrap();
+        let mut allowed = 0;
+        for _ in 0..n {
+            if limiter.peek(ip).await.allowed && limiter.check_rate_limit(ip).await.allowed {
+                allowed += 1;
+            }
+        }
+        allowed
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_REDIS_URL"]
+    async fn test_redis_algorithms() {
+        for (algorithm, expected) in [
+            (RateLimitAlgorithm::FixedWindow, 60),
+            (RateLimitAlgorithm::TokenBucket, 3),
+            (RateLimitAlgorithm::Gcra, 3),
+        ] {
+            let store = test_store(algorithm.name());
+            assert_eq!(allowed(&limiter(store, algorithm), 100).await, expected);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_REDIS_URL"]
+    async fn test_redis_replicas_share_state() {
+        let store = test_store("shared");
+        let first = limiter(store.clone(), RateLimitAlgorithm::Gcra);
+        let second = limiter(store, RateLimitAlgorithm::Gcra);
+
+        assert_eq!(allowed(&first, 2).await, 2);
+        assert_eq!(allowed(&second, 2).await, 1);
+    }
+
+    #[tokio::test]
+    async fn test_unreachable_redis_fail_modes() {
+        // Nothing listens on port 1
+        let connection =
+            RedisStore::connect("redis://127.0.0.1:1", Duration::from_millis(200)).unwrap();
+        let store = Arc::new(RedisStore::new(connection, "ip-api-test"));
+        let ip = "192.0.2.1".parse().unwrap();
+
+        let open =
+            RateLimiter::new(1, Duration::from_secs(60)).with_store(store.clone(), FailMode::Open);
+        assert!(open.check_rate_limit(ip).await.allowed);
+        assert!(open.check_rate_limit(ip).await.allowed);
+
+        let closed =
+            RateLimiter::new(1, Duration::from_secs(60)).with_store(store, FailMode::Closed);
+        let status = closed.check_rate_limit(ip).await;
+        assert!(!status.allowed);
+        assert!(status.retry_after.is_some());
+    }
+}
@@ -1,21 +1,16 @@
 //! Security headers middleware
 
-use crate::utils::security::SecurityHeaders;
-use axum::{
-    body::Body,
-    http::Request,
-    middleware::Next,
-    response::IntoResponse,
-};
+use crate::utils::{client_ip::ClientIp, security::SecurityHeaders};
+use axum::{body::Body, http::Request, middleware::Next, response::IntoResponse};
 
 /// Middleware to add security headers to all responses
 pub async fn add_security_headers(request: Request<Body>, next: Next) -> impl IntoResponse {
-    // Check if request is over HTTPS
+    // Check if request is over HTTPS, as reported by a trusted proxy
     let is_https = request
-        .headers()
-        .get("x-forwarded-proto")
-        .and_then(|v| v.to_str().ok())
-        .map(|v| v == "https")
+        .extensions()
+        .get::<ClientIp>()
+        .and_then(|client| client.proto.as_deref())
+        .map(|proto| proto == "https")
         .unwrap_or(false);
 
     let mut response = next.run(request).await;
@@ -1,5 +1,8 @@
 //! Data models for API responses
 
+use crate::utils::dns::{DnssecStatus, LookupStatus, Verification};
+use axum::http::HeaderMap;
+use hickory_resolver::proto::rr::RecordType;
 use serde::Serialize;
 
 /// Response structure containing client IP information
@@ -9,10 +12,39 @@ pub struct IpResponse {
     #[serde(rename = "IP")]
     pub ip: String,
 
+    /// Where the IP was taken from (direct, proxy-protocol, forwarded,
+    /// x-forwarded-for, x-real-ip or a CDN preset name)
+    #[serde(rename = "IP-Source")]
+    pub ip_source: Option<String>,
+
     /// Reverse DNS hostname (null if lookup fails)
     #[serde(rename = "rDNS")]
     pub rdns: Option<String>,
 
+    /// Every PTR hostname of the address (omitted if the lookup fails)
+    #[serde(rename = "rDNS-Names", skip_serializing_if = "Option::is_none")]
+    pub rdns_names: Option<Vec<String>>,
+
+    /// Seconds until the cached reverse DNS answer expires (0 while it is
+    /// served stale)
+    #[serde(rename = "rDNS-TTL", skip_serializing_if = "Option::is_none")]
+    pub rdns_ttl: Option<u64>,
+
+    /// Outcome of the reverse lookup: ok, nxdomain, timeout, error or
+    /// circuit_open
+    #[serde(rename = "rDNS-Status", skip_serializing_if = "Option::is_none")]
+    pub rdns_status: Option<LookupStatus>,
+
+    /// DNSSEC status of the reverse DNS answer: secure, insecure, bogus or
+    /// indeterminate (only when DNSSEC validation is enabled)
+    #[serde(rename = "rDNS-DNSSEC", skip_serializing_if = "Option::is_none")]
+    pub rdns_dnssec: Option<DnssecStatus>,
+
+    /// Whether a PTR hostname resolves back to the address (only when
+    /// verified)
+    #[serde(rename = "rDNS-Verified", skip_serializing_if = "Option::is_none")]
+    pub rdns_verified: Option<Verification>,
+
     /// User agent string from HTTP headers
     #[serde(rename = "User-Agent")]
     pub user_agent: Option<String>,
@@ -28,18 +60,238 @@ pub struct IpResponse {
     /// Local server time with timezone
     #[serde(rename = "Local-Time")]
     pub local_time: String,
+
+    /// Proxy chain from client to server (only with `?chain=1`)
+    #[serde(rename = "Chain", skip_serializing_if = "Option::is_none")]
+    pub chain: Option<Vec<ProxyHop>>,
+}
+
+/// A hop in the proxy chain
+#[derive(Serialize, Debug)]
+pub struct ProxyHop {
+    /// Hop address, or the node identifier if it is not an IP address
+    #[serde(rename = "IP")]
+    pub ip: String,
+
+    /// Whether the hop is a trusted proxy or CDN edge
+    #[serde(rename = "Trusted")]
+    pub trusted: bool,
+
+    /// Where the hop was taken from (header name, direct or proxy-protocol)
+    #[serde(rename = "Source")]
+    pub source: String,
+
+    /// Reverse DNS hostname of the hop
+    #[serde(rename = "rDNS")]
+    pub rdns: Option<String>,
 }
 
 impl IpResponse {
     /// Convert to plain text format
     pub fn to_plain_text(&self) -> String {
+        let ip_source = self.ip_source.as_deref().unwrap_or("null");
         let rdns = self.rdns.as_deref().unwrap_or("null");
         let user_agent = self.user_agent.as_deref().unwrap_or("null");
 
-        format!(
-            "IP: {}\nrDNS: {}\nUser-Agent: {}\nUnix-Timestamp: {}\nUTC-Time: {}\nLocal-Time: {}",
-            self.ip, rdns, user_agent, self.unix_timestamp, self.utc_time, self.local_time
-        )
+        let mut text = format!(
+            "IP: {}\nIP-Source: {}\nrDNS: {}\nUser-Agent: {}\nUnix-Timestamp: {}\nUTC-Time: {}\nLocal-Time: {}",
+            self.ip,
+            ip_source,
+            rdns,
+            user_agent,
+            self.unix_timestamp,
+            self.utc_time,
+            self.local_time
+        );
+
+        if let Some(ref names) = self.rdns_names {
+            text.push_str(&format!("\nrDNS-Names: {}", names.join(", ")));
+        }
+        if let Some(ttl) = self.rdns_ttl {
+            text.push_str(&format!("\nrDNS-TTL: {}", ttl));
+        }
+        if let Some(status) = self.rdns_status {
+            text.push_str(&format!("\nrDNS-Status: {}", status.as_str()));
+        }
+        if let Some(dnssec) = self.rdns_dnssec {
+            text.push_str(&format!("\nrDNS-DNSSEC: {}", dnssec.as_str()));
+        }
+        if let Some(verified) = self.rdns_verified {
+            text.push_str(&format!("\nrDNS-Verified: {}", verified.as_str()));
+        }
+
+        if let Some(ref chain) = self.chain {
+            text.push_str("\nChain:");
+            for (i, hop) in chain.iter().enumerate() {
+                text.push_str(&format!(
+                    "\n  {}. {} ({}, {}) rDNS: {}",
+                    i + 1,
+                    hop.ip,
+                    hop.source,
+                    if hop.trusted { "trusted" } else { "untrusted" },
+                    hop.rdns.as_deref().unwrap_or("null")
+                ));
+            }
+        }
+
+        text
+    }
+}
+
+/// Response for `/dns` queries
+#[derive(Serialize, Debug)]
+pub struct DnsResponse {
+    /// Name that was queried, in lowercase without a trailing dot
+    #[serde(rename = "Name")]
+    pub name: String,
+
+    /// Record type that was queried
+    #[serde(rename = "Type")]
+    pub record_type: String,
+
+    /// Outcome of the lookup: ok, nxdomain, timeout, error or circuit_open
+    #[serde(rename = "Status")]
+    pub status: LookupStatus,
+
+    /// Seconds until the cached answer expires (0 while it is served stale)
+    #[serde(rename = "TTL")]
+    pub ttl: u64,
+
+    /// DNSSEC status of the answer: secure, insecure, bogus or indeterminate
+    /// (only when DNSSEC validation is enabled)
+    #[serde(rename = "DNSSEC", skip_serializing_if = "Option::is_none")]
+    pub dnssec: Option<DnssecStatus>,
+
+    /// Records found (empty unless the status is ok)
+    #[serde(rename = "Records")]
+    pub records: Vec<DnsRecord>,
+}
+
+/// A record in a `/dns` response
+#[derive(Serialize, Debug)]
+pub struct DnsRecord {
+    /// Record data in presentation form, as `dig +short` prints it
+    #[serde(rename = "Data")]
+    pub data: String,
+
+    /// The data split into fields, for types with more than one
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub fields: Option<RecordFields>,
+}
+
+/// Fields of MX, SRV, SOA and CAA records
+#[derive(Serialize, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum RecordFields {
+    Mx {
+        #[serde(rename = "Preference")]
+        preference: u16,
+        #[serde(rename = "Exchange")]
+        exchange: String,
+    },
+    Srv {
+        #[serde(rename = "Priority")]
+        priority: u16,
+        #[serde(rename = "Weight")]
+        weight: u16,
+        #[serde(rename = "Port")]
+        port: u16,
+        #[serde(rename = "Target")]
+        target: String,
+    },
+    Soa {
+        #[serde(rename = "MName")]
+        mname: String,
+        #[serde(rename = "RName")]
+        rname: String,
+        #[serde(rename = "Serial")]
+        serial: u32,
+        #[serde(rename = "Refresh")]
+        refresh: i32,
+        #[serde(rename = "Retry")]
+        retry: i32,
+        #[serde(rename = "Expire")]
+        expire: i32,
+        #[serde(rename = "Minimum")]
+        minimum: u32,
+    },
+    Caa {
+        #[serde(rename = "Flags")]
+        flags: u8,
+        #[serde(rename = "Tag")]
+        tag: String,
+        #[serde(rename = "Value")]
+        value: String,
+    },
+}
+
+impl DnsRecord {
+    /// Record of the given type from its presentation form
+    pub fn new(record_type: RecordType, data: String) -> Self {
+        let fields = RecordFields::parse(record_type, &data);
+        Self { data, fields }
+    }
+}
+
+impl RecordFields {
+    /// Split record data as produced by the resolver into its fields
+    fn parse(record_type: RecordType, data: &str) -> Option<Self> {
+        let mut parts = data.split(' ');
+        let mut next = || parts.next().map(str::to_string);
+
+        Some(match record_type {
+            RecordType::MX => RecordFields::Mx {
+                preference: next()?.parse().ok()?,
+                exchange: next()?,
+            },
+            RecordType::SRV => RecordFields::Srv {
+                priority: next()?.parse().ok()?,
+                weight: next()?.parse().ok()?,
+                port: next()?.parse().ok()?,
+                target: next()?,
+            },
+            RecordType::SOA => RecordFields::Soa {
+                mname: next()?,
+                rname: next()?,
+                serial: next()?.parse().ok()?,
+                refresh: next()?.parse().ok()?,
+                retry: next()?.parse().ok()?,
+                expire: next()?.parse().ok()?,
+                minimum: next()?.parse().ok()?,
+            },
+            RecordType::CAA => {
+                // The value is quoted and may itself contain spaces
+                let (flags, rest) = data.split_once(' ')?;
+                let (tag, value) = rest.split_once(' ')?;
+                RecordFields::Caa {
+                    flags: flags.parse().ok()?,
+                    tag: tag.to_string(),
+                    value: value.strip_prefix('"')?.strip_suffix('"')?.to_string(),
+                }
+            }
+            _ => return None,
+        })
+    }
+}
+
+impl DnsResponse {
+    /// Convert to plain text format
+    pub fn to_plain_text(&self) -> String {
+        let mut text = format!(
+            "Name: {}\nType: {}\nStatus: {}\nTTL: {}",
+            self.name,
+            self.record_type,
+            self.status.as_str(),
+            self.ttl
+        );
+        if let Some(dnssec) = self.dnssec {
+            text.push_str(&format!("\nDNSSEC: {}", dnssec.as_str()));
+        }
+        text.push_str("\nRecords:");
+        for record in &self.records {
+            text.push_str(&format!("\n  {}", record.data));
+        }
+        text
     }
 }
 
@@ -49,3 +301,27 @@ pub enum ResponseFormat {
     Json,
     PlainText,
 }
+
+impl ResponseFormat {
+    /// Determine response format from the `format` query parameter or Accept header
+    pub fn negotiate(format: Option<&str>, headers: &HeaderMap) -> Self {
+        // Check query parameter first
+        if let Some(fmt) = format {
+            return match fmt.to_lowercase().as_str() {
+                "text" | "plain" | "txt" => ResponseFormat::PlainText,
+                _ => ResponseFormat::Json,
+            };
+        }
+
+        // Check Accept header
+        if let Some(accept) = headers.get("accept")
+            && let Ok(accept_str) = accept.to_str()
+            && accept_str.contains("text/plain")
+        {
+            return ResponseFormat::PlainText;
+        }
+
+        // Default to JSON
+        ResponseFormat::Json
+    }
+}
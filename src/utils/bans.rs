@@ -0,0 +1,798 @@
+//! Automatic and manual client bans
+//!
+//! Clients collect strikes for offenses such as hitting the rate limit or
+//! sending invalid input. Too many strikes within `find_time` ban the
+//! client's network, for twice as long each time it is banned again. Bans
+//! can also be added and removed by hand, and are saved to a file so they
+//! survive restarts.
+
+use crate::utils::{network::Cidr, snapshot};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet, HashMap, hash_map::Entry};
+use std::net::IpAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::{Mutex, RwLock};
+
+/// Version of the ban file format
+const BAN_FILE_VERSION: u32 = 1;
+
+/// Behaviour that counts towards an automatic ban
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Offense {
+    /// Request rejected by the rate limiter
+    RateLimited,
+
+    /// User agent rejected by `security::is_valid_user_agent`
+    InvalidUserAgent,
+
+    /// IP address rejected by `security::sanitize_ip`
+    InvalidIp,
+
+    /// DNS query rejected by `security::sanitize_hostname` or with an
+    /// unsupported record type
+    InvalidDnsQuery,
+
+    /// Admin API request with a missing or wrong token
+    InvalidAdminToken,
+}
+
+impl Offense {
+    /// Offense name as reported in ban reasons
+    pub fn name(&self) -> &'static str {
+        match self {
+            Offense::RateLimited => "rate_limited",
+            Offense::InvalidUserAgent => "invalid_user_agent",
+            Offense::InvalidIp => "invalid_ip",
+            Offense::InvalidDnsQuery => "invalid_dns_query",
+            Offense::InvalidAdminToken => "invalid_admin_token",
+        }
+    }
+}
+
+impl FromStr for Offense {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rate_limited" => Ok(Offense::RateLimited),
+            "invalid_user_agent" => Ok(Offense::InvalidUserAgent),
+            "invalid_ip" => Ok(Offense::InvalidIp),
+            "invalid_dns_query" => Ok(Offense::InvalidDnsQuery),
+            "invalid_admin_token" => Ok(Offense::InvalidAdminToken),
+            other => Err(format!("unknown offense: {}", other)),
+        }
+    }
+}
+
+/// Automatic ban settings
+#[derive(Clone, Debug)]
+pub struct BanConfig {
+    /// Strikes within `find_time` that trigger a ban (0 disables automatic bans)
+    pub max_strikes: u32,
+
+    /// Window strikes are counted in
+    pub find_time: Duration,
+
+    /// Duration of a first ban, doubled for every repeat
+    pub ban_time: Duration,
+
+    /// Longest automatic ban
+    pub max_ban_time: Duration,
+
+    /// IPv4 prefix length clients are banned by
+    pub ipv4_prefix: u8,
+
+    /// IPv6 prefix length clients are banned by
+    pub ipv6_prefix: u8,
+}
+
+/// How a ban was created
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BanSource {
+    Auto,
+    Manual,
+}
+
+/// A banned network
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Ban {
+    /// Banned network
+    pub network: Cidr,
+
+    /// Why the network was banned
+    pub reason: String,
+
+    /// Whether the ban was automatic or manual
+    pub source: BanSource,
+
+    /// Unix timestamp the ban started at
+    pub created_at: u64,
+
+    /// Unix timestamp the ban ends at (`None` for permanent bans)
+    pub expires_at: Option<u64>,
+
+    /// Number of automatic bans of this network so far
+    pub level: u32,
+
+    /// Offenses that led to an automatic ban, by name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub offenses: BTreeMap<String, u32>,
+}
+
+impl Ban {
+    /// Whether the ban is in force at `now`
+    fn is_active(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+
+    /// Seconds until the ban ends, if it ends
+    pub fn remaining_secs(&self) -> Option<u64> {
+        self.expires_at
+            .map(|expires_at| expires_at.saturating_sub(unix_now()))
+    }
+}
+
+/// Strikes collected by a client
+struct Offender {
+    window_start: u64,
+    strikes: BTreeMap<&'static str, u32>,
+    level: u32,
+    last_seen: u64,
+}
+
+/// Bans and strikes carried over a restart
+#[derive(Default, Serialize, Deserialize)]
+pub struct BanSnapshot {
+    #[serde(default)]
+    bans: Vec<Ban>,
+    #[serde(default)]
+    offenders: Vec<SavedOffender>,
+}
+
+/// Offender in a snapshot
+#[derive(Serialize, Deserialize)]
+struct SavedOffender {
+    network: Cidr,
+    window_start: u64,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    strikes: BTreeMap<String, u32>,
+    level: u32,
+    last_seen: u64,
+}
+
+/// On-disk ban file
+#[derive(Serialize, Deserialize)]
+struct BanFile {
+    version: u32,
+    bans: Vec<Ban>,
+}
+
+#[derive(Default)]
+struct BanState {
+    bans: HashMap<Cidr, Ban>,
+    offenders: HashMap<Cidr, Offender>,
+    /// When each network was last unbanned, so gossip peers that still
+    /// have the ban drop it instead of spreading it back
+    unbanned: HashMap<Cidr, u64>,
+    /// Bumped on every change to the bans, for gossip to notice
+    generation: u64,
+    /// Distinct (IPv4, prefix) pairs among the bans, to look clients up
+    /// without scanning every ban
+    prefixes: BTreeSet<(bool, u8)>,
+}
+
+impl BanState {
+    fn update_prefixes(&mut self) {
+        self.generation += 1;
+        self.prefixes = self
+            .bans
+            .keys()
+            .map(|net| (net.is_ipv4(), net.prefix()))
+            .collect();
+    }
+
+    fn find(&self, ip: IpAddr, now: u64) -> Option<&Ban> {
+        let is_ipv4 = ip.to_canonical().is_ipv4();
+        self.prefixes
+            .iter()
+            .filter(|(v4, _)| *v4 == is_ipv4)
+            .filter_map(|(_, prefix)| self.bans.get(&Cidr::new(ip, *prefix)))
+            .find(|ban| ban.is_active(now))
+    }
+}
+
+/// Ban list shared by the middleware, handlers and admin API
+pub struct BanList {
+    config: BanConfig,
+    state: RwLock<BanState>,
+    path: Option<PathBuf>,
+    /// Held while saving so an older list never overwrites a newer one
+    save_lock: Mutex<()>,
+}
+
+impl BanList {
+    /// Create an empty ban list, saved to `path` if given
+    pub fn new(config: BanConfig, path: Option<PathBuf>) -> Self {
+        Self {
+            config,
+            state: RwLock::new(BanState::default()),
+            path,
+            save_lock: Mutex::new(()),
+        }
+    }
+
+    /// Load bans saved by a previous run, returning how many are still active
+    pub async fn load(&self) -> Result<usize, String> {
+        let Some(path) = &self.path else {
+            return Ok(0);
+        };
+
+        let contents = match tokio::fs::read_to_string(path).await {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(0),
+            Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+        };
+
+        let file: BanFile = serde_json::from_str(&contents)
+            .map_err(|e| format!("invalid ban file {}: {}", path.display(), e))?;
+        if file.version != BAN_FILE_VERSION {
+            return Err(format!(
+                "unsupported ban file version {} in {}",
+                file.version,
+                path.display()
+            ));
+        }
+
+        let now = unix_now();
+        let mut state = self.state.write().await;
+        for ban in file.bans.into_iter().filter(|ban| ban.is_active(now)) {
+            // Remember the level so repeat offenders keep escalating
+            if ban.source == BanSource::Auto {
+                state.offenders.insert(
+                    ban.network,
+                    Offender {
+                        window_start: now,
+                        strikes: BTreeMap::new(),
+                        level: ban.level,
+                        last_seen: now,
+                    },
+                );
+            }
+            state.bans.insert(ban.network, ban);
+        }
+        state.update_prefixes();
+
+        Ok(state.bans.len())
+    }
+
+    /// Active ban covering a client, if any
+    pub async fn check(&self, ip: IpAddr) -> Option<Ban> {
+        let state = self.state.read().await;
+        state.find(ip, unix_now()).cloned()
+    }
+
+    /// Count an offense against a client, banning it after too many
+    ///
+    /// Returns the new ban if this offense triggered one.
+    pub async fn record_offense(&self, ip: IpAddr, offense: Offense) -> Option<Ban> {
+        if self.config.max_strikes == 0 {
+            return None;
+        }
+
+        let network = Cidr::new(
+            ip,
+            match ip.to_canonical() {
+                IpAddr::V4(_) => self.config.ipv4_prefix,
+                IpAddr::V6(_) => self.config.ipv6_prefix,
+            },
+        );
+        let now = unix_now();
+
+        let ban = {
+            let mut state = self.state.write().await;
+            if state.find(ip, now).is_some() {
+                return None;
+            }
+
+            let offender = state.offenders.entry(network).or_insert(Offender {
+                window_start: now,
+                strikes: BTreeMap::new(),
+                level: 0,
+                last_seen: now,
+            });
+
+            // Start counting again once the window has passed
+            if now.saturating_sub(offender.window_start) > self.config.find_time.as_secs() {
+                offender.window_start = now;
+                offender.strikes.clear();
+            }
+            *offender.strikes.entry(offense.name()).or_insert(0) += 1;
+            offender.last_seen = now;
+
+            if offender.strikes.values().sum::<u32>() < self.config.max_strikes {
+                return None;
+            }
+
+            // Double the ban for every previous one
+            let duration = self
+                .config
+                .ban_time
+                .saturating_mul(2u32.saturating_pow(offender.level))
+                .min(self.config.max_ban_time);
+            offender.level += 1;
+
+            let ban = Ban {
+                network,
+                reason: format!("automatic: last offense {}", offense.name()),
+                source: BanSource::Auto,
+                created_at: now,
+                expires_at: Some(now.saturating_add(duration.as_secs())),
+                level: offender.level,
+                offenses: std::mem::take(&mut offender.strikes)
+                    .into_iter()
+                    .map(|(name, count)| (name.to_string(), count))
+                    .collect(),
+            };
+            state.unbanned.remove(&network);
+            state.bans.insert(network, ban.clone());
+            state.update_prefixes();
+            ban
+        };
+
+        tracing::warn!(
+            network = %ban.network,
+            offense = offense.name(),
+            level = ban.level,
+            expires_at = ?ban.expires_at,
+            "Client banned"
+        );
+        self.save().await;
+
+        Some(ban)
+    }
+
+    /// Ban a network by hand, permanently if no duration is given
+    pub async fn ban(&self, network: Cidr, reason: String, duration: Option<Duration>) -> Ban {
+        let network = network.normalized();
+        let now = unix_now();
+        let ban = Ban {
+            network,
+            reason,
+            source: BanSource::Manual,
+            created_at: now,
+            expires_at: duration.map(|duration| now.saturating_add(duration.as_secs())),
+            level: 0,
+            offenses: BTreeMap::new(),
+        };
+
+        {
+            let mut state = self.state.write().await;
+            state.unbanned.remove(&network);
+            state.bans.insert(network, ban.clone());
+            state.update_prefixes();
+        }
+
+        tracing::info!(network = %network, reason = %ban.reason, "Network banned manually");
+        self.save().await;
+
+        ban
+    }
+
+    /// Lift the ban on a network and forget its offenses
+    pub async fn unban(&self, network: &Cidr) -> Option<Ban> {
+        let network = network.normalized();
+        let ban = {
+            let mut state = self.state.write().await;
+            state.offenders.remove(&network);
+            let ban = state.bans.remove(&network)?;
+            state.unbanned.insert(network, unix_now());
+            state.update_prefixes();
+            ban
+        };
+
+        tracing::info!(network = %network, "Network unbanned");
+        self.save().await;
+
+        Some(ban)
+    }
+
+    /// Active bans, oldest first
+    pub async fn list(&self) -> Vec<Ban> {
+        let state = self.state.read().await;
+        let now = unix_now();
+
+        let mut bans: Vec<Ban> = state
+            .bans
+            .values()
+            .filter(|ban| ban.is_active(now))
+            .cloned()
+            .collect();
+        bans.sort_by_key(|ban| ban.created_at);
+        bans
+    }
+
+    /// Active bans and recent offenders, to carry over a restart
+    pub async fn snapshot(&self) -> BanSnapshot {
+        let offenders = {
+            let state = self.state.read().await;
+            state
+                .offenders
+                .iter()
+                .map(|(network, offender)| SavedOffender {
+                    network: *network,
+                    window_start: offender.window_start,
+                    strikes: offender
+                        .strikes
+                        .iter()
+                        .map(|(name, count)| (name.to_string(), *count))
+                        .collect(),
+                    level: offender.level,
+                    last_seen: offender.last_seen,
+                })
+                .collect()
+        };
+
+        BanSnapshot {
+            bans: self.list().await,
+            offenders,
+        }
+    }
+
+    /// Restore bans and strikes saved by `snapshot`, returning how many bans
+    /// are still active
+    ///
+    /// Bans that expired and offenders that went quiet while the server was
+    /// down are dropped, as are strikes for offenses this version does not
+    /// know.
+    pub async fn restore(&self, snapshot: BanSnapshot) -> usize {
+        let now = unix_now();
+        let quiet = self.quiet_time();
+
+        let mut state = self.state.write().await;
+        for saved in snapshot.offenders {
+            if now.saturating_sub(saved.last_seen) > quiet {
+                continue;
+            }
+            let strikes = saved
+                .strikes
+                .into_iter()
+                .filter_map(|(name, count)| Some((name.parse::<Offense>().ok()?.name(), count)))
+                .collect();
+            state.offenders.insert(
+                saved.network,
+                Offender {
+                    window_start: saved.window_start,
+                    strikes,
+                    level: saved.level,
+                    last_seen: saved.last_seen,
+                },
+            );
+        }
+
+        // Bans already loaded from the ban file take precedence
+        let mut restored = 0;
+        for ban in snapshot.bans.into_iter().filter(|ban| ban.is_active(now)) {
+            if let Entry::Vacant(entry) = state.bans.entry(ban.network) {
+                entry.insert(ban);
+                restored += 1;
+            }
+        }
+        state.update_prefixes();
+
+        restored
+    }
+
+    /// Counter that changes whenever the bans do
+    pub async fn generation(&self) -> u64 {
+        self.state.read().await.generation
+    }
+
+    /// Active bans and recent unbans, for gossip peers
+    pub async fn gossip_state(&self) -> (Vec<Ban>, Vec<(Cidr, u64)>) {
+        let unbanned = {
+            let state = self.state.read().await;
+            state
+                .unbanned
+                .iter()
+                .map(|(network, unbanned_at)| (*network, *unbanned_at))
+                .collect()
+        };
+        (self.list().await, unbanned)
+    }
+
+    /// Merge bans and unbans received from a gossip peer
+    ///
+    /// The most recently created ban of a network wins, and an unban wins
+    /// over any ban created no later than itself. Returns whether anything
+    /// changed.
+    pub async fn merge(&self, bans: Vec<Ban>, unbanned: Vec<(Cidr, u64)>) -> bool {
+        let now = unix_now();
+        let changed = {
+            let mut state = self.state.write().await;
+            let mut changed = false;
+
+            for (network, unbanned_at) in unbanned {
+                let latest = state.unbanned.entry(network).or_insert(unbanned_at);
+                *latest = (*latest).max(unbanned_at);
+                if state
+                    .bans
+                    .get(&network)
+                    .is_some_and(|ban| ban.created_at <= unbanned_at)
+                {
+                    state.bans.remove(&network);
+                    state.offenders.remove(&network);
+                    changed = true;
+                }
+            }
+
+            for ban in bans {
+                if !ban.is_active(now)
+                    || state
+                        .unbanned
+                        .get(&ban.network)
+                        .is_some_and(|unbanned_at| ban.created_at <= *unbanned_at)
+                {
+                    continue;
+                }
+                let newer = state.bans.get(&ban.network).is_none_or(|current| {
+                    (ban.created_at, ban.expires_at.unwrap_or(u64::MAX))
+                        > (current.created_at, current.expires_at.unwrap_or(u64::MAX))
+                });
+                if newer {
+                    tracing::info!(network = %ban.network, reason = %ban.reason, "Ban received from peer");
+                    state.bans.insert(ban.network, ban);
+                    changed = true;
+                }
+            }
+
+            if changed {
+                state.update_prefixes();
+            }
+            changed
+        };
+
+        if changed {
+            self.save().await;
+        }
+        changed
+    }
+
+    /// How long an offender is remembered after its last offense
+    fn quiet_time(&self) -> u64 {
+        self.config
+            .find_time
+            .max(self.config.max_ban_time)
+            .as_secs()
+    }
+
+    /// Drop expired bans and offenders that have been quiet for long enough
+    pub async fn cleanup(&self) {
+        let now = unix_now();
+        let quiet = self.quiet_time();
+
+        let expired = {
+            let mut state = self.state.write().await;
+            let before = state.bans.len();
+            state.bans.retain(|_, ban| ban.is_active(now));
+            state
+                .offenders
+                .retain(|_, offender| now.saturating_sub(offender.last_seen) <= quiet);
+            state
+                .unbanned
+                .retain(|_, unbanned_at| now.saturating_sub(*unbanned_at) <= quiet);
+
+            // Only a changed ban list needs to go out to gossip peers again
+            let expired = before - state.bans.len();
+            if expired > 0 {
+                state.update_prefixes();
+            }
+            expired
+        };
+
+        if expired > 0 {
+            self.save().await;
+        }
+    }
+
+    /// Write the active bans to the ban file, replacing it atomically
+    async fn save(&self) {
+        let Some(path) = &self.path else {
+            return;
+        };
+
+        let _guard = self.save_lock.lock().await;
+        let file = BanFile {
+            version: BAN_FILE_VERSION,
+            bans: self.list().await,
+        };
+
+        let result = async {
+            let contents = serde_json::to_vec_pretty(&file).map_err(std::io::Error::other)?;
+            snapshot::write_atomic(path, &contents).await
+        }
+        .await;
+
+        if let Err(e) = result {
+            tracing::error!(path = %path.display(), error = %e, "Failed to save bans");
+        }
+    }
+}
+
+/// Current Unix time in seconds
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> BanConfig {
+        BanConfig {
+            max_strikes: 3,
+            find_time: Duration::from_secs(600),
+            ban_time: Duration::from_secs(60),
+            max_ban_time: Duration::from_secs(150),
+            ipv4_prefix: 32,
+            ipv6_prefix: 64,
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    async fn offend(bans: &BanList, client: &str, times: usize) -> Option<Ban> {
+        let mut ban = None;
+        for _ in 0..times {
+            ban = bans.record_offense(ip(client), Offense::RateLimited).await;
+        }
+        ban
+    }
+
+    #[tokio::test]
+    async fn test_strikes_trigger_ban() {
+        let bans = BanList::new(config(), None);
+
+        assert!(offend(&bans, "192.0.2.1", 2).await.is_none());
+        assert!(bans.check(ip("192.0.2.1")).await.is_none());
+
+        let ban = bans
+            .record_offense(ip("192.0.2.1"), Offense::InvalidIp)
+            .await
+            .unwrap();
+        assert_eq!(ban.offenses["rate_limited"], 2);
+        assert_eq!(ban.offenses["invalid_ip"], 1);
+        assert_eq!(ban.remaining_secs(), Some(60));
+
+        assert!(bans.check(ip("192.0.2.1")).await.is_some());
+        assert!(bans.check(ip("::ffff:192.0.2.1")).await.is_some());
+        assert!(bans.check(ip("192.0.2.2")).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_ban_escalates() {
+        let bans = BanList::new(config(), None);
+        let network = Cidr::new(ip("2001:db8::1"), 64);
+
+        for expected in [60, 120, 150] {
+            let ban = offend(&bans, "2001:db8::1", 3).await.unwrap();
+            assert_eq!(ban.network, network);
+            assert_eq!(ban.remaining_secs(), Some(expected));
+
+            // Expire the ban but keep the offender's history
+            bans.state.write().await.bans.clear();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_manual_ban_and_unban() {
+        let bans = BanList::new(config(), None);
+        let network: Cidr = "198.51.100.7/24".parse().unwrap();
+
+        let ban = bans.ban(network, "abuse report".into(), None).await;
+        assert_eq!(ban.network.to_string(), "198.51.100.0/24");
+        assert!(bans.check(ip("198.51.100.200")).await.is_some());
+        assert_eq!(bans.list().await.len(), 1);
+
+        assert!(bans.unban(&network).await.is_some());
+        assert!(bans.check(ip("198.51.100.200")).await.is_none());
+        assert!(bans.unban(&network).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_longest_manual_ban_does_not_overflow() {
+        let bans = BanList::new(config(), None);
+        let network: Cidr = "198.51.100.0/24".parse().unwrap();
+
+        let ban = bans
+            .ban(
+                network,
+                "abuse report".into(),
+                Some(Duration::from_secs(u64::MAX)),
+            )
+            .await;
+        assert_eq!(ban.expires_at, Some(u64::MAX));
+        assert!(bans.check(ip("198.51.100.1")).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_longest_automatic_ban_does_not_overflow() {
+        let config = BanConfig {
+            ban_time: Duration::from_secs(u64::MAX),
+            max_ban_time: Duration::from_secs(u64::MAX),
+            ..config()
+        };
+        let bans = BanList::new(config, None);
+
+        let ban = offend(&bans, "192.0.2.1", 3).await.unwrap();
+        assert_eq!(ban.expires_at, Some(u64::MAX));
+        assert!(bans.check(ip("192.0.2.1")).await.is_some());
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_changes_generation_only_when_bans_expire() {
+        let bans = BanList::new(config(), None);
+        bans.ban("203.0.113.0/24".parse().unwrap(), "manual".into(), None)
+            .await;
+        offend(&bans, "192.0.2.1", 3).await;
+
+        let generation = bans.generation().await;
+        bans.cleanup().await;
+        assert_eq!(bans.generation().await, generation);
+
+        // Expire the automatic ban
+        for ban in bans.state.write().await.bans.values_mut() {
+            if ban.source == BanSource::Auto {
+                ban.expires_at = Some(0);
+            }
+        }
+        bans.cleanup().await;
+        assert_ne!(bans.generation().await, generation);
+        assert_eq!(bans.list().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_bans_persist() {
+        let path = std::env::temp_dir().join(format!("ip-api-bans-{}.json", std::process::id()));
+
+        let bans = BanList::new(config(), Some(path.clone()));
+        bans.ban("203.0.113.0/24".parse().unwrap(), "manual".into(), None)
+            .await;
+        offend(&bans, "192.0.2.1", 3).await;
+
+        let restored = BanList::new(config(), Some(path.clone()));
+        assert_eq!(restored.load().await, Ok(2));
+        assert!(restored.check(ip("203.0.113.9")).await.is_some());
+        assert!(restored.check(ip("192.0.2.1")).await.is_some());
+
+        // Escalation continues from the saved level
+        restored.state.write().await.bans.clear();
+        let ban = offend(&restored, "192.0.2.1", 3).await.unwrap();
+        assert_eq!(ban.level, 2);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restore_counts_only_new_bans() {
+        let bans = BanList::new(config(), None);
+        bans.ban("203.0.113.0/24".parse().unwrap(), "manual".into(), None)
+            .await;
+        offend(&bans, "192.0.2.1", 3).await;
+        let snapshot = bans.snapshot().await;
+
+        let restored = BanList::new(config(), None);
+        restored
+            .ban("203.0.113.0/24".parse().unwrap(), "manual".into(), None)
+            .await;
+        assert_eq!(restored.restore(snapshot).await, 1);
+        assert_eq!(restored.list().await.len(), 2);
+    }
+}
@@ -1,68 +1,1184 @@
 //! DNS response caching
 
+use crate::utils::dns::{DnsAnswer, DnsFailure, DnssecStatus};
+use crate::utils::snapshot::Clock;
+use async_trait::async_trait;
+use lru::LruCache;
+use serde::Serialize;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex as StdMutex};
 use std::time::{Duration, Instant};
-use tokio::sync::RwLock;
+use tokio::sync::{Mutex, watch};
+
+/// Longest an answer is cached by default, whatever its TTL
+const DEFAULT_MAX_TTL: Duration = Duration::from_secs(86400);
+
+/// Upper bound on every configured duration, so that expiry times always fit
+/// in an `Instant`
+const MAX_DURATION: Duration = Duration::from_secs(365 * 86400);
+
+/// Cache entry in a snapshot: key, value and expiry as Unix milliseconds
+pub type SavedDnsEntry = (String, Option<Vec<String>>, u64);
 
 /// Cache entry with expiration
 struct CacheEntry {
-    value: Option<String>,
+    /// Records found, or None if the lookup failed
+    value: Option<Vec<String>>,
+    /// Why the lookup failed, if it was made here
+    failure: Option<DnsFailure>,
+    /// DNSSEC status, if the lookup was made and validated here
+    dnssec: Option<DnssecStatus>,
     expires_at: Instant,
+    /// Times served since it was cached
+    hits: u32,
+    /// Looked up here and not yet sent to gossip peers
+    unshared: bool,
+}
+
+/// Cache activity counters
+#[derive(Default)]
+struct Counters {
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: AtomicU64,
+    expirations: AtomicU64,
+    coalesced: AtomicU64,
+    stale_hits: AtomicU64,
+    prefetches: AtomicU64,
+    l2_hits: AtomicU64,
+    l2_misses: AtomicU64,
+    l2_errors: AtomicU64,
+    l2_writes: AtomicU64,
+}
+
+/// Second-level cache shared between replicas
+///
+/// Consulted when an answer is not in the in-memory cache, before asking
+/// the upstream nameservers. Only answers are stored; failed lookups stay
+/// local.
+#[async_trait]
+pub trait DnsStore: Send + Sync {
+    /// Records stored for `key` and the time left before they expire
+    async fn get(&self, key: &str) -> Result<Option<(Vec<String>, Duration)>, String>;
+
+    /// Store records for `key`, expiring after `ttl`
+    async fn set(&self, key: &str, records: &[String], ttl: Duration) -> Result<(), String>;
+}
+
+/// A lookup result served from the cache
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CachedAnswer {
+    /// Records found, or None if the lookup failed
+    pub value: Option<Vec<String>>,
+
+    /// Why the lookup failed, if known (failures cached by gossip peers or
+    /// restored from a snapshot lose their reason)
+    pub failure: Option<DnsFailure>,
+
+    /// DNSSEC status, if the answer was looked up and validated here
+    pub dnssec: Option<DnssecStatus>,
+
+    /// Time left before the entry expires (zero once stale)
+    pub ttl: Duration,
+}
+
+impl CachedAnswer {
+    fn new(entry: &CacheEntry, now: Instant) -> Self {
+        Self {
+            value: entry.value.clone(),
+            failure: entry.failure,
+            dnssec: entry.dnssec,
+            ttl: entry.expires_at.saturating_duration_since(now),
+        }
+    }
+}
+
+/// A cache entry as read, with whether it is due for a refresh
+enum Cached {
+    /// Live and not due for a refresh, or missing
+    Fresh(Option<CachedAnswer>),
+
+    /// Expired, but within the stale window
+    Stale(CachedAnswer),
+
+    /// Live, popular and about to expire
+    Prefetch(CachedAnswer),
+}
+
+/// Result of an in-flight lookup, None until it completes
+type Pending = watch::Receiver<Option<CachedAnswer>>;
+
+/// Lookups in progress, by cache key
+type InFlight = StdMutex<HashMap<String, watch::Sender<Option<CachedAnswer>>>>;
+
+/// Removes an in-flight lookup when its leader finishes or is cancelled
+///
+/// Dropping the sender wakes any waiters, which retry the lookup
+/// themselves if no result was sent.
+struct InFlightGuard {
+    in_flight: Arc<InFlight>,
+    key: String,
+}
+
+impl Drop for InFlightGuard {
+    fn drop(&mut self) {
+        self.in_flight.lock().unwrap().remove(&self.key);
+    }
+}
+
+/// Snapshot of the cache size and activity counters
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DnsCacheStats {
+    /// Entries currently cached
+    pub size: usize,
+
+    /// Most entries kept before the least recently used are evicted
+    pub capacity: usize,
+
+    /// Lookups answered from the in-memory cache
+    pub hits: u64,
+
+    /// Lookups not in the in-memory cache, or expired
+    pub misses: u64,
+
+    /// Live entries evicted to make room
+    pub evictions: u64,
+
+    /// Entries dropped because their TTL ran out
+    pub expirations: u64,
+
+    /// Lookups that waited for an identical lookup already in progress
+    pub coalesced: u64,
+
+    /// Expired entries served while they were refreshed
+    pub stale_hits: u64,
+
+    /// Frequently used entries refreshed before they expired
+    pub prefetches: u64,
+
+    /// Second-level cache activity, if one is configured
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub l2: Option<DnsTierStats>,
+}
+
+/// Activity counters of the second-level cache
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DnsTierStats {
+    /// In-memory misses answered from the second-level cache
+    pub hits: u64,
+
+    /// In-memory misses not found there either
+    pub misses: u64,
+
+    /// Reads and writes that failed (reads count as misses too)
+    pub errors: u64,
+
+    /// Answers stored for other replicas
+    pub writes: u64,
 }
 
-/// DNS cache for reverse lookups
+/// DNS cache for reverse and forward lookups
+///
+/// Reverse lookups are keyed by IP address and forward lookups by record
+/// type and name (`A host.example`). The cache holds at most `capacity`
+/// entries, evicting the least recently used. Answers are kept for the TTL
+/// the upstream gave them, clamped to the TTL bounds; without one, results
+/// with records are kept for the positive TTL and empty answers and failed
+/// lookups for the negative TTL.
+///
+/// Concurrent misses for the same key share a single lookup (see
+/// `get_or_lookup`). Expired entries can be served for a while longer
+/// while they are refreshed in the background, and entries in frequent use
+/// can be refreshed shortly before they expire.
+///
+/// With a second-level store (see `with_l2`), misses are looked up there
+/// before the upstream, and answers looked up here are written to it, so
+/// replicas sharing the store share their answers.
 #[derive(Clone)]
 pub struct DnsCache {
-    cache: Arc<RwLock<HashMap<String, CacheEntry>>>,
-    ttl: Duration,
+    cache: Arc<Mutex<LruCache<String, CacheEntry>>>,
+    in_flight: Arc<InFlight>,
+    positive_ttl: Duration,
+    negative_ttl: Duration,
+    min_ttl: Duration,
+    max_ttl: Duration,
+    stale: Duration,
+    prefetch: Duration,
+    prefetch_min_hits: u32,
+    l2: Option<Arc<dyn DnsStore>>,
+    counters: Arc<Counters>,
 }
 
 impl DnsCache {
-    /// Create a new DNS cache with specified TTL
-    pub fn new(ttl: Duration) -> Self {
+    /// Create a new DNS cache holding up to `capacity` entries
+    pub fn new(capacity: usize, positive_ttl: Duration, negative_ttl: Duration) -> Self {
+        let capacity = NonZeroUsize::new(capacity).unwrap_or(NonZeroUsize::MIN);
         Self {
-            cache: Arc::new(RwLock::new(HashMap::new())),
-            ttl,
+            cache: Arc::new(Mutex::new(LruCache::new(capacity))),
+            in_flight: Arc::new(StdMutex::new(HashMap::new())),
+            positive_ttl: positive_ttl.min(MAX_DURATION),
+            negative_ttl: negative_ttl.min(MAX_DURATION),
+            min_ttl: Duration::ZERO,
+            max_ttl: DEFAULT_MAX_TTL,
+            stale: Duration::ZERO,
+            prefetch: Duration::ZERO,
+            prefetch_min_hits: 0,
+            l2: None,
+            counters: Arc::new(Counters::default()),
         }
     }
 
-    /// Get a cached value if it exists and hasn't expired
-    pub async fn get(&self, key: &str) -> Option<Option<String>> {
-        let cache = self.cache.read().await;
+    /// Cache answers for at least `min` and at most `max`, whatever TTL the
+    /// upstream gave them
+    pub fn with_ttl_bounds(mut self, min: Duration, max: Duration) -> Self {
+        self.min_ttl = min.min(MAX_DURATION);
+        self.max_ttl = max.min(MAX_DURATION);
+        self
+    }
+
+    /// Serve entries for up to `stale` after they expire while refreshing
+    /// them in the background
+    pub fn with_stale(mut self, stale: Duration) -> Self {
+        self.stale = stale.min(MAX_DURATION);
+        self
+    }
+
+    /// Refresh entries served at least `min_hits` times when they are
+    /// within `window` of expiring
+    pub fn with_prefetch(mut self, window: Duration, min_hits: u32) -> Self {
+        self.prefetch = window;
+        self.prefetch_min_hits = min_hits;
+        self
+    }
+
+    /// Share answers with other replicas through a second-level store
+    pub fn with_l2(mut self, store: Arc<dyn DnsStore>) -> Self {
+        self.l2 = Some(store);
+        self
+    }
 
-        if let Some(entry) = cache.get(key) {
-            if Instant::now() < entry.expires_at {
-                return Some(entry.value.clone());
+    /// Whether an entry can still be served, possibly stale
+    fn usable(&self, entry: &CacheEntry, now: Instant) -> bool {
+        now < entry.expires_at + self.stale
+    }
+
+    /// TTL for a lookup result, given the upstream's TTL if any
+    fn ttl(&self, value: &Option<Vec<String>>, upstream: Option<Duration>) -> Duration {
+        match (value, upstream) {
+            (Some(_), Some(ttl)) => ttl.max(self.min_ttl).min(self.max_ttl),
+            (Some(records), None) if !records.is_empty() => self.positive_ttl,
+            _ => self.negative_ttl,
+        }
+    }
+
+    /// Get a cached value if it exists and can still be served
+    pub async fn get(&self, key: &str) -> Option<Option<Vec<String>>> {
+        match self.read(key).await {
+            Cached::Fresh(answer) => answer.map(|answer| answer.value),
+            Cached::Stale(answer) | Cached::Prefetch(answer) => Some(answer.value),
+        }
+    }
+
+    /// Read an entry, noting whether it is due for a refresh
+    async fn read(&self, key: &str) -> Cached {
+        let mut cache = self.cache.lock().await;
+        let now = Instant::now();
+
+        match cache.get_mut(key) {
+            Some(entry) if now < entry.expires_at => {
+                self.counters.hits.fetch_add(1, Ordering::Relaxed);
+                entry.hits = entry.hits.saturating_add(1);
+
+                let prefetch = !self.prefetch.is_zero()
+                    && entry.hits >= self.prefetch_min_hits
+                    && entry.expires_at - now <= self.prefetch;
+                if prefetch {
+                    return Cached::Prefetch(CachedAnswer::new(entry, now));
+                }
+                return Cached::Fresh(Some(CachedAnswer::new(entry, now)));
+            }
+            Some(entry) if self.usable(entry, now) => {
+                self.counters.stale_hits.fetch_add(1, Ordering::Relaxed);
+                return Cached::Stale(CachedAnswer::new(entry, now));
+            }
+            Some(_) => {
+                cache.pop(key);
+                self.counters.expirations.fetch_add(1, Ordering::Relaxed);
+            }
+            None => {}
+        }
+
+        self.counters.misses.fetch_add(1, Ordering::Relaxed);
+        Cached::Fresh(None)
+    }
+
+    /// Get a cached value, or run `lookup` and cache its result
+    ///
+    /// Callers that miss while a lookup for the same key is in progress wait
+    /// for it and share its result instead of starting their own. Stale
+    /// entries, and entries due for a prefetch, are returned at once and
+    /// refreshed in the background.
+    pub async fn get_or_lookup<F, Fut>(&self, key: &str, lookup: F) -> CachedAnswer
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<DnsAnswer, DnsFailure>> + Send + 'static,
+    {
+        match self.read(key).await {
+            Cached::Fresh(Some(answer)) => return answer,
+            Cached::Fresh(None) => {}
+            Cached::Stale(answer) => {
+                self.refresh(key, lookup);
+                return answer;
+            }
+            Cached::Prefetch(answer) => {
+                if self.refresh(key, lookup) {
+                    self.counters.prefetches.fetch_add(1, Ordering::Relaxed);
+                }
+                return answer;
+            }
+        }
+
+        loop {
+            let pending = {
+                let mut in_flight = self.in_flight.lock().unwrap();
+                match in_flight.get(key) {
+                    Some(sender) => Some(sender.subscribe()),
+                    None => {
+                        in_flight.insert(key.to_string(), watch::channel(None).0);
+                        None
+                    }
+                }
+            };
+
+            match pending {
+                Some(pending) => {
+                    if let Some(answer) = self.wait(pending).await {
+                        return answer;
+                    }
+                    // The lookup was cancelled before it finished; try again
+                }
+                None => return self.lead(key, lookup).await,
             }
         }
+    }
+
+    /// Wait for an in-flight lookup, or None if it was cancelled
+    async fn wait(&self, mut pending: Pending) -> Option<CachedAnswer> {
+        self.counters.coalesced.fetch_add(1, Ordering::Relaxed);
+        let result = pending.wait_for(Option::is_some).await.ok()?;
+        result.clone()
+    }
+
+    /// Run a lookup registered in `in_flight` and share its result
+    async fn lead<F, Fut>(&self, key: &str, lookup: F) -> CachedAnswer
+    where
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = Result<DnsAnswer, DnsFailure>>,
+    {
+        let _guard = InFlightGuard {
+            in_flight: self.in_flight.clone(),
+            key: key.to_string(),
+        };
+
+        // A lookup may have finished between the cache miss and registering
+        let now = Instant::now();
+        let cached = self
+            .cache
+            .lock()
+            .await
+            .peek(key)
+            .filter(|entry| now < entry.expires_at)
+            .map(|entry| CachedAnswer::new(entry, now));
+        let answer = match cached {
+            Some(answer) => answer,
+            None => match self.read_l2(key).await {
+                Some(answer) => answer,
+                None => self.store(key, lookup().await).await,
+            },
+        };
 
-        None
+        self.share(key, &answer);
+        answer
+    }
+
+    /// Look up a served entry again in the background, unless a lookup for
+    /// it is already in progress
+    ///
+    /// Returns whether a lookup was started.
+    fn refresh<F, Fut>(&self, key: &str, lookup: F) -> bool
+    where
+        F: FnOnce() -> Fut + Send + 'static,
+        Fut: Future<Output = Result<DnsAnswer, DnsFailure>> + Send + 'static,
+    {
+        {
+            let mut in_flight = self.in_flight.lock().unwrap();
+            if in_flight.contains_key(key) {
+                return false;
+            }
+            in_flight.insert(key.to_string(), watch::channel(None).0);
+        }
+
+        let cache = self.clone();
+        let key = key.to_string();
+        tokio::spawn(async move {
+            let _guard = InFlightGuard {
+                in_flight: cache.in_flight.clone(),
+                key: key.clone(),
+            };
+            let result = lookup().await;
+
+            // A failed refresh keeps serving the records already cached
+            let answer = match result {
+                Err(failure) if cache.has_records(&key).await => CachedAnswer {
+                    value: None,
+                    failure: Some(failure),
+                    dnssec: None,
+                    ttl: Duration::ZERO,
+                },
+                result => cache.store(&key, result).await,
+            };
+            cache.share(&key, &answer);
+        });
+        true
+    }
+
+    /// Whether a servable answer is cached, without counting it as used
+    async fn has_records(&self, key: &str) -> bool {
+        let cache = self.cache.lock().await;
+        let now = Instant::now();
+        cache
+            .peek(key)
+            .is_some_and(|entry| entry.value.is_some() && self.usable(entry, now))
+    }
+
+    /// Wake callers waiting for `key` with its result
+    fn share(&self, key: &str, answer: &CachedAnswer) {
+        if let Some(sender) = self.in_flight.lock().unwrap().get(key) {
+            sender.send_replace(Some(answer.clone()));
+        }
+    }
+
+    /// Cache the result of a lookup made here
+    ///
+    /// Lookups skipped by the circuit breaker are not cached, so they are
+    /// made as soon as it closes.
+    async fn store(&self, key: &str, result: Result<DnsAnswer, DnsFailure>) -> CachedAnswer {
+        let (value, ttl, failure, dnssec) = match result {
+            Ok(answer) => (Some(answer.records), answer.ttl, None, answer.dnssec),
+            Err(DnsFailure::CircuitOpen) => {
+                return CachedAnswer {
+                    value: None,
+                    failure: Some(DnsFailure::CircuitOpen),
+                    dnssec: None,
+                    ttl: Duration::ZERO,
+                };
+            }
+            Err(failure) => (None, None, Some(failure), None),
+        };
+        let ttl = self.ttl(&value, ttl);
+        self.insert_for(key.to_string(), value.clone(), failure, dnssec, ttl)
+            .await;
+        if let Some(records) = &value {
+            self.write_l2(key, records.clone(), ttl);
+        }
+
+        CachedAnswer {
+            value,
+            failure,
+            dnssec,
+            ttl,
+        }
+    }
+
+    /// Look up a missed key in the second-level store, caching it here if
+    /// found
+    async fn read_l2(&self, key: &str) -> Option<CachedAnswer> {
+        let store = self.l2.as_ref()?;
+        let found = match store.get(key).await {
+            Ok(found) => found,
+            Err(e) => {
+                tracing::warn!(key, error = %e, "DNS cache L2 read failed");
+                self.counters.l2_errors.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        };
+        let Some((records, ttl)) = found else {
+            self.counters.l2_misses.fetch_add(1, Ordering::Relaxed);
+            return None;
+        };
+
+        self.counters.l2_hits.fetch_add(1, Ordering::Relaxed);
+        let ttl = ttl.min(self.max_ttl);
+        let value = Some(records);
+        self.insert_shared(key.to_string(), value.clone(), ttl)
+            .await;
+        Some(CachedAnswer {
+            value,
+            failure: None,
+            dnssec: None,
+            ttl,
+        })
+    }
+
+    /// Write an answer looked up here to the second-level store in the
+    /// background
+    fn write_l2(&self, key: &str, records: Vec<String>, ttl: Duration) {
+        let Some(store) = self.l2.clone() else {
+            return;
+        };
+        let counters = self.counters.clone();
+        let key = key.to_string();
+        tokio::spawn(async move {
+            match store.set(&key, &records, ttl).await {
+                Ok(()) => counters.l2_writes.fetch_add(1, Ordering::Relaxed),
+                Err(e) => {
+                    tracing::warn!(key, error = %e, "DNS cache L2 write failed");
+                    counters.l2_errors.fetch_add(1, Ordering::Relaxed)
+                }
+            };
+        });
     }
 
     /// Insert a value into the cache
-    pub async fn insert(&self, key: String, value: Option<String>) {
-        let mut cache = self.cache.write().await;
+    pub async fn insert(&self, key: String, value: Option<Vec<String>>) {
+        let ttl = self.ttl(&value, None);
+        self.insert_for(key, value, None, None, ttl).await;
+    }
+
+    /// Insert a value looked up here, expiring after `ttl`
+    async fn insert_for(
+        &self,
+        key: String,
+        value: Option<Vec<String>>,
+        failure: Option<DnsFailure>,
+        dnssec: Option<DnssecStatus>,
+        ttl: Duration,
+    ) {
+        let mut cache = self.cache.lock().await;
+        let expires_at = Instant::now() + ttl.min(MAX_DURATION);
 
-        cache.insert(
+        self.put(
+            &mut cache,
             key,
             CacheEntry {
                 value,
-                expires_at: Instant::now() + self.ttl,
+                failure,
+                dnssec,
+                expires_at,
+                hits: 0,
+                unshared: true,
             },
         );
     }
 
+    /// Insert a value looked up by a gossip peer, expiring after `ttl` at
+    /// most
+    ///
+    /// Entries looked up here are kept, as they are at least as fresh.
+    pub async fn insert_shared(&self, key: String, value: Option<Vec<String>>, ttl: Duration) {
+        let mut cache = self.cache.lock().await;
+        let now = Instant::now();
+
+        if cache.peek(&key).is_some_and(|entry| now < entry.expires_at) {
+            return;
+        }
+        // The peer already applied the answer's TTL
+        let expires_at = now + ttl.min(self.max_ttl);
+        self.put(
+            &mut cache,
+            key,
+            CacheEntry {
+                value,
+                failure: None,
+                dnssec: None,
+                expires_at,
+                hits: 0,
+                unshared: false,
+            },
+        );
+    }
+
+    /// Store an entry, counting the entry it evicts if any
+    fn put(&self, cache: &mut LruCache<String, CacheEntry>, key: String, entry: CacheEntry) {
+        // `push` returns the replaced entry when the key was already cached
+        if let Some((evicted, old)) = cache.push(key.clone(), entry)
+            && evicted != key
+        {
+            let counter = if self.usable(&old, Instant::now()) {
+                &self.counters.evictions
+            } else {
+                &self.counters.expirations
+            };
+            counter.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Entries looked up since the last call, with their remaining TTL
+    pub async fn take_unshared(&self) -> Vec<(String, Option<Vec<String>>, Duration)> {
+        let mut cache = self.cache.lock().await;
+        let now = Instant::now();
+
+        cache
+            .iter_mut()
+            .filter(|(_, entry)| entry.unshared && now < entry.expires_at)
+            .map(|(key, entry)| {
+                entry.unshared = false;
+                (key.clone(), entry.value.clone(), entry.expires_at - now)
+            })
+            .collect()
+    }
+
+    /// Servable entries, least recently used first, for a snapshot
+    pub async fn snapshot(&self, clock: &Clock) -> Vec<SavedDnsEntry> {
+        let cache = self.cache.lock().await;
+        let now = Instant::now();
+
+        cache
+            .iter()
+            .rev()
+            .filter(|(_, entry)| self.usable(entry, now))
+            .map(|(key, entry)| {
+                let expires_at = clock.to_unix_millis(entry.expires_at);
+                (key.clone(), entry.value.clone(), expires_at)
+            })
+            .collect()
+    }
+
+    /// Restore entries saved by `snapshot`, returning how many were kept
+    ///
+    /// Entries keep the time they had left by the wall clock, so any that
+    /// can no longer be served are dropped, as are the least recently used
+    /// beyond the capacity.
+    pub async fn restore(&self, entries: Vec<SavedDnsEntry>, clock: &Clock) -> usize {
+        let mut cache = self.cache.lock().await;
+        let now = Instant::now();
+        let excess = entries.len().saturating_sub(cache.cap().get());
+        let mut restored = 0;
+
+        for (key, value, expires_at) in entries.into_iter().skip(excess) {
+            let Some(expires_at) = clock.to_instant(expires_at) else {
+                continue;
+            };
+            let entry = CacheEntry {
+                value,
+                failure: None,
+                dnssec: None,
+                // Never longer than this instance would cache it for
+                expires_at: expires_at.min(now + self.max_ttl.max(self.positive_ttl)),
+                hits: 0,
+                unshared: false,
+            };
+            if self.usable(&entry, now) {
+                self.put(&mut cache, key, entry);
+                restored += 1;
+            }
+        }
+
+        restored
+    }
+
     /// Clean up expired entries
     pub async fn cleanup(&self) {
-        let mut cache = self.cache.write().await;
+        let mut cache = self.cache.lock().await;
         let now = Instant::now();
+        let before = cache.len();
 
-        cache.retain(|_, entry| now < entry.expires_at);
+        cache.retain(|_, entry| self.usable(entry, now));
+        let expired = (before - cache.len()) as u64;
+        self.counters
+            .expirations
+            .fetch_add(expired, Ordering::Relaxed);
     }
 
     /// Get cache size
     pub async fn size(&self) -> usize {
-        self.cache.read().await.len()
+        self.cache.lock().await.len()
+    }
+
+    /// Cache size and activity since startup
+    pub async fn stats(&self) -> DnsCacheStats {
+        let cache = self.cache.lock().await;
+        DnsCacheStats {
+            size: cache.len(),
+            capacity: cache.cap().get(),
+            hits: self.counters.hits.load(Ordering::Relaxed),
+            misses: self.counters.misses.load(Ordering::Relaxed),
+            evictions: self.counters.evictions.load(Ordering::Relaxed),
+            expirations: self.counters.expirations.load(Ordering::Relaxed),
+            coalesced: self.counters.coalesced.load(Ordering::Relaxed),
+            stale_hits: self.counters.stale_hits.load(Ordering::Relaxed),
+            prefetches: self.counters.prefetches.load(Ordering::Relaxed),
+            l2: self.l2.as_ref().map(|_| DnsTierStats {
+                hits: self.counters.l2_hits.load(Ordering::Relaxed),
+                misses: self.counters.l2_misses.load(Ordering::Relaxed),
+                errors: self.counters.l2_errors.load(Ordering::Relaxed),
+                writes: self.counters.l2_writes.load(Ordering::Relaxed),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn found(name: &str) -> Option<Vec<String>> {
+        Some(vec![name.to_string()])
+    }
+
+    fn answer(name: &str, ttl: Option<u64>) -> Result<DnsAnswer, DnsFailure> {
+        Ok(DnsAnswer {
+            records: vec![name.to_string()],
+            ttl: ttl.map(Duration::from_secs),
+            dnssec: None,
+        })
+    }
+
+    /// A lookup returning `value` that counts how often it runs
+    fn counted(
+        lookups: &Arc<AtomicU64>,
+        value: Result<DnsAnswer, DnsFailure>,
+    ) -> impl FnOnce() -> std::future::Ready<Result<DnsAnswer, DnsFailure>> + Send + 'static {
+        let lookups = lookups.clone();
+        move || {
+            lookups.fetch_add(1, Ordering::SeqCst);
+            std::future::ready(value)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_least_recently_used_entry_is_evicted() {
+        let cache = DnsCache::new(2, Duration::from_secs(60), Duration::from_secs(60));
+        cache.insert("a".to_string(), found("a.example")).await;
+        cache.insert("b".to_string(), found("b.example")).await;
+
+        // Using "a" makes "b" the least recently used
+        assert_eq!(cache.get("a").await, Some(found("a.example")));
+        cache.insert("c".to_string(), found("c.example")).await;
+
+        assert!(cache.get("b").await.is_none());
+        assert_eq!(cache.get("a").await, Some(found("a.example")));
+        assert_eq!(cache.get("c").await, Some(found("c.example")));
+
+        let stats = cache.stats().await;
+        assert_eq!(stats.size, 2);
+        assert_eq!(stats.capacity, 2);
+        assert_eq!(stats.hits, 3);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.evictions, 1);
+        assert_eq!(stats.expirations, 0);
+    }
+
+    #[tokio::test]
+    async fn test_negative_results_use_negative_ttl() {
+        let cache = DnsCache::new(10, Duration::from_secs(60), Duration::ZERO);
+        cache
+            .insert("found".to_string(), found("host.example"))
+            .await;
+        cache.insert("empty".to_string(), Some(Vec::new())).await;
+        cache.insert("failed".to_string(), None).await;
+
+        assert_eq!(cache.get("found").await, Some(found("host.example")));
+        assert!(cache.get("empty").await.is_none());
+        assert!(cache.get("failed").await.is_none());
+
+        let stats = cache.stats().await;
+        assert_eq!(stats.expirations, 2);
+        assert_eq!(stats.size, 1);
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_counts_expirations() {
+        let cache = DnsCache::new(10, Duration::ZERO, Duration::ZERO);
+        cache.insert("a".to_string(), found("a.example")).await;
+        cache.insert("b".to_string(), None).await;
+
+        cache.cleanup().await;
+        let stats = cache.stats().await;
+        assert_eq!(stats.size, 0);
+        assert_eq!(stats.expirations, 2);
+        assert_eq!(stats.evictions, 0);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_misses_share_one_lookup() {
+        let cache = DnsCache::new(10, Duration::from_secs(60), Duration::from_secs(60));
+        let lookups = Arc::new(AtomicU64::new(0));
+
+        let mut tasks = tokio::task::JoinSet::new();
+        for _ in 0..20 {
+            let (cache, lookups) = (cache.clone(), lookups.clone());
+            tasks.spawn(async move {
+                cache
+                    .get_or_lookup("192.0.2.1", move || async move {
+                        lookups.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        answer("host.example", None)
+                    })
+                    .await
+            });
+        }
+        while let Some(result) = tasks.join_next().await {
+            assert_eq!(result.unwrap().value, found("host.example"));
+        }
+
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.stats().await.coalesced, 19);
+    }
+
+    #[tokio::test]
+    async fn test_cancelled_lookup_lets_waiters_retry() {
+        let cache = DnsCache::new(10, Duration::from_secs(60), Duration::from_secs(60));
+
+        let leader = {
+            let cache = cache.clone();
+            tokio::spawn(
+                async move { cache.get_or_lookup("192.0.2.1", std::future::pending).await },
+            )
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let waiter = {
+            let cache = cache.clone();
+            tokio::spawn(async move {
+                cache
+                    .get_or_lookup("192.0.2.1", || async { answer("host.example", None) })
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        leader.abort();
+
+        assert_eq!(waiter.await.unwrap().value, found("host.example"));
+        assert_eq!(cache.stats().await.coalesced, 1);
+    }
+
+    #[tokio::test]
+    async fn test_stale_entry_is_served_while_refreshed() {
+        let cache = DnsCache::new(10, Duration::from_millis(20), Duration::from_millis(20))
+            .with_stale(Duration::from_secs(60));
+        let lookups = Arc::new(AtomicU64::new(0));
+        cache.insert("a".to_string(), found("old.example")).await;
+        tokio::time::sleep(Duration::from_millis(30)).await;
+
+        let value = cache
+            .get_or_lookup("a", counted(&lookups, answer("new.example", None)))
+            .await;
+        assert_eq!(value.value, found("old.example"));
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.get("a").await, Some(found("new.example")));
+        assert_eq!(cache.stats().await.stale_hits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_refresh_keeps_stale_records() {
+        let cache =
+            DnsCache::new(10, Duration::ZERO, Duration::ZERO).with_stale(Duration::from_secs(60));
+        let lookups = Arc::new(AtomicU64::new(0));
+        cache.insert("a".to_string(), found("old.example")).await;
+
+        let value = cache
+            .get_or_lookup("a", counted(&lookups, Err(DnsFailure::Error)))
+            .await;
+        assert_eq!(value.value, found("old.example"));
+
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+        assert_eq!(cache.get("a").await, Some(found("old.example")));
+    }
+
+    #[tokio::test]
+    async fn test_popular_entries_are_prefetched() {
+        let cache = DnsCache::new(10, Duration::from_secs(60), Duration::from_secs(60))
+            .with_prefetch(Duration::from_secs(120), 2);
+        let lookups = Arc::new(AtomicU64::new(0));
+        cache.insert("a".to_string(), found("old.example")).await;
+
+        // The first hit is not enough to prefetch
+        let value = cache
+            .get_or_lookup("a", counted(&lookups, answer("new.example", None)))
+            .await;
+        assert_eq!(value.value, found("old.example"));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(lookups.load(Ordering::SeqCst), 0);
+
+        let value = cache
+            .get_or_lookup("a", counted(&lookups, answer("new.example", None)))
+            .await;
+        assert_eq!(value.value, found("old.example"));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        let stats = cache.stats().await;
+        assert_eq!(stats.prefetches, 1);
+        assert_eq!(stats.stale_hits, 0);
+        assert_eq!(cache.get("a").await, Some(found("new.example")));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_ttl_is_honoured_within_bounds() {
+        let cache = DnsCache::new(10, Duration::from_secs(300), Duration::from_secs(60))
+            .with_ttl_bounds(Duration::from_secs(30), Duration::from_secs(3600));
+        let lookups = Arc::new(AtomicU64::new(0));
+
+        let short = cache
+            .get_or_lookup("short", counted(&lookups, answer("a.example", Some(5))))
+            .await;
+        assert_eq!(short.ttl, Duration::from_secs(30));
+
+        let long = cache
+            .get_or_lookup("long", counted(&lookups, answer("b.example", Some(86400))))
+            .await;
+        assert_eq!(long.ttl, Duration::from_secs(3600));
+
+        let exact = cache
+            .get_or_lookup("exact", counted(&lookups, answer("c.example", Some(120))))
+            .await;
+        assert_eq!(exact.ttl, Duration::from_secs(120));
+
+        // Without an upstream TTL the configured ones apply
+        let fallback = cache
+            .get_or_lookup("fallback", counted(&lookups, answer("d.example", None)))
+            .await;
+        assert_eq!(fallback.ttl, Duration::from_secs(300));
+
+        // A negative answer uses the SOA minimum
+        let negative = DnsAnswer {
+            records: Vec::new(),
+            ttl: Some(Duration::from_secs(900)),
+            dnssec: None,
+        };
+        let empty = cache
+            .get_or_lookup("empty", counted(&lookups, Ok(negative)))
+            .await;
+        assert_eq!(empty.ttl, Duration::from_secs(900));
+
+        let failed = cache
+            .get_or_lookup("failed", counted(&lookups, Err(DnsFailure::Error)))
+            .await;
+        assert_eq!(failed.ttl, Duration::from_secs(60));
+
+        // Served from the cache with the time left
+        let cached = cache
+            .get_or_lookup("exact", counted(&lookups, answer("c.example", Some(120))))
+            .await;
+        assert!(cached.ttl <= Duration::from_secs(120));
+        assert!(cached.ttl > Duration::from_secs(110));
+        assert_eq!(lookups.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn test_huge_settings_do_not_overflow() {
+        let cache = DnsCache::new(10, Duration::MAX, Duration::MAX)
+            .with_ttl_bounds(Duration::MAX, Duration::MAX)
+            .with_stale(Duration::MAX);
+        let lookups = Arc::new(AtomicU64::new(0));
+
+        let found_answer = cache
+            .get_or_lookup("a", counted(&lookups, answer("a.example", Some(u64::MAX))))
+            .await;
+        assert_eq!(found_answer.ttl, MAX_DURATION);
+        cache.insert("b".to_string(), None).await;
+        cache
+            .insert_shared("c".to_string(), found("c.example"), Duration::MAX)
+            .await;
+
+        let clock = Clock::now();
+        let entries = vec![("d".to_string(), found("d.example"), u64::MAX)];
+        assert_eq!(cache.restore(entries, &clock).await, 1);
+        assert_eq!(cache.get("d").await, Some(found("d.example")));
+    }
+
+    #[tokio::test]
+    async fn test_restore_keeps_remaining_ttl() {
+        let clock = Clock::now();
+        let now = clock.to_unix_millis(Instant::now());
+        let entries = vec![
+            ("expired".to_string(), found("a.example"), now - 1_000),
+            ("live".to_string(), found("b.example"), now + 100_000),
+            ("failed".to_string(), None, now + 10_000),
+        ];
+
+        let cache = DnsCache::new(10, Duration::from_secs(300), Duration::from_secs(60));
+        assert_eq!(cache.restore(entries, &clock).await, 2);
+        assert!(cache.get("expired").await.is_none());
+
+        let lookups = Arc::new(AtomicU64::new(0));
+        let live = cache
+            .get_or_lookup("live", counted(&lookups, Err(DnsFailure::Error)))
+            .await;
+        assert_eq!(live.value, found("b.example"));
+        assert!(live.ttl <= Duration::from_secs(100));
+        assert!(live.ttl > Duration::from_secs(90));
+        assert_eq!(cache.get("failed").await, Some(None));
+        assert_eq!(lookups.load(Ordering::SeqCst), 0);
+
+        // Round trip, least recently used first, into a smaller cache
+        let saved = cache.snapshot(&clock).await;
+        assert_eq!(saved[0].0, "live");
+        let small = DnsCache::new(1, Duration::from_secs(300), Duration::from_secs(60));
+        assert_eq!(small.restore(saved, &clock).await, 1);
+        assert_eq!(small.get("failed").await, Some(None));
+        assert_eq!(small.stats().await.evictions, 0);
+    }
+
+    #[tokio::test]
+    async fn test_failures_keep_their_reason() {
+        let cache = DnsCache::new(10, Duration::from_secs(300), Duration::from_secs(60));
+        let lookups = Arc::new(AtomicU64::new(0));
+
+        // Skipped lookups are not cached
+        let skipped = cache
+            .get_or_lookup("a", counted(&lookups, Err(DnsFailure::CircuitOpen)))
+            .await;
+        assert_eq!(skipped.value, None);
+        assert_eq!(skipped.failure, Some(DnsFailure::CircuitOpen));
+        assert_eq!(cache.size().await, 0);
+
+        let timed_out = cache
+            .get_or_lookup("a", counted(&lookups, Err(DnsFailure::Timeout)))
+            .await;
+        assert_eq!(timed_out.failure, Some(DnsFailure::Timeout));
+        assert_eq!(timed_out.ttl, Duration::from_secs(60));
+
+        let cached = cache
+            .get_or_lookup("a", counted(&lookups, answer("a.example", None)))
+            .await;
+        assert_eq!(cached.value, None);
+        assert_eq!(cached.failure, Some(DnsFailure::Timeout));
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_answers_keep_their_dnssec_status() {
+        let cache = DnsCache::new(10, Duration::from_secs(300), Duration::from_secs(60));
+        let lookups = Arc::new(AtomicU64::new(0));
+
+        let secure = Ok(DnsAnswer {
+            records: vec!["a.example".to_string()],
+            ttl: None,
+            dnssec: Some(DnssecStatus::Secure),
+        });
+        cache.get_or_lookup("a", counted(&lookups, secure)).await;
+        let cached = cache
+            .get_or_lookup("a", counted(&lookups, answer("b.example", None)))
+            .await;
+        assert_eq!(cached.dnssec, Some(DnssecStatus::Secure));
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        // Answers from elsewhere carry no proof
+        cache
+            .insert_shared(
+                "b".to_string(),
+                Some(vec!["b.example".to_string()]),
+                Duration::from_secs(60),
+            )
+            .await;
+        let shared = cache
+            .get_or_lookup("b", counted(&lookups, answer("c.example", None)))
+            .await;
+        assert_eq!(shared.value, Some(vec!["b.example".to_string()]));
+        assert_eq!(shared.dnssec, None);
+    }
+
+    /// Second-level store kept in memory, or failing every command
+    #[derive(Default)]
+    struct MemoryStore {
+        entries: StdMutex<HashMap<String, (Vec<String>, Duration)>>,
+        broken: bool,
+    }
+
+    #[async_trait]
+    impl DnsStore for MemoryStore {
+        async fn get(&self, key: &str) -> Result<Option<(Vec<String>, Duration)>, String> {
+            if self.broken {
+                return Err("connection refused".to_string());
+            }
+            Ok(self.entries.lock().unwrap().get(key).cloned())
+        }
+
+        async fn set(&self, key: &str, records: &[String], ttl: Duration) -> Result<(), String> {
+            if self.broken {
+                return Err("connection refused".to_string());
+            }
+            let entry = (records.to_vec(), ttl);
+            self.entries.lock().unwrap().insert(key.to_string(), entry);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_l2_shares_answers_between_caches() {
+        let store = Arc::new(MemoryStore::default());
+        let new_cache = || {
+            DnsCache::new(10, Duration::from_secs(300), Duration::from_secs(60))
+                .with_l2(store.clone())
+        };
+        let (first, second) = (new_cache(), new_cache());
+        let lookups = Arc::new(AtomicU64::new(0));
+
+        first
+            .get_or_lookup("a", counted(&lookups, answer("a.example", Some(120))))
+            .await;
+        // Failed lookups stay local
+        first
+            .get_or_lookup("b", counted(&lookups, Err(DnsFailure::Error)))
+            .await;
+        tokio::task::yield_now().await;
+        assert_eq!(
+            store.entries.lock().unwrap().get("a"),
+            Some(&(vec!["a.example".to_string()], Duration::from_secs(120)))
+        );
+        assert!(!store.entries.lock().unwrap().contains_key("b"));
+
+        // The second cache finds "a" in the store, then in memory
+        for _ in 0..2 {
+            let shared = second
+                .get_or_lookup("a", counted(&lookups, answer("other.example", None)))
+                .await;
+            assert_eq!(shared.value, found("a.example"));
+            assert!(shared.ttl > Duration::from_secs(119));
+        }
+        assert_eq!(lookups.load(Ordering::SeqCst), 2);
+
+        let stats = second.stats().await;
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        let l2 = stats.l2.unwrap();
+        assert_eq!((l2.hits, l2.misses, l2.errors, l2.writes), (1, 0, 0, 0));
+        let l2 = first.stats().await.l2.unwrap();
+        assert_eq!((l2.hits, l2.misses, l2.errors, l2.writes), (0, 2, 0, 1));
+    }
+
+    #[tokio::test]
+    async fn test_l2_errors_fall_back_to_lookup() {
+        let store = Arc::new(MemoryStore {
+            broken: true,
+            ..MemoryStore::default()
+        });
+        let cache =
+            DnsCache::new(10, Duration::from_secs(300), Duration::from_secs(60)).with_l2(store);
+        let lookups = Arc::new(AtomicU64::new(0));
+
+        let answer = cache
+            .get_or_lookup("a", counted(&lookups, answer("a.example", None)))
+            .await;
+        assert_eq!(answer.value, found("a.example"));
+        assert_eq!(lookups.load(Ordering::SeqCst), 1);
+
+        // Wait for the background write to fail
+        tokio::task::yield_now().await;
+        let l2 = cache.stats().await.l2.unwrap();
+        assert_eq!((l2.hits, l2.misses, l2.errors, l2.writes), (0, 1, 2, 0));
+    }
+
+    #[tokio::test]
+    async fn test_stats_omit_l2_without_store() {
+        let cache = DnsCache::new(10, Duration::from_secs(300), Duration::from_secs(60));
+        assert_eq!(cache.stats().await.l2, None);
     }
 }
@@ -0,0 +1,137 @@
+//! Redis-backed second-level DNS cache
+//!
+//! Lets replicas share DNS answers: each answer is stored as a JSON list of
+//! records under its cache key, expiring with the answer's TTL.
+
+use crate::utils::cache::DnsStore;
+use async_trait::async_trait;
+use redis::aio::ConnectionManager;
+use std::time::Duration;
+
+/// DNS answers stored in Redis
+pub struct RedisDnsStore {
+    connection: ConnectionManager,
+    prefix: String,
+}
+
+impl RedisDnsStore {
+    /// Create a store keeping its keys under `prefix`
+    ///
+    /// Use `RedisStore::connect` to create the connection.
+    pub fn new(connection: ConnectionManager, prefix: impl Into<String>) -> Self {
+        Self {
+            connection,
+            prefix: prefix.into(),
+        }
+    }
+
+    fn key(&self, key: &str) -> String {
+        format!("{}:{}", self.prefix, key)
+    }
+}
+
+#[async_trait]
+impl DnsStore for RedisDnsStore {
+    async fn get(&self, key: &str) -> Result<Option<(Vec<String>, Duration)>, String> {
+        let key = self.key(key);
+        let mut connection = self.connection.clone();
+        let (value, ttl_ms): (Option<String>, i64) = redis::pipe()
+            .atomic()
+            .get(&key)
+            .pttl(&key)
+            .query_async(&mut connection)
+            .await
+            .map_err(|e| e.to_string())?;
+
+        // A negative PTTL means the key expired between the two commands, or
+        // was stored without an expiry by something else
+        let (Some(value), Ok(ttl_ms)) = (value, u64::try_from(ttl_ms)) else {
+            return Ok(None);
+        };
+        let records = serde_json::from_str(&value).map_err(|e| e.to_string())?;
+        Ok(Some((records, Duration::from_millis(ttl_ms))))
+    }
+
+    async fn set(&self, key: &str, records: &[String], ttl: Duration) -> Result<(), String> {
+        let ttl_ms = ttl.as_millis() as u64;
+        if ttl_ms == 0 {
+            return Ok(());
+        }
+        let value = serde_json::to_string(records).map_err(|e| e.to_string())?;
+
+        let mut connection = self.connection.clone();
+        redis::cmd("SET")
+            .arg(self.key(key))
+            .arg(value)
+            .arg("PX")
+            .arg(ttl_ms)
+            .query_async::<()>(&mut connection)
+            .await
+            .map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::rate_limit_redis::RedisStore;
+    use crate::utils::cache::DnsCache;
+    use crate::utils::dns::DnsAnswer;
+    use std::sync::Arc;
+
+    /// Store for the server in `TEST_REDIS_URL` (e.g. `redis://127.0.0.1:6379`)
+    fn test_store(prefix: &str) -> Arc<RedisDnsStore> {
+        let url = std::env::var("TEST_REDIS_URL").expect("TEST_REDIS_URL is not set");
+        let connection = RedisStore::connect(&url, Duration::from_secs(1)).unwrap();
+        let prefix = format!("ip-api-test:dns:{}:{}", prefix, std::process::id());
+        Arc::new(RedisDnsStore::new(connection, prefix))
+    }
+
+    fn cache(store: Arc<RedisDnsStore>) -> DnsCache {
+        DnsCache::new(10, Duration::from_secs(60), Duration::from_secs(60)).with_l2(store)
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_REDIS_URL"]
+    async fn test_redis_round_trip() {
+        let store = test_store("round-trip");
+        let records = vec!["a.example".to_string(), "b.example".to_string()];
+
+        store
+            .set("192.0.2.1", &records, Duration::from_secs(30))
+            .await
+            .unwrap();
+        let (stored, ttl) = store.get("192.0.2.1").await.unwrap().unwrap();
+        assert_eq!(stored, records);
+        assert!(ttl > Duration::from_secs(29) && ttl <= Duration::from_secs(30));
+
+        assert_eq!(store.get("192.0.2.2").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs TEST_REDIS_URL"]
+    async fn test_redis_answers_are_shared_between_caches() {
+        let store = test_store("shared");
+        let (first, second) = (cache(store.clone()), cache(store));
+
+        let answer = || async {
+            Ok(DnsAnswer {
+                records: vec!["host.example".to_string()],
+                ttl: Some(Duration::from_secs(120)),
+                dnssec: None,
+            })
+        };
+        first.get_or_lookup("192.0.2.1", answer).await;
+
+        // The write to Redis happens in the background
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        let shared = second
+            .get_or_lookup("192.0.2.1", std::future::pending)
+            .await;
+        assert_eq!(shared.value, Some(vec!["host.example".to_string()]));
+        assert!(shared.ttl > Duration::from_secs(110));
+
+        let l2 = second.stats().await.l2.unwrap();
+        assert_eq!((l2.hits, l2.misses, l2.errors), (1, 0, 0));
+    }
+}
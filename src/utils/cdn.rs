@@ -0,0 +1,115 @@
+//! CDN provider presets for client IP headers
+//!
+//! A preset names the header a CDN uses to pass the client address. The
+//! header is only honoured when the request comes from that provider's
+//! published ranges, which are loaded from `<dir>/<preset>.txt`.
+
+use crate::utils::network::Cidr;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Known CDN and proxy presets
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CdnPreset {
+    Cloudflare,
+    Akamai,
+    Fastly,
+    Nginx,
+}
+
+impl CdnPreset {
+    /// Preset name, also used as the ranges file name and reported IP source
+    pub fn name(&self) -> &'static str {
+        match self {
+            CdnPreset::Cloudflare => "cloudflare",
+            CdnPreset::Akamai => "akamai",
+            CdnPreset::Fastly => "fastly",
+            CdnPreset::Nginx => "nginx",
+        }
+    }
+
+    /// Header carrying the client address
+    pub fn header(&self) -> &'static str {
+        match self {
+            CdnPreset::Cloudflare => "cf-connecting-ip",
+            CdnPreset::Akamai => "true-client-ip",
+            CdnPreset::Fastly => "fastly-client-ip",
+            CdnPreset::Nginx => "x-real-ip",
+        }
+    }
+}
+
+impl FromStr for CdnPreset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "cloudflare" => Ok(CdnPreset::Cloudflare),
+            "akamai" => Ok(CdnPreset::Akamai),
+            "fastly" => Ok(CdnPreset::Fastly),
+            "nginx" => Ok(CdnPreset::Nginx),
+            other => Err(format!("unknown CDN preset: {}", other)),
+        }
+    }
+}
+
+/// A preset together with the networks it is trusted from
+#[derive(Clone, Debug)]
+pub struct CdnRanges {
+    pub preset: CdnPreset,
+    pub networks: Vec<Cidr>,
+}
+
+impl CdnRanges {
+    /// Check whether an address belongs to this provider
+    pub fn contains(&self, ip: &std::net::IpAddr) -> bool {
+        self.networks.iter().any(|net| net.contains(ip))
+    }
+}
+
+/// Load the ranges for each preset from `dir`
+pub fn load_ranges(dir: &Path, presets: &[CdnPreset]) -> Result<Vec<CdnRanges>, String> {
+    presets
+        .iter()
+        .map(|preset| {
+            let path = dir.join(format!("{}.txt", preset.name()));
+            let contents = std::fs::read_to_string(&path)
+                .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+
+            Ok(CdnRanges {
+                preset: *preset,
+                networks: parse_ranges(&contents)
+                    .map_err(|e| format!("{}: {}", path.display(), e))?,
+            })
+        })
+        .collect()
+}
+
+/// Parse a ranges file: one network per line, `#` starts a comment
+fn parse_ranges(contents: &str) -> Result<Vec<Cidr>, String> {
+    contents
+        .lines()
+        .map(|line| line.split('#').next().unwrap_or("").trim())
+        .filter(|line| !line.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_preset() {
+        assert_eq!("Cloudflare".parse(), Ok(CdnPreset::Cloudflare));
+        assert!("unknown".parse::<CdnPreset>().is_err());
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        let ranges =
+            parse_ranges("# Cloudflare\n173.245.48.0/20\n\n2400:cb00::/32 # v6\n").unwrap();
+        assert_eq!(ranges.len(), 2);
+        assert!(parse_ranges("not a range").is_err());
+    }
+}
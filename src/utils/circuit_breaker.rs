@@ -0,0 +1,209 @@
+//! Circuit breaker for upstream lookups
+//!
+//! After `threshold` consecutive failures the breaker opens and calls are
+//! skipped for the cooldown. Then a single probe is let through: success
+//! closes the breaker, failure opens it for another cooldown.
+
+use serde::Serialize;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Longest cooldown, so that the end of one always fits in an `Instant`
+const MAX_COOLDOWN: Duration = Duration::from_secs(365 * 86400);
+
+/// Breaker state as reported by `/health`
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum BreakerState {
+    /// Calls go through
+    Closed,
+
+    /// Calls are skipped until the cooldown ends
+    Open,
+
+    /// A probe call is deciding whether to close again
+    HalfOpen,
+}
+
+/// Breaker state and failure count
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BreakerStatus {
+    pub state: BreakerState,
+
+    /// Failures since the last success
+    pub consecutive_failures: u32,
+
+    /// Seconds until a probe is let through (only while open)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_in_seconds: Option<u64>,
+}
+
+enum State {
+    Closed,
+    Open {
+        until: Instant,
+    },
+    /// A probe was let through; another one is allowed after `until` in
+    /// case it never reports back
+    HalfOpen {
+        until: Instant,
+    },
+}
+
+struct Inner {
+    state: State,
+    failures: u32,
+}
+
+/// Consecutive-failure circuit breaker
+pub struct CircuitBreaker {
+    /// Failures that open the breaker (0 never opens it)
+    threshold: u32,
+    cooldown: Duration,
+    inner: Mutex<Inner>,
+}
+
+impl CircuitBreaker {
+    /// Open after `threshold` consecutive failures, for `cooldown` (at most
+    /// a year)
+    pub fn new(threshold: u32, cooldown: Duration) -> Self {
+        Self {
+            threshold,
+            cooldown: cooldown.min(MAX_COOLDOWN),
+            inner: Mutex::new(Inner {
+                state: State::Closed,
+                failures: 0,
+            }),
+        }
+    }
+
+    /// Whether a call may go ahead
+    ///
+    /// Once the cooldown is over, the first caller is let through as the
+    /// probe and the rest are turned away until it reports back.
+    pub fn allow(&self) -> bool {
+        let mut inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        match inner.state {
+            State::Closed => true,
+            State::Open { until } | State::HalfOpen { until } if now >= until => {
+                inner.state = State::HalfOpen {
+                    until: now + self.cooldown,
+                };
+                true
+            }
+            State::Open { .. } | State::HalfOpen { .. } => false,
+        }
+    }
+
+    /// Report a successful call, closing the breaker
+    pub fn record_success(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.state = State::Closed;
+        inner.failures = 0;
+    }
+
+    /// Report a failed call, opening the breaker at the threshold
+    pub fn record_failure(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.failures = inner.failures.saturating_add(1);
+
+        // Failures only reset on success, so a failed probe reopens it too
+        if self.threshold > 0 && inner.failures >= self.threshold {
+            inner.state = State::Open {
+                until: Instant::now() + self.cooldown,
+            };
+        }
+    }
+
+    /// Current state, for monitoring
+    pub fn status(&self) -> BreakerStatus {
+        let inner = self.inner.lock().unwrap();
+        let now = Instant::now();
+        let (state, retry_in) = match inner.state {
+            State::Closed => (BreakerState::Closed, None),
+            State::Open { until } => {
+                let retry_in = until.saturating_duration_since(now);
+                (
+                    BreakerState::Open,
+                    Some(retry_in.as_secs_f64().ceil() as u64),
+                )
+            }
+            State::HalfOpen { .. } => (BreakerState::HalfOpen, None),
+        };
+        BreakerStatus {
+            state,
+            consecutive_failures: inner.failures,
+            retry_in_seconds: retry_in,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_opens_after_consecutive_failures() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(30));
+
+        breaker.record_failure();
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(breaker.allow());
+        assert_eq!(breaker.status().state, BreakerState::Closed);
+
+        breaker.record_failure();
+        assert!(!breaker.allow());
+        let status = breaker.status();
+        assert_eq!(status.state, BreakerState::Open);
+        assert_eq!(status.consecutive_failures, 3);
+        assert_eq!(status.retry_in_seconds, Some(30));
+    }
+
+    #[test]
+    fn test_probe_closes_or_reopens() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(!breaker.allow());
+
+        // One probe after the cooldown; a failed probe reopens at once
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow());
+        assert!(!breaker.allow());
+        assert_eq!(breaker.status().state, BreakerState::HalfOpen);
+        breaker.record_failure();
+        assert_eq!(breaker.status().state, BreakerState::Open);
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(breaker.allow());
+        breaker.record_success();
+        assert!(breaker.allow());
+        assert_eq!(breaker.status().state, BreakerState::Closed);
+        assert_eq!(breaker.status().consecutive_failures, 0);
+    }
+
+    #[test]
+    fn test_huge_cooldown_does_not_overflow() {
+        let breaker = CircuitBreaker::new(1, Duration::MAX);
+        breaker.record_failure();
+        assert!(!breaker.allow());
+        assert_eq!(
+            breaker.status().retry_in_seconds,
+            Some(MAX_COOLDOWN.as_secs())
+        );
+    }
+
+    #[test]
+    fn test_zero_threshold_never_opens() {
+        let breaker = CircuitBreaker::new(0, Duration::from_secs(30));
+        for _ in 0..10 {
+            breaker.record_failure();
+        }
+        assert!(breaker.allow());
+        assert_eq!(breaker.status().state, BreakerState::Closed);
+    }
+}
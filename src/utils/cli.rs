@@ -12,5 +12,10 @@ pub fn parse_port() -> Result<u16, Box<dyn std::error::Error>> {
         .position(|arg| arg == "--port")
         .and_then(|i| args.get(i + 1))
         .and_then(|p| p.parse::<u16>().ok())
-        .ok_or_else(|| "Usage: ip-api --port <PORT>".into())
+        .ok_or_else(|| "Usage: ip-api --port <PORT> [--proxy-protocol]".into())
+}
+
+/// Check whether the --proxy-protocol flag was passed
+pub fn parse_proxy_protocol() -> bool {
+    env::args().any(|arg| arg == "--proxy-protocol")
 }
@@ -0,0 +1,445 @@
+//! Client address resolution behind trusted proxies
+//!
+//! Forwarding headers are only honoured when the TCP peer is a trusted
+//! proxy. The forwarding chain is then walked from the right, skipping
+//! trusted hops, and the first untrusted hop is taken as the client.
+//! Finally, CDN presets may replace that address with the provider's client
+//! IP header when it belongs to the provider's ranges.
+
+use crate::utils::cdn::CdnRanges;
+use crate::utils::network::Cidr;
+use axum::http::HeaderMap;
+use std::net::{IpAddr, Ipv4Addr};
+
+/// Networks whose forwarding headers are trusted
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    networks: Vec<Cidr>,
+    cdn: Vec<CdnRanges>,
+}
+
+impl TrustedProxies {
+    /// Create a trusted proxy list from CIDR networks
+    pub fn new(networks: Vec<Cidr>) -> Self {
+        Self {
+            networks,
+            cdn: Vec::new(),
+        }
+    }
+
+    /// Also trust CDN client IP headers from the given provider ranges
+    pub fn with_cdn_presets(mut self, cdn: Vec<CdnRanges>) -> Self {
+        self.cdn = cdn;
+        self
+    }
+
+    /// Check whether an address belongs to a trusted proxy
+    pub fn is_trusted(&self, ip: &IpAddr) -> bool {
+        self.networks.iter().any(|net| net.contains(ip))
+    }
+}
+
+/// Where the client address was taken from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpSource {
+    /// The TCP peer
+    Direct,
+    /// The PROXY protocol header
+    ProxyProtocol,
+    /// The RFC 7239 `Forwarded` header
+    Forwarded,
+    /// The `X-Forwarded-For` header
+    XForwardedFor,
+    /// The `X-Real-IP` header
+    XRealIp,
+    /// A CDN preset header
+    Cdn(&'static str),
+}
+
+impl IpSource {
+    /// Name reported in responses
+    pub fn name(&self) -> &'static str {
+        match self {
+            IpSource::Direct => "direct",
+            IpSource::ProxyProtocol => "proxy-protocol",
+            IpSource::Forwarded => "forwarded",
+            IpSource::XForwardedFor => "x-forwarded-for",
+            IpSource::XRealIp => "x-real-ip",
+            IpSource::Cdn(name) => name,
+        }
+    }
+}
+
+/// Resolved client address for a request
+#[derive(Clone, Debug)]
+pub struct ClientIp {
+    /// Client address after walking the trusted proxy chain
+    pub ip: IpAddr,
+
+    /// Address of the directly connected peer
+    pub peer: IpAddr,
+
+    /// Where the peer address was taken from (direct or proxy-protocol)
+    pub peer_source: IpSource,
+
+    /// Protocol the client used, when reported by a trusted proxy
+    pub proto: Option<String>,
+
+    /// Where the client address was taken from
+    pub source: IpSource,
+}
+
+/// A single hop parsed from a forwarding header
+struct Hop {
+    node: String,
+    ip: Option<IpAddr>,
+    proto: Option<String>,
+    source: IpSource,
+}
+
+/// Maximum number of hops reported by [`chain`]
+const MAX_CHAIN_HOPS: usize = 16;
+
+/// A hop in the forwarding chain
+#[derive(Clone, Debug)]
+pub struct ChainHop {
+    /// Node identifier as it appeared in the header
+    pub node: String,
+
+    /// Parsed address, if the node is an IP address
+    pub ip: Option<IpAddr>,
+
+    /// Whether the hop is a trusted proxy or CDN edge
+    pub trusted: bool,
+
+    /// Where the hop was taken from
+    pub source: IpSource,
+}
+
+/// Resolve the client address for a request
+///
+/// Headers are consulted in order of preference: `Forwarded` (RFC 7239),
+/// `X-Forwarded-For`, then `X-Real-IP`. Only the first one present is used.
+pub fn resolve(headers: &HeaderMap, peer: IpAddr, trusted: &TrustedProxies) -> ClientIp {
+    let mut client = ClientIp {
+        ip: peer,
+        peer,
+        peer_source: IpSource::Direct,
+        proto: None,
+        source: IpSource::Direct,
+    };
+
+    if trusted.is_trusted(&peer) {
+        walk_chain(&mut client, headers, trusted);
+    }
+
+    // The address we stopped at may be a CDN edge with its own client header
+    if let Some(ranges) = trusted.cdn.iter().find(|r| r.contains(&client.ip))
+        && let Some(ip) = headers
+            .get(ranges.preset.header())
+            .and_then(|v| v.to_str().ok())
+            .and_then(parse_node)
+    {
+        client.ip = ip;
+        client.source = IpSource::Cdn(ranges.preset.name());
+    }
+
+    client
+}
+
+/// List every hop from the client to the connected peer, in order
+///
+/// Unlike [`resolve`], this reports all hops found in the headers whether or
+/// not they are trusted. Only the hops closest to the server are kept.
+pub fn chain(headers: &HeaderMap, client: &ClientIp, trusted: &TrustedProxies) -> Vec<ChainHop> {
+    let is_trusted =
+        |ip: &IpAddr| trusted.is_trusted(ip) || trusted.cdn.iter().any(|r| r.contains(ip));
+    let mut hops = Vec::new();
+
+    if let IpSource::Cdn(_) = client.source {
+        hops.push(ChainHop {
+            node: client.ip.to_string(),
+            ip: Some(client.ip),
+            trusted: is_trusted(&client.ip),
+            source: client.source,
+        });
+    }
+
+    hops.extend(header_hops(headers).into_iter().map(|hop| ChainHop {
+        trusted: hop.ip.as_ref().is_some_and(is_trusted),
+        node: hop.node,
+        ip: hop.ip,
+        source: hop.source,
+    }));
+
+    hops.push(ChainHop {
+        node: client.peer.to_string(),
+        ip: Some(client.peer),
+        trusted: is_trusted(&client.peer),
+        source: client.peer_source,
+    });
+
+    let excess = hops.len().saturating_sub(MAX_CHAIN_HOPS);
+    hops.split_off(excess)
+}
+
+/// Hops from the preferred forwarding header, leftmost first
+fn header_hops(headers: &HeaderMap) -> Vec<Hop> {
+    forwarded_hops(headers)
+        .or_else(|| x_forwarded_for_hops(headers))
+        .or_else(|| x_real_ip_hops(headers))
+        .unwrap_or_default()
+}
+
+/// Walk the forwarding chain from the right, skipping trusted hops
+fn walk_chain(client: &mut ClientIp, headers: &HeaderMap, trusted: &TrustedProxies) {
+    let hops = header_hops(headers);
+
+    for hop in hops.iter().rev() {
+        let Some(ip) = hop.ip else {
+            // Obfuscated or unknown node: the hop to its right is the
+            // closest address we can vouch for
+            break;
+        };
+
+        client.ip = ip;
+        client.proto = hop.proto.clone();
+        client.source = hop.source;
+
+        if !trusted.is_trusted(&ip) {
+            break;
+        }
+    }
+}
+
+/// Collect all values of a header, joined as one comma-separated list
+fn header_values(headers: &HeaderMap, name: &str) -> Option<String> {
+    let values: Vec<&str> = headers
+        .get_all(name)
+        .iter()
+        .filter_map(|v| v.to_str().ok())
+        .collect();
+
+    if values.is_empty() {
+        None
+    } else {
+        Some(values.join(","))
+    }
+}
+
+/// Parse `Forwarded: for=...;proto=...` elements
+fn forwarded_hops(headers: &HeaderMap) -> Option<Vec<Hop>> {
+    let value = header_values(headers, "forwarded")?;
+
+    let hops = value
+        .split(',')
+        .map(|element| {
+            let mut hop = Hop {
+                node: "unknown".to_string(),
+                ip: None,
+                proto: None,
+                source: IpSource::Forwarded,
+            };
+
+            for pair in element.split(';') {
+                let Some((key, val)) = pair.split_once('=') else {
+                    continue;
+                };
+                let val = val.trim().trim_matches('"');
+
+                match key.trim().to_ascii_lowercase().as_str() {
+                    "for" => {
+                        hop.node = val.to_string();
+                        hop.ip = parse_node(val);
+                    }
+                    "proto" => hop.proto = Some(val.to_ascii_lowercase()),
+                    _ => {}
+                }
+            }
+
+            hop
+        })
+        .collect();
+
+    Some(hops)
+}
+
+/// Parse `X-Forwarded-For`, taking the protocol from `X-Forwarded-Proto`
+fn x_forwarded_for_hops(headers: &HeaderMap) -> Option<Vec<Hop>> {
+    let value = header_values(headers, "x-forwarded-for")?;
+    let proto = forwarded_proto(headers);
+
+    Some(
+        value
+            .split(',')
+            .map(|node| Hop {
+                node: node.trim().to_string(),
+                ip: parse_node(node),
+                proto: proto.clone(),
+                source: IpSource::XForwardedFor,
+            })
+            .collect(),
+    )
+}
+
+/// Parse `X-Real-IP` as a single-hop chain
+fn x_real_ip_hops(headers: &HeaderMap) -> Option<Vec<Hop>> {
+    let value = headers.get("x-real-ip")?.to_str().ok()?;
+
+    Some(vec![Hop {
+        node: value.trim().to_string(),
+        ip: parse_node(value),
+        proto: forwarded_proto(headers),
+        source: IpSource::XRealIp,
+    }])
+}
+
+/// First `X-Forwarded-Proto` value, as set by the outermost proxy
+fn forwarded_proto(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("x-forwarded-proto")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.split(',').next())
+        .map(|v| v.trim().to_ascii_lowercase())
+        .filter(|v| !v.is_empty())
+}
+
+/// Parse a node identifier such as `192.0.2.1`, `192.0.2.1:8080`,
+/// `2001:db8::1` or `[2001:db8::1]:8080`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    let node = node.trim().trim_matches('"');
+
+    if let Some(rest) = node.strip_prefix('[') {
+        return rest.split(']').next()?.parse().ok();
+    }
+
+    if let Ok(ip) = node.parse() {
+        return Some(ip);
+    }
+
+    node.rsplit_once(':')
+        .and_then(|(host, _)| host.parse::<Ipv4Addr>().ok())
+        .map(IpAddr::V4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn trusted(list: &str) -> TrustedProxies {
+        TrustedProxies::new(crate::utils::network::parse_cidr_list(list).unwrap())
+    }
+
+    fn headers(pairs: &[(&'static str, &'static str)]) -> HeaderMap {
+        let mut map = HeaderMap::new();
+        for (name, value) in pairs {
+            map.append(*name, HeaderValue::from_static(value));
+        }
+        map
+    }
+
+    #[test]
+    fn test_untrusted_peer_ignores_headers() {
+        let h = headers(&[("x-forwarded-for", "1.2.3.4")]);
+        let client = resolve(&h, "203.0.113.7".parse().unwrap(), &trusted("10.0.0.0/8"));
+        assert_eq!(client.ip.to_string(), "203.0.113.7");
+        assert_eq!(client.source, IpSource::Direct);
+    }
+
+    #[test]
+    fn test_x_forwarded_for_skips_trusted_hops() {
+        let h = headers(&[("x-forwarded-for", "6.6.6.6, 198.51.100.1, 10.0.0.2")]);
+        let client = resolve(&h, "10.0.0.1".parse().unwrap(), &trusted("10.0.0.0/8"));
+        assert_eq!(client.ip.to_string(), "198.51.100.1");
+        assert_eq!(client.source, IpSource::XForwardedFor);
+    }
+
+    #[test]
+    fn test_all_trusted_takes_leftmost() {
+        let h = headers(&[("x-forwarded-for", "10.0.0.3, 10.0.0.2")]);
+        let client = resolve(&h, "10.0.0.1".parse().unwrap(), &trusted("10.0.0.0/8"));
+        assert_eq!(client.ip.to_string(), "10.0.0.3");
+    }
+
+    #[test]
+    fn test_forwarded_header() {
+        let h = headers(&[(
+            "forwarded",
+            "for=192.0.2.60;proto=http, for=\"[2001:db8:cafe::17]:4711\";proto=https",
+        )]);
+        let client = resolve(&h, "127.0.0.1".parse().unwrap(), &trusted("127.0.0.1"));
+        assert_eq!(client.ip.to_string(), "2001:db8:cafe::17");
+        assert_eq!(client.proto.as_deref(), Some("https"));
+    }
+
+    #[test]
+    fn test_forwarded_preferred_over_x_forwarded_for() {
+        let h = headers(&[
+            ("forwarded", "for=192.0.2.60"),
+            ("x-forwarded-for", "198.51.100.1"),
+        ]);
+        let client = resolve(&h, "127.0.0.1".parse().unwrap(), &trusted("127.0.0.1"));
+        assert_eq!(client.ip.to_string(), "192.0.2.60");
+    }
+
+    #[test]
+    fn test_unknown_node_stops_walk() {
+        let h = headers(&[("forwarded", "for=unknown, for=10.0.0.2")]);
+        let client = resolve(&h, "10.0.0.1".parse().unwrap(), &trusted("10.0.0.0/8"));
+        assert_eq!(client.ip.to_string(), "10.0.0.2");
+    }
+
+    #[test]
+    fn test_x_real_ip_with_proto() {
+        let h = headers(&[
+            ("x-real-ip", "192.0.2.9:5555"),
+            ("x-forwarded-proto", "HTTPS"),
+        ]);
+        let client = resolve(
+            &h,
+            "::ffff:127.0.0.1".parse().unwrap(),
+            &trusted("127.0.0.1"),
+        );
+        assert_eq!(client.ip.to_string(), "192.0.2.9");
+        assert_eq!(client.proto.as_deref(), Some("https"));
+    }
+
+    #[test]
+    fn test_cdn_preset_behind_trusted_proxy() {
+        use crate::utils::cdn::{CdnPreset, CdnRanges};
+
+        let trusted = trusted("127.0.0.1").with_cdn_presets(vec![CdnRanges {
+            preset: CdnPreset::Cloudflare,
+            networks: vec!["173.245.48.0/20".parse().unwrap()],
+        }]);
+        let h = headers(&[
+            ("x-forwarded-for", "198.51.100.9, 173.245.48.1"),
+            ("cf-connecting-ip", "198.51.100.9"),
+        ]);
+
+        let client = resolve(&h, "127.0.0.1".parse().unwrap(), &trusted);
+        assert_eq!(client.ip.to_string(), "198.51.100.9");
+        assert_eq!(client.source, IpSource::Cdn("cloudflare"));
+
+        // The preset header is ignored from outside the provider's ranges
+        let client = resolve(&h, "203.0.113.1".parse().unwrap(), &trusted);
+        assert_eq!(client.ip.to_string(), "203.0.113.1");
+    }
+
+    #[test]
+    fn test_chain_lists_all_hops() {
+        let trusted = trusted("10.0.0.0/8");
+        let h = headers(&[("x-forwarded-for", "unknown, 198.51.100.1, 10.0.0.2")]);
+        let client = resolve(&h, "10.0.0.1".parse().unwrap(), &trusted);
+
+        let hops = chain(&h, &client, &trusted);
+        let nodes: Vec<&str> = hops.iter().map(|hop| hop.node.as_str()).collect();
+        assert_eq!(nodes, ["unknown", "198.51.100.1", "10.0.0.2", "10.0.0.1"]);
+        assert_eq!(
+            hops.iter().map(|hop| hop.trusted).collect::<Vec<_>>(),
+            [false, false, true, true]
+        );
+        assert_eq!(hops[3].source, IpSource::Direct);
+    }
+}
@@ -1,37 +1,802 @@
 //! DNS lookup utilities
+//!
+//! Reverse lookups go through an async stub resolver that queries the
+//! configured upstream nameservers directly, so a slow nameserver never ties
+//! up a blocking thread. Without configured upstreams the nameservers and
+//! options from `/etc/resolv.conf` are used.
+//!
+//! Answers carry the TTL the upstream gave them (the lowest record TTL, or
+//! the SOA minimum for an empty answer) so the cache can honour it.
+//!
+//! Each lookup has a deadline, and a circuit breaker skips lookups while the
+//! upstreams keep timing out or cannot be reached, so an outage costs
+//! requests no more than the deadline before rDNS is simply left out.
+//!
+//! With a trust anchor file configured, answers are DNSSEC-validated here,
+//! from the signatures up to the trust anchor, rather than by trusting the
+//! upstream's AD bit. Bogus answers are dropped like a validating resolver
+//! would.
+//!
+//! The same resolver and cache answer `/dns` queries for the other common
+//! record types, presented the way zone files write them.
+//!
+//! A PTR record is only as trustworthy as whoever controls the reverse zone,
+//! so names can be forward-confirmed: a name counts as verified when its
+//! A/AAAA records include the original address.
 
-use crate::utils::cache::DnsCache;
-use std::net::IpAddr;
+use crate::utils::cache::{CachedAnswer, DnsCache};
+use crate::utils::circuit_breaker::{BreakerStatus, CircuitBreaker};
+use hickory_resolver::config::{NameServerConfig, ResolverConfig, ResolverOpts};
+use hickory_resolver::net::runtime::TokioRuntimeProvider;
+use hickory_resolver::net::{DnsError, NetError};
+use hickory_resolver::proto::dnssec::{Proof, TrustAnchors};
+use hickory_resolver::proto::rr::{Name, RData, Record, RecordType};
+use hickory_resolver::{Resolver, TokioResolver};
+use serde::{Serialize, Serializer};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::task::JoinSet;
 
-/// Perform reverse DNS lookup for an IP address
+/// Default DNS port
+const DNS_PORT: u16 = 53;
+
+/// Most PTR names forward-confirmed per address
+const MAX_VERIFIED_NAMES: usize = 8;
+
+/// Record types `/dns` can be asked for
+pub const QUERY_TYPES: [RecordType; 10] = [
+    RecordType::A,
+    RecordType::AAAA,
+    RecordType::MX,
+    RecordType::TXT,
+    RecordType::NS,
+    RecordType::SOA,
+    RecordType::CAA,
+    RecordType::SRV,
+    RecordType::PTR,
+    RecordType::CNAME,
+];
+
+/// Outcome of forward-confirmed reverse DNS
 ///
-/// Returns the hostname if lookup succeeds, None otherwise.
-/// This operation is performed in a blocking task to avoid blocking
-/// the async runtime.
-pub async fn reverse_lookup(ip_str: &str) -> Option<String> {
-    let ip: IpAddr = ip_str.parse().ok()?;
+/// Serialized as `true`, `false` or `"unknown"`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Verification {
+    /// A PTR name resolves back to the address
+    Verified,
 
-    tokio::task::spawn_blocking(move || dns_lookup::lookup_addr(&ip).ok())
-        .await
-        .ok()
-        .flatten()
+    /// No PTR name resolves back to the address
+    Failed,
+
+    /// There is no PTR name, or a lookup failed before a match was found
+    Unknown,
+}
+
+impl Verification {
+    /// Plain text form
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Verification::Verified => "true",
+            Verification::Failed => "false",
+            Verification::Unknown => "unknown",
+        }
+    }
+}
+
+impl Serialize for Verification {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Verification::Verified => serializer.serialize_bool(true),
+            Verification::Failed => serializer.serialize_bool(false),
+            Verification::Unknown => serializer.serialize_str("unknown"),
+        }
+    }
+}
+
+/// Why a lookup gave no answer
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DnsFailure {
+    /// No answer within the lookup deadline
+    Timeout,
+
+    /// The upstream answered with an error (e.g. SERVFAIL) or could not be
+    /// reached
+    Error,
+
+    /// Not attempted while the circuit breaker is open
+    CircuitOpen,
+
+    /// The answer failed DNSSEC validation
+    Bogus,
+}
+
+/// DNSSEC validation outcome of an answer
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DnssecStatus {
+    /// Signed, with a chain of trust up to a trust anchor
+    Secure,
+
+    /// Proven to be in an unsigned zone
+    Insecure,
+
+    /// Signatures or proofs that should be there are missing or wrong
+    Bogus,
+
+    /// Not validated here, e.g. because the lookup failed or the answer
+    /// came from a gossip peer, the shared cache or a snapshot
+    Indeterminate,
+}
+
+impl DnssecStatus {
+    /// Status of a set of records: the weakest of their proofs
+    ///
+    /// RRSIGs are skipped, as only the one used carries its RRset's proof.
+    fn of_records<'a>(records: impl IntoIterator<Item = &'a Record>) -> Self {
+        let mut status = None;
+        for record in records {
+            if record.record_type() == RecordType::RRSIG {
+                continue;
+            }
+            let proof = match record.proof {
+                Proof::Secure => DnssecStatus::Secure,
+                Proof::Insecure => DnssecStatus::Insecure,
+                Proof::Bogus => return DnssecStatus::Bogus,
+                Proof::Indeterminate => DnssecStatus::Indeterminate,
+            };
+            status = Some(match (status, proof) {
+                (Some(DnssecStatus::Indeterminate), _) | (_, DnssecStatus::Indeterminate) => {
+                    DnssecStatus::Indeterminate
+                }
+                (Some(DnssecStatus::Insecure), _) => DnssecStatus::Insecure,
+                _ => proof,
+            });
+        }
+        status.unwrap_or(DnssecStatus::Indeterminate)
+    }
+
+    /// Plain text form
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DnssecStatus::Secure => "secure",
+            DnssecStatus::Insecure => "insecure",
+            DnssecStatus::Bogus => "bogus",
+            DnssecStatus::Indeterminate => "indeterminate",
+        }
+    }
+}
+
+/// Outcome of a lookup, as reported in `rDNS-Status` and by `/dns`
+#[derive(Serialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum LookupStatus {
+    /// Records were found
+    Ok,
+
+    /// The name has no records of the type asked for
+    #[serde(rename = "nxdomain")]
+    NxDomain,
+
+    /// The lookup timed out
+    Timeout,
+
+    /// The lookup failed
+    Error,
+
+    /// The lookup was skipped while the resolver is unhealthy
+    CircuitOpen,
+}
+
+impl LookupStatus {
+    /// Status of a cached lookup
+    pub fn of(answer: &CachedAnswer) -> Self {
+        match (&answer.value, answer.failure) {
+            (Some(names), _) if !names.is_empty() => LookupStatus::Ok,
+            (Some(_), _) => LookupStatus::NxDomain,
+            (None, Some(DnsFailure::Timeout)) => LookupStatus::Timeout,
+            (None, Some(DnsFailure::CircuitOpen)) => LookupStatus::CircuitOpen,
+            (None, _) => LookupStatus::Error,
+        }
+    }
+
+    /// Plain text form
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LookupStatus::Ok => "ok",
+            LookupStatus::NxDomain => "nxdomain",
+            LookupStatus::Timeout => "timeout",
+            LookupStatus::Error => "error",
+            LookupStatus::CircuitOpen => "circuit_open",
+        }
+    }
+}
+
+/// Records found by a lookup
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DnsAnswer {
+    /// Hostnames or addresses, empty if there are none
+    pub records: Vec<String>,
+
+    /// How long the upstream allows the answer to be cached, if it said
+    pub ttl: Option<Duration>,
+
+    /// DNSSEC validation outcome, if answers are validated
+    pub dnssec: Option<DnssecStatus>,
+}
+
+/// Transport used to reach upstream nameservers
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DnsProtocol {
+    /// UDP, retrying over TCP when a response is truncated or fails
+    #[default]
+    Udp,
+
+    /// TCP only
+    Tcp,
+}
+
+impl FromStr for DnsProtocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "udp" => Ok(DnsProtocol::Udp),
+            "tcp" => Ok(DnsProtocol::Tcp),
+            other => Err(format!("unknown DNS protocol: {}", other)),
+        }
+    }
+}
+
+/// Resolver settings
+///
+/// Options left unset keep the value from `/etc/resolv.conf`, or the
+/// resolver's default when upstreams are configured.
+#[derive(Clone, Debug, Default)]
+pub struct DnsResolverConfig {
+    /// Upstream nameservers (from `/etc/resolv.conf` if empty)
+    pub servers: Vec<SocketAddr>,
+
+    /// Transport used to reach the upstreams
+    pub protocol: DnsProtocol,
+
+    /// Time to wait for each query
+    pub timeout: Option<Duration>,
+
+    /// Attempts per query before giving up
+    pub attempts: Option<usize>,
+
+    /// Advertise EDNS(0) in queries
+    pub edns: Option<bool>,
+
+    /// Longest a lookup may take, across all attempts and upstreams
+    pub deadline: Option<Duration>,
+
+    /// Consecutive timeouts or unreachable upstreams that open the circuit
+    /// breaker (0 disables it)
+    pub breaker_failures: u32,
+
+    /// How long the circuit breaker stays open before a lookup is retried
+    pub breaker_cooldown: Duration,
+
+    /// DNSKEY records to validate answers against (no validation if unset)
+    pub trust_anchor: Option<PathBuf>,
+}
+
+/// Async stub resolver for reverse lookups
+pub struct DnsResolver {
+    resolver: TokioResolver,
+    deadline: Option<Duration>,
+    breaker: CircuitBreaker,
+    validating: bool,
+}
+
+impl DnsResolver {
+    /// Build a resolver from its settings
+    pub fn new(config: &DnsResolverConfig) -> Result<Self, String> {
+        let (resolver_config, mut options) = if config.servers.is_empty() {
+            hickory_resolver::system_conf::read_system_conf()
+                .map_err(|e| format!("failed to read /etc/resolv.conf: {}", e))?
+        } else {
+            let servers = config
+                .servers
+                .iter()
+                .map(|server| name_server(*server, config.protocol))
+                .collect();
+            (
+                ResolverConfig::from_name_servers(servers),
+                ResolverOpts::default(),
+            )
+        };
+
+        if let Some(timeout) = config.timeout {
+            options.timeout = timeout;
+        }
+        if let Some(attempts) = config.attempts {
+            options.attempts = attempts;
+        }
+        if let Some(edns) = config.edns {
+            options.edns0 = edns;
+        }
+        options.try_tcp_on_error = config.protocol == DnsProtocol::Udp;
+        // Results are cached by `DnsCache`
+        options.cache_size = 0;
+
+        let mut builder =
+            Resolver::builder_with_config(resolver_config, TokioRuntimeProvider::default())
+                .with_options(options);
+        if let Some(path) = &config.trust_anchor {
+            let anchors = TrustAnchors::from_file(path).map_err(|e| {
+                format!("failed to read trust anchor file {}: {}", path.display(), e)
+            })?;
+            if anchors.is_empty() {
+                return Err(format!("no DNSKEY in trust anchor file {}", path.display()));
+            }
+            builder = builder.with_trust_anchor(Arc::new(anchors));
+        }
+        let resolver = builder
+            .build()
+            .map_err(|e| format!("failed to build DNS resolver: {}", e))?;
+
+        Ok(Self {
+            resolver,
+            deadline: config.deadline,
+            breaker: CircuitBreaker::new(config.breaker_failures, config.breaker_cooldown),
+            validating: config.trust_anchor.is_some(),
+        })
+    }
+
+    /// State of the circuit breaker
+    pub fn breaker_status(&self) -> BreakerStatus {
+        self.breaker.status()
+    }
+
+    /// DNSSEC status to report for a cached answer, if answers are validated
+    ///
+    /// Answers cached without a status were not validated here and are
+    /// indeterminate.
+    pub fn dnssec_status(&self, answer: &CachedAnswer) -> Option<DnssecStatus> {
+        if !self.validating {
+            return None;
+        }
+        Some(match answer.failure {
+            Some(DnsFailure::Bogus) => DnssecStatus::Bogus,
+            _ => answer.dnssec.unwrap_or(DnssecStatus::Indeterminate),
+        })
+    }
+
+    /// Perform reverse DNS lookup for an IP address
+    ///
+    /// Returns the PTR hostnames without their trailing dot (empty if there
+    /// are none), or why the lookup failed.
+    pub async fn reverse_lookup(&self, ip: IpAddr) -> Result<DnsAnswer, DnsFailure> {
+        self.lookup(Name::from(ip), RecordType::PTR).await
+    }
+
+    /// Look up records of a hostname
+    ///
+    /// `record_type` is one of `QUERY_TYPES`, e.g. `A` or `AAAA` for its
+    /// addresses. Returns the records in presentation form (empty if there
+    /// are none), or why the lookup failed.
+    pub async fn forward_lookup(
+        &self,
+        host: &str,
+        record_type: RecordType,
+    ) -> Result<DnsAnswer, DnsFailure> {
+        // Fully qualified, so search domains are never appended
+        let Ok(name) = Name::from_ascii(format!("{}.", host)) else {
+            return Ok(DnsAnswer::default());
+        };
+        self.lookup(name, record_type).await
+    }
+
+    /// Query records of one type, treating "no records" as an empty answer
+    ///
+    /// Returns the records in presentation form with the lowest of their
+    /// TTLs, or for an empty answer the negative TTL from the SOA record if
+    /// there is one.
+    ///
+    /// Timeouts and unreachable upstreams count towards opening the circuit
+    /// breaker; any answer from an upstream, even an error, shows it is up.
+    ///
+    /// When validating, the answer carries the weakest proof among its
+    /// records (including any CNAMEs followed), or among the denial records
+    /// for an empty answer.
+    async fn lookup(&self, name: Name, record_type: RecordType) -> Result<DnsAnswer, DnsFailure> {
+        if !self.breaker.allow() {
+            return Err(DnsFailure::CircuitOpen);
+        }
+
+        let lookup = self.resolver.lookup(name.clone(), record_type);
+        let result = match self.deadline {
+            Some(deadline) => tokio::time::timeout(deadline, lookup)
+                .await
+                .unwrap_or(Err(NetError::Timeout)),
+            None => lookup.await,
+        };
+
+        match &result {
+            Ok(_) | Err(NetError::Dns(_)) => self.breaker.record_success(),
+            Err(_) => self.breaker.record_failure(),
+        }
+
+        match result {
+            Ok(lookup) => {
+                let records: Vec<_> = lookup
+                    .answers()
+                    .iter()
+                    .filter(|record| record.record_type() == record_type)
+                    .collect();
+                let ttl = records.iter().map(|record| record.ttl).min();
+                Ok(DnsAnswer {
+                    records: records
+                        .into_iter()
+                        .filter_map(|record| present(&record.data))
+                        .collect(),
+                    ttl: ttl.map(secs),
+                    dnssec: self.validated(lookup.answers()),
+                })
+            }
+            Err(NetError::Dns(DnsError::NoRecordsFound(no_records))) => Ok(DnsAnswer {
+                records: Vec::new(),
+                ttl: no_records.negative_ttl.map(secs),
+                dnssec: self.validated(no_records.authorities.as_deref().unwrap_or_default()),
+            }),
+            // No records, without a secure proof that there are none
+            Err(NetError::Dns(DnsError::Nsec { proof, .. })) if proof != Proof::Bogus => {
+                Ok(DnsAnswer {
+                    records: Vec::new(),
+                    ttl: None,
+                    dnssec: Some(match proof {
+                        Proof::Insecure => DnssecStatus::Insecure,
+                        _ => DnssecStatus::Indeterminate,
+                    }),
+                })
+            }
+            Err(e) => {
+                tracing::debug!(%name, ?record_type, error = %e, "DNS lookup failed");
+                match e {
+                    NetError::Timeout => Err(DnsFailure::Timeout),
+                    NetError::Dns(DnsError::DnssecBogus | DnsError::Nsec { .. }) => {
+                        Err(DnsFailure::Bogus)
+                    }
+                    _ => Err(DnsFailure::Error),
+                }
+            }
+        }
+    }
+
+    /// DNSSEC status of the records in an answer, if answers are validated
+    fn validated(&self, records: &[Record]) -> Option<DnssecStatus> {
+        self.validating.then(|| DnssecStatus::of_records(records))
+    }
+}
+
+/// Record data in presentation form, as in a zone file
+///
+/// Names lose their trailing dot and TXT strings are joined, so A/AAAA give
+/// an address, PTR/NS/CNAME a hostname, MX `preference exchange`, SRV
+/// `priority weight port target`, SOA `mname rname serial refresh retry
+/// expire minimum` and CAA `flags tag "value"`.
+fn present(rdata: &RData) -> Option<String> {
+    let text = match rdata {
+        RData::A(a) => a.0.to_string(),
+        RData::AAAA(aaaa) => aaaa.0.to_string(),
+        RData::PTR(ptr) => host(&ptr.0),
+        RData::NS(ns) => host(&ns.0),
+        RData::CNAME(cname) => host(&cname.0),
+        RData::MX(mx) => format!("{} {}", mx.preference, host(&mx.exchange)),
+        RData::SRV(srv) => format!(
+            "{} {} {} {}",
+            srv.priority,
+            srv.weight,
+            srv.port,
+            host(&srv.target)
+        ),
+        RData::SOA(soa) => format!(
+            "{} {} {} {} {} {} {}",
+            host(&soa.mname),
+            host(&soa.rname),
+            soa.serial,
+            soa.refresh,
+            soa.retry,
+            soa.expire,
+            soa.minimum
+        ),
+        RData::CAA(caa) => format!(
+            "{} {} \"{}\"",
+            caa.flags(),
+            caa.tag,
+            String::from_utf8_lossy(&caa.value)
+        ),
+        RData::TXT(txt) => txt
+            .txt_data
+            .iter()
+            .map(|part| String::from_utf8_lossy(part))
+            .collect(),
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// A name without its trailing dot (`.` for the root, e.g. a null MX)
+fn host(name: &Name) -> String {
+    let name = name.to_ascii();
+    match name.trim_end_matches('.') {
+        "" => ".".to_string(),
+        trimmed => trimmed.to_string(),
+    }
+}
+
+/// Parse a record type `/dns` can be asked for, ignoring case
+pub fn parse_record_type(record_type: &str) -> Option<RecordType> {
+    let record_type = RecordType::from_str(&record_type.trim().to_ascii_uppercase()).ok()?;
+    QUERY_TYPES.contains(&record_type).then_some(record_type)
+}
+
+/// A TTL in seconds as a Duration
+fn secs(ttl: u32) -> Duration {
+    Duration::from_secs(ttl.into())
+}
+
+/// Nameserver reached over the given transport
+fn name_server(addr: SocketAddr, protocol: DnsProtocol) -> NameServerConfig {
+    let mut server = match protocol {
+        DnsProtocol::Udp => NameServerConfig::udp_and_tcp(addr.ip()),
+        DnsProtocol::Tcp => NameServerConfig::tcp(addr.ip()),
+    };
+    for connection in &mut server.connections {
+        connection.port = addr.port();
+    }
+    server
+}
+
+/// Parse a comma-separated list of nameservers
+///
+/// Each entry is an IP address, optionally with a port (`192.0.2.1:5353`,
+/// `[2001:db8::1]:5353`); the port defaults to 53.
+pub fn parse_server_list(list: &str) -> Result<Vec<SocketAddr>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(|item| {
+            item.parse::<SocketAddr>()
+                .or_else(|_| {
+                    item.parse::<IpAddr>()
+                        .map(|ip| SocketAddr::new(ip, DNS_PORT))
+                })
+                .map_err(|_| format!("invalid DNS server: {}", item))
+        })
+        .collect()
 }
 
 /// Perform reverse DNS lookup with caching
 ///
-/// Checks cache first, performs lookup if not cached, and stores result
-pub async fn reverse_lookup_cached(ip_str: &str, cache: Arc<DnsCache>) -> Option<String> {
-    // Check cache first
-    if let Some(cached) = cache.get(ip_str).await {
-        return cached;
+/// Checks cache first, performs lookup if not cached, and stores result.
+/// Concurrent lookups of the same address share one query.
+pub async fn reverse_lookup_cached(
+    ip: IpAddr,
+    resolver: &Arc<DnsResolver>,
+    cache: &DnsCache,
+) -> CachedAnswer {
+    let resolver = resolver.clone();
+
+    cache
+        .get_or_lookup(&ip.to_string(), move || async move {
+            resolver.reverse_lookup(ip).await
+        })
+        .await
+}
+
+/// Look up records of a hostname with caching
+///
+/// Answers are cached under `TYPE host` (e.g. `MX example.com`), so `/dns`
+/// queries and forward confirmation share them.
+pub async fn query_cached(
+    host: &str,
+    record_type: RecordType,
+    resolver: &Arc<DnsResolver>,
+    cache: &DnsCache,
+) -> CachedAnswer {
+    let key = format!("{} {}", record_type, host.to_lowercase());
+    let (host, resolver) = (host.to_string(), resolver.clone());
+
+    cache
+        .get_or_lookup(&key, move || async move {
+            resolver.forward_lookup(&host, record_type).await
+        })
+        .await
+}
+
+/// Look up the addresses of a hostname with caching
+pub async fn forward_lookup_cached(
+    host: &str,
+    record_type: RecordType,
+    resolver: &Arc<DnsResolver>,
+    cache: &DnsCache,
+) -> Option<Vec<String>> {
+    query_cached(host, record_type, resolver, cache).await.value
+}
+
+/// Forward-confirm reverse DNS (FCrDNS)
+///
+/// Resolves each PTR name of `ip` (up to `MAX_VERIFIED_NAMES`, concurrently)
+/// and checks whether any of them points back at `ip`.
+pub async fn verify(
+    ip: IpAddr,
+    names: Option<&[String]>,
+    resolver: &Arc<DnsResolver>,
+    cache: &Arc<DnsCache>,
+) -> Verification {
+    let names = match names {
+        Some(names) if !names.is_empty() => names,
+        _ => return Verification::Unknown,
+    };
+    let record_type = match ip {
+        IpAddr::V4(_) => RecordType::A,
+        IpAddr::V6(_) => RecordType::AAAA,
+    };
+
+    let mut lookups = JoinSet::new();
+    for name in names.iter().take(MAX_VERIFIED_NAMES) {
+        let (name, resolver, cache) = (name.clone(), resolver.clone(), cache.clone());
+        lookups.spawn(
+            async move { forward_lookup_cached(&name, record_type, &resolver, &cache).await },
+        );
+    }
+
+    let mut complete = true;
+    while let Some(result) = lookups.join_next().await {
+        match result.ok().flatten() {
+            Some(addrs) if addrs.iter().any(|addr| addr.parse() == Ok(ip)) => {
+                return Verification::Verified;
+            }
+            Some(_) => {}
+            None => complete = false,
+        }
+    }
+
+    if complete {
+        Verification::Failed
+    } else {
+        Verification::Unknown
     }
+}
 
-    // Perform lookup
-    let result = reverse_lookup(ip_str).await;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hickory_resolver::config::ProtocolConfig;
+    use std::net::Ipv4Addr;
 
-    // Store in cache
-    cache.insert(ip_str.to_string(), result.clone()).await;
+    #[test]
+    fn test_parse_server_list() {
+        let servers =
+            parse_server_list("192.0.2.1, 192.0.2.2:5353,[2001:db8::1]:54,2001:db8::2").unwrap();
+        assert_eq!(
+            servers,
+            vec![
+                "192.0.2.1:53".parse().unwrap(),
+                "192.0.2.2:5353".parse().unwrap(),
+                "[2001:db8::1]:54".parse().unwrap(),
+                "[2001:db8::2]:53".parse().unwrap(),
+            ]
+        );
 
-    result
+        assert!(parse_server_list("").unwrap().is_empty());
+        assert!(parse_server_list("dns.example").is_err());
+    }
+
+    #[test]
+    fn test_name_server_uses_port_and_protocol() {
+        let udp = name_server("192.0.2.1:5353".parse().unwrap(), DnsProtocol::Udp);
+        assert_eq!(udp.connections.len(), 2);
+        assert!(udp.connections.iter().all(|c| c.port == 5353));
+
+        let tcp = name_server("192.0.2.1:5353".parse().unwrap(), DnsProtocol::Tcp);
+        assert_eq!(tcp.connections.len(), 1);
+        assert!(matches!(tcp.connections[0].protocol, ProtocolConfig::Tcp));
+    }
+
+    #[test]
+    fn test_dns_protocol_from_str() {
+        assert_eq!("UDP".parse::<DnsProtocol>().unwrap(), DnsProtocol::Udp);
+        assert_eq!("tcp".parse::<DnsProtocol>().unwrap(), DnsProtocol::Tcp);
+        assert!("quic".parse::<DnsProtocol>().is_err());
+    }
+
+    #[test]
+    fn test_parse_record_type() {
+        assert_eq!(parse_record_type("mx"), Some(RecordType::MX));
+        assert_eq!(parse_record_type(" AAAA "), Some(RecordType::AAAA));
+        assert_eq!(parse_record_type("cname"), Some(RecordType::CNAME));
+        assert_eq!(parse_record_type("ANY"), None);
+        assert_eq!(parse_record_type("AXFR"), None);
+        assert_eq!(parse_record_type(""), None);
+    }
+
+    #[test]
+    fn test_present_record_data() {
+        let present_str =
+            |record_type, data| present(&RData::try_from_str(record_type, data).unwrap()).unwrap();
+
+        assert_eq!(
+            present_str(RecordType::MX, "10 mail.example.com."),
+            "10 mail.example.com"
+        );
+        assert_eq!(present_str(RecordType::MX, "0 ."), "0 .");
+        assert_eq!(
+            present_str(RecordType::SRV, "5 10 5060 sip.example.com."),
+            "5 10 5060 sip.example.com"
+        );
+        assert_eq!(
+            present_str(
+                RecordType::SOA,
+                "ns1.example.com. hostmaster.example.com. 2024010101 7200 3600 1209600 300"
+            ),
+            "ns1.example.com hostmaster.example.com 2024010101 7200 3600 1209600 300"
+        );
+        assert_eq!(
+            present_str(RecordType::CAA, "0 issue \"ca.example.net\""),
+            "0 issue \"ca.example.net\""
+        );
+        assert_eq!(
+            present_str(RecordType::TXT, "\"v=spf1 \" \"-all\""),
+            "v=spf1 -all"
+        );
+        assert_eq!(
+            present_str(RecordType::CNAME, "www.example.com."),
+            "www.example.com"
+        );
+    }
+
+    #[test]
+    fn test_dnssec_status_is_the_weakest_proof() {
+        let status = |proofs: &[Proof]| {
+            let records: Vec<Record> = proofs
+                .iter()
+                .map(|proof| {
+                    let mut record = Record::from_rdata(
+                        Name::root(),
+                        300,
+                        RData::A(Ipv4Addr::new(192, 0, 2, 1).into()),
+                    );
+                    record.proof = *proof;
+                    record
+                })
+                .collect();
+            DnssecStatus::of_records(&records)
+        };
+
+        assert_eq!(
+            status(&[Proof::Secure, Proof::Secure]),
+            DnssecStatus::Secure
+        );
+        assert_eq!(
+            status(&[Proof::Secure, Proof::Insecure]),
+            DnssecStatus::Insecure
+        );
+        assert_eq!(
+            status(&[Proof::Insecure, Proof::Indeterminate]),
+            DnssecStatus::Indeterminate
+        );
+        assert_eq!(
+            status(&[Proof::Indeterminate, Proof::Bogus, Proof::Secure]),
+            DnssecStatus::Bogus
+        );
+        assert_eq!(status(&[]), DnssecStatus::Indeterminate);
+    }
+
+    #[test]
+    fn test_verification_serializes_as_bool_or_unknown() {
+        let json = serde_json::to_string(&[
+            Verification::Verified,
+            Verification::Failed,
+            Verification::Unknown,
+        ])
+        .unwrap();
+        assert_eq!(json, r#"[true,false,"unknown"]"#);
+    }
 }
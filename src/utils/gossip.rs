@@ -0,0 +1,593 @@
+//! Gossip between instances, for deployments without Redis
+//!
+//! Every `interval`, each instance sends its configured peers what changed
+//! locally: requests it accepted per rate limited client, its bans and
+//! unbans, and reverse DNS results it looked up. Peers merge that into their
+//! own state, so all instances converge without a shared store:
+//!
+//! - Accepted requests are charged to the client on the peer as well, so a
+//!   client's budget is spent across all instances
+//! - The most recent ban of a network wins, and an unban wins over older bans
+//! - DNS results fill gaps in the peer's cache, never replacing its own
+//!
+//! Messages are UDP datagrams of JSON, signed with HMAC-SHA256 over the
+//! shared secret. Messages older than `MAX_AGE` or seen before are dropped.
+//! Lost datagrams are not resent: counters are best effort, and the full ban
+//! list is resent every `FULL_SYNC_ROUNDS` rounds.
+
+use crate::middleware::rate_limit::RateLimitPolicies;
+use crate::utils::{
+    bans::{Ban, BanList},
+    cache::DnsCache,
+    network::Cidr,
+};
+use hmac::{Hmac, KeyInit, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::hash::{BuildHasher, RandomState};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::net::UdpSocket;
+
+/// Version of the message format
+const GOSSIP_VERSION: u32 = 1;
+
+/// Length of the HMAC-SHA256 tag in front of each message
+const TAG_LEN: usize = 32;
+
+/// Payload size a message is split at, well below the UDP limit
+const MAX_PAYLOAD: usize = 8 * 1024;
+
+/// Oldest (or furthest in the future) message accepted, in milliseconds
+const MAX_AGE: u64 = 30_000;
+
+/// Rounds between sending the full ban list rather than just changes
+const FULL_SYNC_ROUNDS: u64 = 10;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Gossip settings
+#[derive(Clone, Debug)]
+pub struct GossipConfig {
+    /// UDP address to receive gossip on
+    pub bind: SocketAddr,
+
+    /// Peers to send gossip to, as `host:port`
+    pub peers: Vec<String>,
+
+    /// Secret shared by all peers, used to sign messages
+    pub secret: Vec<u8>,
+
+    /// Time between rounds
+    pub interval: Duration,
+}
+
+/// One change to share with peers
+#[derive(Debug, Serialize, Deserialize)]
+enum Update {
+    /// Requests accepted for a client under a policy
+    #[serde(rename = "l")]
+    Limit(String, IpAddr, u32),
+
+    /// An active ban
+    #[serde(rename = "b")]
+    Ban(Ban),
+
+    /// A network unbanned at a Unix time in seconds
+    #[serde(rename = "u")]
+    Unban(Cidr, u64),
+
+    /// DNS records by cache key and their remaining TTL in seconds
+    #[serde(rename = "d")]
+    Dns(String, Option<Vec<String>>, u64),
+}
+
+/// Signed message body
+#[derive(Debug, Serialize, Deserialize)]
+struct Message {
+    version: u32,
+
+    /// Sending instance, to ignore our own messages
+    node: u64,
+
+    /// Unix time in milliseconds the message was sent at
+    sent_at: u64,
+
+    /// Random value telling apart messages sent at the same time
+    nonce: u64,
+
+    updates: Vec<Update>,
+}
+
+/// Gossip endpoint of this instance
+pub struct Gossip {
+    config: GossipConfig,
+    node: u64,
+    socket: UdpSocket,
+    rate_limits: Arc<RateLimitPolicies>,
+    bans: Arc<BanList>,
+    dns_cache: Arc<DnsCache>,
+    /// Messages already applied, by (node, nonce), with when they were sent
+    seen: Mutex<HashMap<(u64, u64), u64>>,
+    random: RandomState,
+    /// Messages sealed so far, so each gets its own nonce
+    sequence: AtomicU64,
+}
+
+impl Gossip {
+    /// Bind the gossip socket
+    pub async fn bind(
+        config: GossipConfig,
+        rate_limits: Arc<RateLimitPolicies>,
+        bans: Arc<BanList>,
+        dns_cache: Arc<DnsCache>,
+    ) -> Result<Self, String> {
+        let socket = UdpSocket::bind(config.bind)
+            .await
+            .map_err(|e| format!("failed to bind gossip socket {}: {}", config.bind, e))?;
+        let random = RandomState::new();
+
+        Ok(Self {
+            node: random.hash_one((std::process::id(), unix_millis())),
+            config,
+            socket,
+            rate_limits,
+            bans,
+            dns_cache,
+            seen: Mutex::new(HashMap::new()),
+            random,
+            sequence: AtomicU64::new(0),
+        })
+    }
+
+    /// Address the gossip socket is bound to
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Receive messages and send rounds until the process exits
+    pub async fn run(self: Arc<Self>) {
+        let receiver = self.clone();
+        tokio::spawn(async move { receiver.receive().await });
+
+        let mut interval = tokio::time::interval(self.config.interval);
+        let mut round = 0u64;
+        let mut sent_generation = None;
+        loop {
+            interval.tick().await;
+
+            // Bans only go out when they changed, or for a periodic full sync
+            let generation = self.bans.generation().await;
+            let send_bans =
+                round.is_multiple_of(FULL_SYNC_ROUNDS) || sent_generation != Some(generation);
+            sent_generation = Some(generation);
+            round += 1;
+
+            self.send_round(send_bans).await;
+        }
+    }
+
+    /// Send local changes to every peer
+    async fn send_round(&self, send_bans: bool) {
+        let mut updates: Vec<Update> = self
+            .rate_limits
+            .take_unshared()
+            .await
+            .into_iter()
+            .map(|(policy, ip, requests)| Update::Limit(policy, ip, requests))
+            .collect();
+
+        if send_bans {
+            let (bans, unbanned) = self.bans.gossip_state().await;
+            updates.extend(bans.into_iter().map(Update::Ban));
+            updates.extend(
+                unbanned
+                    .into_iter()
+                    .map(|(network, at)| Update::Unban(network, at)),
+            );
+        }
+
+        updates.extend(
+            self.dns_cache
+                .take_unshared()
+                .await
+                .into_iter()
+                .map(|(key, value, ttl)| Update::Dns(key, value, ttl.as_secs())),
+        );
+
+        if updates.is_empty() {
+            return;
+        }
+
+        let datagrams = self.seal_all(updates);
+        for peer in &self.config.peers {
+            let addrs = match tokio::net::lookup_host(peer.as_str()).await {
+                Ok(addrs) => addrs,
+                Err(e) => {
+                    tracing::warn!(peer = %peer, error = %e, "Failed to resolve gossip peer");
+                    continue;
+                }
+            };
+            for addr in addrs {
+                for datagram in &datagrams {
+                    if let Err(e) = self.socket.send_to(datagram, addr).await {
+                        tracing::debug!(peer = %addr, error = %e, "Failed to send gossip");
+                    }
+                }
+            }
+        }
+    }
+
+    /// Receive and apply messages from peers
+    async fn receive(&self) {
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let (len, from) = match self.socket.recv_from(&mut buf).await {
+                Ok(received) => received,
+                Err(e) => {
+                    tracing::debug!(error = %e, "Failed to receive gossip");
+                    continue;
+                }
+            };
+
+            match self.open(&buf[..len]) {
+                Ok(Some(message)) => self.apply(message).await,
+                Ok(None) => {}
+                Err(e) => tracing::warn!(peer = %from, error = %e, "Rejected gossip message"),
+            }
+        }
+    }
+
+    /// Merge a peer's updates into local state
+    async fn apply(&self, message: Message) {
+        let mut bans = Vec::new();
+        let mut unbanned = Vec::new();
+
+        for update in message.updates {
+            match update {
+                Update::Limit(policy, ip, requests) => {
+                    if let Some(limiter) = self.rate_limits.by_name(&policy) {
+                        limiter.absorb(ip, requests).await;
+                    }
+                }
+                Update::Ban(ban) => bans.push(ban),
+                Update::Unban(network, at) => unbanned.push((network, at)),
+                Update::Dns(key, value, ttl) => {
+                    self.dns_cache
+                        .insert_shared(key, value, Duration::from_secs(ttl))
+                        .await;
+                }
+            }
+        }
+
+        if !bans.is_empty() || !unbanned.is_empty() {
+            self.bans.merge(bans, unbanned).await;
+        }
+    }
+
+    /// Split updates into signed datagrams of at most `MAX_PAYLOAD` bytes
+    fn seal_all(&self, updates: Vec<Update>) -> Vec<Vec<u8>> {
+        let mut datagrams = Vec::new();
+        let mut batch = Vec::new();
+        let mut size = 0;
+
+        for update in updates {
+            let update_size = serde_json::to_vec(&update).map_or(0, |bytes| bytes.len() + 1);
+            if !batch.is_empty() && size + update_size > MAX_PAYLOAD {
+                datagrams.push(self.seal(std::mem::take(&mut batch)));
+                size = 0;
+            }
+            size += update_size;
+            batch.push(update);
+        }
+        if !batch.is_empty() {
+            datagrams.push(self.seal(batch));
+        }
+
+        datagrams
+    }
+
+    /// Sign a message holding `updates`
+    fn seal(&self, updates: Vec<Update>) -> Vec<u8> {
+        let message = Message {
+            version: GOSSIP_VERSION,
+            node: self.node,
+            sent_at: unix_millis(),
+            nonce: self
+                .random
+                .hash_one((self.node, self.sequence.fetch_add(1, Ordering::Relaxed))),
+            updates,
+        };
+        let payload = serde_json::to_vec(&message).unwrap_or_default();
+
+        let mut datagram = self
+            .mac()
+            .chain_update(&payload)
+            .finalize()
+            .into_bytes()
+            .to_vec();
+        datagram.extend_from_slice(&payload);
+        datagram
+    }
+
+    /// Verify and decode a datagram
+    ///
+    /// Returns `None` for our own messages and messages already applied.
+    fn open(&self, datagram: &[u8]) -> Result<Option<Message>, String> {
+        if datagram.len() < TAG_LEN {
+            return Err("message too short".to_string());
+        }
+        let (tag, payload) = datagram.split_at(TAG_LEN);
+        self.mac()
+            .chain_update(payload)
+            .verify_slice(tag)
+            .map_err(|_| "invalid signature".to_string())?;
+
+        let message: Message =
+            serde_json::from_slice(payload).map_err(|e| format!("invalid message: {}", e))?;
+        if message.version != GOSSIP_VERSION {
+            return Err(format!("unsupported version {}", message.version));
+        }
+        if message.node == self.node {
+            return Ok(None);
+        }
+
+        let now = unix_millis();
+        if now.abs_diff(message.sent_at) > MAX_AGE {
+            return Err("message too old".to_string());
+        }
+
+        // A replayed message would count its requests twice
+        let mut seen = self.seen.lock().unwrap_or_else(PoisonError::into_inner);
+        seen.retain(|_, sent_at| now.abs_diff(*sent_at) <= MAX_AGE);
+        if seen
+            .insert((message.node, message.nonce), message.sent_at)
+            .is_some()
+        {
+            return Ok(None);
+        }
+
+        Ok(Some(message))
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.config.secret).expect("HMAC accepts any key length")
+    }
+}
+
+/// Current Unix time in milliseconds
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::rate_limit::RateLimiter;
+    use crate::utils::bans::BanConfig;
+
+    struct Node {
+        gossip: Arc<Gossip>,
+        rate_limits: Arc<RateLimitPolicies>,
+        bans: Arc<BanList>,
+        dns_cache: Arc<DnsCache>,
+    }
+
+    async fn node(secret: &str) -> Node {
+        let rate_limits = Arc::new(RateLimitPolicies::new(RateLimiter::new(
+            3,
+            Duration::from_secs(60),
+        )));
+        let bans = Arc::new(BanList::new(
+            BanConfig {
+                max_strikes: 0,
+                find_time: Duration::from_secs(600),
+                ban_time: Duration::from_secs(600),
+                max_ban_time: Duration::from_secs(600),
+                ipv4_prefix: 32,
+                ipv6_prefix: 64,
+            },
+            None,
+        ));
+        let dns_cache = Arc::new(DnsCache::new(
+            100,
+            Duration::from_secs(300),
+            Duration::from_secs(300),
+        ));
+        let config = GossipConfig {
+            bind: "127.0.0.1:0".parse().unwrap(),
+            peers: Vec::new(),
+            secret: secret.as_bytes().to_vec(),
+            interval: Duration::from_secs(1),
+        };
+        let gossip = Gossip::bind(config, rate_limits.clone(), bans.clone(), dns_cache.clone())
+            .await
+            .unwrap();
+
+        Node {
+            gossip: Arc::new(gossip),
+            rate_limits,
+            bans,
+            dns_cache,
+        }
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    /// Deliver what `from` would send in a round to `to`
+    async fn deliver(from: &Node, to: &Node) {
+        let (bans, unbanned) = from.bans.gossip_state().await;
+        let mut updates: Vec<Update> = from
+            .rate_limits
+            .take_unshared()
+            .await
+            .into_iter()
+            .map(|(policy, ip, requests)| Update::Limit(policy, ip, requests))
+            .collect();
+        updates.extend(bans.into_iter().map(Update::Ban));
+        updates.extend(unbanned.into_iter().map(|(net, at)| Update::Unban(net, at)));
+        updates.extend(
+            from.dns_cache
+                .take_unshared()
+                .await
+                .into_iter()
+                .map(|(key, value, ttl)| Update::Dns(key, value, ttl.as_secs())),
+        );
+
+        for datagram in from.gossip.seal_all(updates) {
+            let message = to.gossip.open(&datagram).unwrap().unwrap();
+            to.gossip.apply(message).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_messages_are_authenticated() {
+        let (a, b, other) = (
+            node("secret").await,
+            node("secret").await,
+            node("other").await,
+        );
+        let datagram = a
+            .gossip
+            .seal(vec![Update::Unban("192.0.2.0/24".parse().unwrap(), 1)]);
+
+        assert!(other.gossip.open(&datagram).is_err());
+        assert!(b.gossip.open(&datagram[..10]).is_err());
+
+        let mut tampered = datagram.clone();
+        *tampered.last_mut().unwrap() ^= 1;
+        assert!(b.gossip.open(&tampered).is_err());
+
+        // Own messages and replays are ignored
+        assert!(a.gossip.open(&datagram).unwrap().is_none());
+        assert!(b.gossip.open(&datagram).unwrap().is_some());
+        assert!(b.gossip.open(&datagram).unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_large_updates_are_split() {
+        let a = node("secret").await;
+        let updates = (0..2000)
+            .map(|i| {
+                Update::Limit(
+                    "default".to_string(),
+                    ip(&format!("10.0.{}.{}", i / 256, i % 256)),
+                    1,
+                )
+            })
+            .collect();
+
+        let datagrams = a.gossip.seal_all(updates);
+        assert!(datagrams.len() > 1);
+        assert!(
+            datagrams
+                .iter()
+                .all(|d| d.len() <= TAG_LEN + MAX_PAYLOAD + 128)
+        );
+
+        // Batches sealed in the same millisecond are not taken for replays
+        let b = node("secret").await;
+        for datagram in &datagrams {
+            let message = b.gossip.open(datagram).unwrap().unwrap();
+            b.gossip.apply(message).await;
+        }
+        let limiter = b.rate_limits.for_path("/");
+        for i in [0, 1000, 1999] {
+            let client = ip(&format!("10.0.{}.{}", i / 256, i % 256));
+            assert_eq!(limiter.peek(client).await.remaining, 2);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rate_limits_are_shared() {
+        let (a, b) = (node("secret").await, node("secret").await);
+        let client = ip("192.0.2.1");
+
+        for _ in 0..2 {
+            assert!(
+                a.rate_limits
+                    .for_path("/")
+                    .check_rate_limit(client)
+                    .await
+                    .allowed
+            );
+        }
+        deliver(&a, &b).await;
+
+        let limiter = b.rate_limits.for_path("/");
+        assert!(limiter.check_rate_limit(client).await.allowed);
+        assert!(!limiter.check_rate_limit(client).await.allowed);
+
+        // Absorbed requests are not sent back
+        assert!(a.rate_limits.take_unshared().await.is_empty());
+        deliver(&b, &a).await;
+        assert!(!a.rate_limits.for_path("/").peek(client).await.allowed);
+    }
+
+    #[tokio::test]
+    async fn test_bans_and_unbans_converge() {
+        let (a, b) = (node("secret").await, node("secret").await);
+        let network: Cidr = "198.51.100.0/24".parse().unwrap();
+
+        a.bans.ban(network, "abuse".to_string(), None).await;
+        let before_unban = a.bans.list().await;
+        deliver(&a, &b).await;
+        assert!(b.bans.check(ip("198.51.100.9")).await.is_some());
+
+        b.bans.unban(&network).await.unwrap();
+        deliver(&b, &a).await;
+        assert!(a.bans.check(ip("198.51.100.9")).await.is_none());
+
+        // A peer that missed the unban drops the ban, and cannot spread it back
+        let c = node("secret").await;
+        c.bans.merge(before_unban, Vec::new()).await;
+        deliver(&a, &c).await;
+        assert!(c.bans.check(ip("198.51.100.9")).await.is_none());
+
+        let d = node("secret").await;
+        d.bans.merge(a.bans.list().await, Vec::new()).await;
+        d.bans.merge(vec![], b.bans.gossip_state().await.1).await;
+        deliver(&d, &a).await;
+        assert!(a.bans.check(ip("198.51.100.9")).await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_dns_results_are_shared() {
+        let (a, b) = (node("secret").await, node("secret").await);
+        a.dns_cache
+            .insert(
+                "192.0.2.1".to_string(),
+                Some(vec!["host.example".to_string()]),
+            )
+            .await;
+        b.dns_cache
+            .insert(
+                "192.0.2.2".to_string(),
+                Some(vec!["mine.example".to_string()]),
+            )
+            .await;
+
+        deliver(&a, &b).await;
+        assert_eq!(
+            b.dns_cache.get("192.0.2.1").await,
+            Some(Some(vec!["host.example".to_string()]))
+        );
+
+        // Shared entries are not passed on again
+        assert!(
+            b.dns_cache
+                .take_unshared()
+                .await
+                .iter()
+                .all(|(key, _, _)| key == "192.0.2.2")
+        );
+    }
+}
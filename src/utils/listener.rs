@@ -0,0 +1,201 @@
+//! Connection listener with optional PROXY protocol support
+
+use crate::utils::client_ip::TrustedProxies;
+use crate::utils::proxy_protocol::{self, ProxyHeader};
+use axum::extract::connect_info::Connected;
+use axum::serve::{IncomingStream, Listener};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+/// Time allowed for a client to send its PROXY header
+const HEADER_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Connections with a parsed header waiting to be served
+const ACCEPT_BACKLOG: usize = 1024;
+
+/// Information about an accepted connection
+#[derive(Clone, Debug)]
+pub struct ConnectionInfo {
+    /// Address of the TCP peer
+    pub peer: SocketAddr,
+
+    /// PROXY protocol header, when the listener expects one
+    pub proxy: Option<ProxyHeader>,
+}
+
+impl ConnectionInfo {
+    /// Address the connection originates from
+    ///
+    /// This is the PROXY protocol source when present, otherwise the TCP peer.
+    pub fn source(&self) -> SocketAddr {
+        self.proxy
+            .as_ref()
+            .and_then(|header| header.source)
+            .unwrap_or(self.peer)
+    }
+}
+
+impl Connected<IncomingStream<'_, ConnectionListener>> for ConnectionInfo {
+    fn connect_info(stream: IncomingStream<'_, ConnectionListener>) -> Self {
+        stream.remote_addr().clone()
+    }
+}
+
+/// Listener that optionally expects a PROXY protocol header on every connection
+pub struct ConnectionListener {
+    local_addr: SocketAddr,
+    mode: Mode,
+}
+
+enum Mode {
+    Direct(TcpListener),
+    ProxyProtocol(mpsc::Receiver<(TcpStream, ConnectionInfo)>),
+}
+
+impl ConnectionListener {
+    /// Bind a listener
+    ///
+    /// With `proxy_protocol` set, every connection must come from one of the
+    /// given trusted proxies and start with a v1 or v2 header. Headers are
+    /// read in background tasks so that a slow client cannot stall the accept
+    /// loop; connections from other peers or without a valid header are
+    /// dropped.
+    pub async fn bind(addr: &str, proxy_protocol: Option<Arc<TrustedProxies>>) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+
+        let mode = match proxy_protocol {
+            Some(trusted) => {
+                let (tx, rx) = mpsc::channel(ACCEPT_BACKLOG);
+                tokio::spawn(accept_proxied(listener, trusted, tx));
+                Mode::ProxyProtocol(rx)
+            }
+            None => Mode::Direct(listener),
+        };
+
+        Ok(Self { local_addr, mode })
+    }
+}
+
+impl Listener for ConnectionListener {
+    type Io = TcpStream;
+    type Addr = ConnectionInfo;
+
+    async fn accept(&mut self) -> (Self::Io, Self::Addr) {
+        match &mut self.mode {
+            Mode::Direct(listener) => {
+                let (stream, peer) = accept_tcp(listener).await;
+                (stream, ConnectionInfo { peer, proxy: None })
+            }
+            Mode::ProxyProtocol(rx) => match rx.recv().await {
+                Some(connection) => connection,
+                // The acceptor task only exits if the runtime is shutting down
+                None => std::future::pending().await,
+            },
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<Self::Addr> {
+        Ok(ConnectionInfo {
+            peer: self.local_addr,
+            proxy: None,
+        })
+    }
+}
+
+/// Accept loop for PROXY protocol listeners
+///
+/// Anyone can write a PROXY header, so only trusted peers may send one;
+/// otherwise a direct client could claim any source address.
+async fn accept_proxied(
+    listener: TcpListener,
+    trusted: Arc<TrustedProxies>,
+    tx: mpsc::Sender<(TcpStream, ConnectionInfo)>,
+) {
+    loop {
+        let (mut stream, peer) = accept_tcp(&listener).await;
+        if !trusted.is_trusted(&peer.ip()) {
+            tracing::debug!(peer = %peer, "Rejected PROXY protocol connection from untrusted peer");
+            continue;
+        }
+        let tx = tx.clone();
+
+        tokio::spawn(async move {
+            let header =
+                tokio::time::timeout(HEADER_TIMEOUT, proxy_protocol::read_header(&mut stream))
+                    .await;
+
+            match header {
+                Ok(Ok(header)) => {
+                    let info = ConnectionInfo {
+                        peer,
+                        proxy: Some(header),
+                    };
+                    let _ = tx.send((stream, info)).await;
+                }
+                Ok(Err(e)) => {
+                    tracing::debug!(peer = %peer, error = %e, "Rejected PROXY protocol connection");
+                }
+                Err(_) => {
+                    tracing::debug!(peer = %peer, "Timed out waiting for PROXY protocol header");
+                }
+            }
+        });
+    }
+}
+
+/// Accept a TCP connection, retrying on errors
+async fn accept_tcp(listener: &TcpListener) -> (TcpStream, SocketAddr) {
+    loop {
+        match listener.accept().await {
+            Ok(connection) => return connection,
+            Err(e) => {
+                tracing::error!(error = %e, "Failed to accept connection");
+                // Back off on errors such as running out of file descriptors
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    const HEADER: &[u8] = b"PROXY TCP4 198.51.100.7 192.0.2.1 40000 80\r\n";
+
+    async fn listener(trusted: &str) -> ConnectionListener {
+        let trusted = TrustedProxies::new(vec![trusted.parse().unwrap()]);
+        ConnectionListener::bind("127.0.0.1:0", Some(Arc::new(trusted)))
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_trusted_peer_sets_source() {
+        let mut listener = listener("127.0.0.0/8").await;
+        let mut client = TcpStream::connect(listener.local_addr).await.unwrap();
+        client.write_all(HEADER).await.unwrap();
+
+        let (_, info) = listener.accept().await;
+        assert_eq!(info.source(), "198.51.100.7:40000".parse().unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_untrusted_peer_is_rejected() {
+        let mut listener = listener("192.0.2.0/24").await;
+        let mut client = TcpStream::connect(listener.local_addr).await.unwrap();
+        client.write_all(HEADER).await.unwrap();
+
+        // The connection is closed without ever being handed to the server
+        let mut buf = [0; 1];
+        assert!(matches!(client.read(&mut buf).await, Ok(0) | Err(_)));
+        let accepted = tokio::time::timeout(Duration::from_millis(200), listener.accept()).await;
+        assert!(accepted.is_err());
+    }
+}
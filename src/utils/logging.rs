@@ -1,6 +1,6 @@
 //! Logging configuration
 
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
+use tracing_subscriber::{EnvFilter, layer::SubscriberExt, util::SubscriberInitExt};
 
 /// Initialize structured logging
 pub fn init_logging() {
@@ -14,9 +14,15 @@ pub fn init_logging() {
 
     if use_json {
         // JSON formatted logs
-        tracing_subscriber::registry().with(env_filter).with(tracing_subscriber::fmt::layer().json()).init();
+        tracing_subscriber::registry()
+            .with(env_filter)
+            .with(tracing_subscriber::fmt::layer().json())
+            .init();
     } else {
         // Human-readable logs
-        tracing_subscriber::registry().with(env_filter).with(tracing_subscriber::fmt::layer().pretty()).init();
+        tracing_subscriber::registry()
+            .with(env_filter)
+            .with(tracing_subscriber::fmt::layer().pretty())
+            .init();
     }
-}
\ No newline at end of file
+}
@@ -1,7 +1,7 @@
 //! Metrics collection and reporting
 
-use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 
 /// Metrics collector for API statistics
 #[derive(Clone)]
@@ -1,10 +1,19 @@
 //! Utility functions and helpers
 
+pub mod bans;
+pub mod cache;
+pub mod cache_redis;
+pub mod cdn;
+pub mod circuit_breaker;
 pub mod cli;
+pub mod client_ip;
 pub mod dns;
+pub mod gossip;
+pub mod listener;
+pub mod logging;
+pub mod metrics;
 pub mod network;
-pub mod time;
+pub mod proxy_protocol;
 pub mod security;
-pub mod metrics;
-pub mod cache;
-pub mod logging;
\ No newline at end of file
+pub mod snapshot;
+pub mod time;
@@ -1,5 +1,10 @@
 //! Network configuration utilities
 
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
 /// Determine bind address based on port number
 ///
 /// Port 7112 binds to `[::]` for IPv6 dual-stack support.
@@ -11,3 +16,211 @@ pub fn get_bind_address(port: u16) -> String {
         format!("0.0.0.0:{}", port)
     }
 }
+
+/// An IP network in CIDR notation (e.g. `10.0.0.0/8` or `2001:db8::/32`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct Cidr {
+    addr: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    /// The network of the given prefix length containing `ip`
+    ///
+    /// Host bits are cleared, and IPv4-mapped addresses become IPv4
+    /// networks, so equal networks always compare equal.
+    pub fn new(ip: IpAddr, prefix: u8) -> Self {
+        let prefix = match ip.to_canonical() {
+            IpAddr::V4(_) => prefix.min(32),
+            IpAddr::V6(_) => prefix.min(128),
+        };
+        Cidr {
+            addr: truncate_to_prefix(ip, prefix, prefix),
+            prefix,
+        }
+    }
+
+    /// Same network with host bits cleared
+    pub fn normalized(&self) -> Self {
+        match self.addr {
+            IpAddr::V6(v6) if v6.to_ipv4_mapped().is_some() && self.prefix >= 96 => {
+                Cidr::new(self.addr, self.prefix - 96)
+            }
+            IpAddr::V6(v6) => Cidr {
+                addr: IpAddr::V6((u128::from(v6) & prefix_mask_v6(self.prefix)).into()),
+                prefix: self.prefix,
+            },
+            IpAddr::V4(_) => Cidr::new(self.addr, self.prefix),
+        }
+    }
+
+    /// Prefix length
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Whether this is an IPv4 network
+    pub fn is_ipv4(&self) -> bool {
+        self.addr.is_ipv4()
+    }
+
+    /// Check whether an address falls inside this network
+    ///
+    /// IPv4-mapped IPv6 addresses (`::ffff:a.b.c.d`) are matched against
+    /// IPv4 networks, since that is how IPv4 peers appear on a dual-stack
+    /// listener.
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        match (self.addr, ip.to_canonical()) {
+            (IpAddr::V4(net), IpAddr::V4(ip)) => {
+                let mask = prefix_mask_v4(self.prefix);
+                u32::from(net) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(net), IpAddr::V6(ip)) => {
+                let mask = prefix_mask_v6(self.prefix);
+                u128::from(net) & mask == u128::from(ip) & mask
+            }
+            _ => false,
+        }
+    }
+}
+
+impl FromStr for Cidr {
+    type Err = String;
+
+    /// Parse `addr/prefix`, or a bare address as a single-host network
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (addr, prefix) = match s.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (s, None),
+        };
+
+        let addr: IpAddr = addr
+            .parse()
+            .map_err(|_| format!("invalid network address: {}", s))?;
+        let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+
+        let prefix = match prefix {
+            Some(p) => p
+                .parse::<u8>()
+                .ok()
+                .filter(|p| *p <= max_prefix)
+                .ok_or_else(|| format!("invalid prefix length: {}", s))?,
+            None => max_prefix,
+        };
+
+        Ok(Cidr { addr, prefix })
+    }
+}
+
+impl fmt::Display for Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+impl Serialize for Cidr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for Cidr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Truncate an address to its network prefix
+///
+/// IPv4-mapped IPv6 addresses are truncated as IPv4, so a client seen on a
+/// dual-stack listener lands in the same bucket as on an IPv4 listener.
+pub fn truncate_to_prefix(ip: IpAddr, ipv4_prefix: u8, ipv6_prefix: u8) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V4(v4) => IpAddr::V4((u32::from(v4) & prefix_mask_v4(ipv4_prefix.min(32))).into()),
+        IpAddr::V6(v6) => {
+            IpAddr::V6((u128::from(v6) & prefix_mask_v6(ipv6_prefix.min(128))).into())
+        }
+    }
+}
+
+/// Parse a comma-separated list of networks, ignoring empty items
+pub fn parse_cidr_list(list: &str) -> Result<Vec<Cidr>, String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(str::parse)
+        .collect()
+}
+
+fn prefix_mask_v4(prefix: u8) -> u32 {
+    u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0)
+}
+
+fn prefix_mask_v6(prefix: u8) -> u128 {
+    u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cidr_contains() {
+        let net: Cidr = "10.0.0.0/8".parse().unwrap();
+        assert!(net.contains(&"10.1.2.3".parse().unwrap()));
+        assert!(!net.contains(&"11.0.0.1".parse().unwrap()));
+        assert!(net.contains(&"::ffff:10.0.0.1".parse().unwrap()));
+
+        let net: Cidr = "2001:db8::/32".parse().unwrap();
+        assert!(net.contains(&"2001:db8:1::1".parse().unwrap()));
+        assert!(!net.contains(&"2001:db9::1".parse().unwrap()));
+
+        let any: Cidr = "0.0.0.0/0".parse().unwrap();
+        assert!(any.contains(&"203.0.113.9".parse().unwrap()));
+    }
+
+    #[test]
+    fn test_cidr_parse() {
+        assert_eq!(
+            "127.0.0.1".parse::<Cidr>().unwrap().to_string(),
+            "127.0.0.1/32"
+        );
+        assert_eq!("::1".parse::<Cidr>().unwrap().to_string(), "::1/128");
+        assert!("10.0.0.0/33".parse::<Cidr>().is_err());
+        assert!("not-a-network/8".parse::<Cidr>().is_err());
+        assert_eq!(parse_cidr_list(" 10.0.0.0/8, ,::1 ").unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_truncate_to_prefix() {
+        let truncate =
+            |ip: &str, v4, v6| truncate_to_prefix(ip.parse().unwrap(), v4, v6).to_string();
+
+        assert_eq!(truncate("192.0.2.77", 24, 64), "192.0.2.0");
+        assert_eq!(truncate("::ffff:192.0.2.77", 32, 64), "192.0.2.77");
+        assert_eq!(truncate("2001:db8:1:2:3:4:5:6", 32, 64), "2001:db8:1:2::");
+        assert_eq!(truncate("2001:db8:1:2ff::1", 32, 56), "2001:db8:1:200::");
+        assert_eq!(truncate("2001:db8::1", 32, 128), "2001:db8::1");
+    }
+
+    #[test]
+    fn test_cidr_normalized() {
+        let net = Cidr::new("192.0.2.77".parse().unwrap(), 24);
+        assert_eq!(net.to_string(), "192.0.2.0/24");
+        assert_eq!("192.0.2.77/24".parse::<Cidr>().unwrap().normalized(), net);
+        assert_eq!(
+            "::ffff:192.0.2.77/120"
+                .parse::<Cidr>()
+                .unwrap()
+                .normalized(),
+            net
+        );
+        assert_eq!(
+            Cidr::new("2001:db8::1".parse().unwrap(), 64).to_string(),
+            "2001:db8::/64"
+        );
+    }
+}
@@ -0,0 +1,379 @@
+//! PROXY protocol (v1 text and v2 binary) header parsing
+//!
+//! TCP load balancers such as HAProxy or AWS NLB prepend a PROXY header to
+//! each connection carrying the original client address. See
+//! <https://www.haproxy.org/download/2.9/doc/proxy-protocol.txt>.
+
+use serde::Serialize;
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use tokio::io::{AsyncRead, AsyncReadExt};
+
+/// Signature that starts every v2 header
+const V2_SIGNATURE: [u8; 12] = *b"\r\n\r\n\0\r\nQUIT\n";
+
+/// Maximum length of a v1 header including the trailing CRLF
+const V1_MAX_LENGTH: usize = 107;
+
+const PP2_TYPE_ALPN: u8 = 0x01;
+const PP2_TYPE_AUTHORITY: u8 = 0x02;
+const PP2_TYPE_UNIQUE_ID: u8 = 0x05;
+const PP2_TYPE_SSL: u8 = 0x20;
+const PP2_SUBTYPE_SSL_VERSION: u8 = 0x21;
+const PP2_SUBTYPE_SSL_CN: u8 = 0x22;
+const PP2_SUBTYPE_SSL_CIPHER: u8 = 0x23;
+const PP2_SUBTYPE_SSL_SIG_ALG: u8 = 0x24;
+const PP2_SUBTYPE_SSL_KEY_ALG: u8 = 0x25;
+const PP2_CLIENT_SSL: u8 = 0x01;
+
+/// Parsed PROXY protocol header
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ProxyHeader {
+    /// Original client address (None for `LOCAL`/`UNKNOWN` connections)
+    pub source: Option<SocketAddr>,
+
+    /// Address the client originally connected to
+    pub destination: Option<SocketAddr>,
+
+    /// Type-length-value fields (v2 only)
+    pub tlvs: ProxyTlvs,
+}
+
+/// TLV fields carried by a v2 header
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct ProxyTlvs {
+    /// Negotiated application protocol (e.g. `h2`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub alpn: Option<String>,
+
+    /// Host name the client asked for (usually the TLS SNI)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub authority: Option<String>,
+
+    /// Opaque connection identifier, hex encoded
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub unique_id: Option<String>,
+
+    /// TLS details of the client connection
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ssl: Option<SslInfo>,
+}
+
+/// TLS details from the `PP2_TYPE_SSL` TLV
+#[derive(Clone, Debug, Default, PartialEq, Serialize)]
+pub struct SslInfo {
+    /// Whether the client connected over TLS
+    pub ssl: bool,
+
+    /// Whether a client certificate was presented and verified
+    pub verified: bool,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cipher: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sig_alg: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key_alg: Option<String>,
+
+    /// Common name of the client certificate
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cn: Option<String>,
+}
+
+/// Read a v1 or v2 PROXY header from the start of a stream
+///
+/// Reads exactly the header bytes, leaving the rest of the stream untouched.
+pub async fn read_header<R: AsyncRead + Unpin>(stream: &mut R) -> io::Result<ProxyHeader> {
+    // Both a v2 signature and the shortest v1 header are at least 12 bytes
+    let mut prefix = [0u8; 12];
+    stream.read_exact(&mut prefix).await?;
+
+    if prefix == V2_SIGNATURE {
+        let mut fixed = [0u8; 4];
+        stream.read_exact(&mut fixed).await?;
+
+        let len = u16::from_be_bytes([fixed[2], fixed[3]]) as usize;
+        let mut payload = vec![0u8; len];
+        stream.read_exact(&mut payload).await?;
+
+        parse_v2(fixed[0], fixed[1], &payload)
+    } else if prefix.starts_with(b"PROXY ") {
+        let mut line = prefix.to_vec();
+        while !line.ends_with(b"\r\n") {
+            if line.len() >= V1_MAX_LENGTH {
+                return Err(invalid("v1 header too long"));
+            }
+            line.push(stream.read_u8().await?);
+        }
+
+        parse_v1(&line)
+    } else {
+        Err(invalid("missing PROXY protocol header"))
+    }
+}
+
+/// Parse a complete v1 header line, including the trailing CRLF
+fn parse_v1(line: &[u8]) -> io::Result<ProxyHeader> {
+    let line = std::str::from_utf8(line)
+        .map_err(|_| invalid("v1 header is not ASCII"))?
+        .trim_end_matches("\r\n");
+    let parts: Vec<&str> = line.split(' ').collect();
+
+    match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => Ok(ProxyHeader::default()),
+        ["PROXY", family @ ("TCP4" | "TCP6"), src, dst, sport, dport] => {
+            let parse_ip = |s: &str| -> io::Result<IpAddr> {
+                let ip: IpAddr = s.parse().map_err(|_| invalid("invalid v1 address"))?;
+                match (*family, ip) {
+                    ("TCP4", IpAddr::V4(_)) | ("TCP6", IpAddr::V6(_)) => Ok(ip),
+                    _ => Err(invalid("v1 address does not match family")),
+                }
+            };
+            let parse_port =
+                |s: &str| -> io::Result<u16> { s.parse().map_err(|_| invalid("invalid v1 port")) };
+
+            Ok(ProxyHeader {
+                source: Some(SocketAddr::new(parse_ip(src)?, parse_port(sport)?)),
+                destination: Some(SocketAddr::new(parse_ip(dst)?, parse_port(dport)?)),
+                tlvs: ProxyTlvs::default(),
+            })
+        }
+        _ => Err(invalid("malformed v1 header")),
+    }
+}
+
+/// Parse the part of a v2 header following the signature
+fn parse_v2(version_command: u8, family: u8, payload: &[u8]) -> io::Result<ProxyHeader> {
+    if version_command >> 4 != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    let command = version_command & 0x0f;
+    if command > 1 {
+        return Err(invalid("unsupported v2 command"));
+    }
+
+    let (addresses, tlv_data) = match family >> 4 {
+        0x1 if payload.len() >= 12 => {
+            let src = Ipv4Addr::new(payload[0], payload[1], payload[2], payload[3]);
+            let dst = Ipv4Addr::new(payload[4], payload[5], payload[6], payload[7]);
+            let sport = u16::from_be_bytes([payload[8], payload[9]]);
+            let dport = u16::from_be_bytes([payload[10], payload[11]]);
+            (
+                Some((
+                    SocketAddr::new(src.into(), sport),
+                    SocketAddr::new(dst.into(), dport),
+                )),
+                &payload[12..],
+            )
+        }
+        0x2 if payload.len() >= 36 => {
+            let src: [u8; 16] = payload[0..16].try_into().unwrap();
+            let dst: [u8; 16] = payload[16..32].try_into().unwrap();
+            let sport = u16::from_be_bytes([payload[32], payload[33]]);
+            let dport = u16::from_be_bytes([payload[34], payload[35]]);
+            (
+                Some((
+                    SocketAddr::new(Ipv6Addr::from(src).into(), sport),
+                    SocketAddr::new(Ipv6Addr::from(dst).into(), dport),
+                )),
+                &payload[36..],
+            )
+        }
+        0x3 if payload.len() >= 216 => (None, &payload[216..]),
+        0x0 => (None, payload),
+        _ => return Err(invalid("invalid v2 address block")),
+    };
+
+    let mut header = ProxyHeader {
+        tlvs: parse_tlvs(tlv_data)?,
+        ..ProxyHeader::default()
+    };
+
+    // LOCAL connections (health checks from the balancer itself) keep the
+    // real peer address
+    if command == 1
+        && let Some((source, destination)) = addresses
+    {
+        header.source = Some(source);
+        header.destination = Some(destination);
+    }
+
+    Ok(header)
+}
+
+/// Parse the TLV vector that follows the address block
+fn parse_tlvs(mut data: &[u8]) -> io::Result<ProxyTlvs> {
+    let mut tlvs = ProxyTlvs::default();
+
+    while !data.is_empty() {
+        let (kind, value, rest) = split_tlv(data)?;
+        data = rest;
+
+        match kind {
+            PP2_TYPE_ALPN => tlvs.alpn = Some(lossy(value)),
+            PP2_TYPE_AUTHORITY => tlvs.authority = Some(lossy(value)),
+            PP2_TYPE_UNIQUE_ID => {
+                tlvs.unique_id = Some(value.iter().map(|b| format!("{:02x}", b)).collect())
+            }
+            PP2_TYPE_SSL => tlvs.ssl = Some(parse_ssl(value)?),
+            _ => {}
+        }
+    }
+
+    Ok(tlvs)
+}
+
+/// Parse the `PP2_TYPE_SSL` TLV and its sub-TLVs
+fn parse_ssl(value: &[u8]) -> io::Result<SslInfo> {
+    if value.len() < 5 {
+        return Err(invalid("truncated SSL TLV"));
+    }
+
+    let client = value[0];
+    let verify = u32::from_be_bytes([value[1], value[2], value[3], value[4]]);
+    let mut info = SslInfo {
+        ssl: client & PP2_CLIENT_SSL != 0,
+        verified: verify == 0,
+        ..SslInfo::default()
+    };
+
+    let mut data = &value[5..];
+    while !data.is_empty() {
+        let (kind, value, rest) = split_tlv(data)?;
+        data = rest;
+
+        let field = match kind {
+            PP2_SUBTYPE_SSL_VERSION => &mut info.version,
+            PP2_SUBTYPE_SSL_CN => &mut info.cn,
+            PP2_SUBTYPE_SSL_CIPHER => &mut info.cipher,
+            PP2_SUBTYPE_SSL_SIG_ALG => &mut info.sig_alg,
+            PP2_SUBTYPE_SSL_KEY_ALG => &mut info.key_alg,
+            _ => continue,
+        };
+        *field = Some(lossy(value));
+    }
+
+    Ok(info)
+}
+
+/// Split one TLV off the front of `data`
+fn split_tlv(data: &[u8]) -> io::Result<(u8, &[u8], &[u8])> {
+    if data.len() < 3 {
+        return Err(invalid("truncated TLV"));
+    }
+
+    let len = u16::from_be_bytes([data[1], data[2]]) as usize;
+    let end = 3 + len;
+    if data.len() < end {
+        return Err(invalid("truncated TLV"));
+    }
+
+    Ok((data[0], &data[3..end], &data[end..]))
+}
+
+fn lossy(value: &[u8]) -> String {
+    String::from_utf8_lossy(value).into_owned()
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn parse(bytes: &[u8]) -> io::Result<(ProxyHeader, Vec<u8>)> {
+        let mut reader = bytes;
+        let header = read_header(&mut reader).await?;
+        Ok((header, reader.to_vec()))
+    }
+
+    fn tlv(kind: u8, value: &[u8]) -> Vec<u8> {
+        let mut out = vec![kind];
+        out.extend_from_slice(&(value.len() as u16).to_be_bytes());
+        out.extend_from_slice(value);
+        out
+    }
+
+    #[tokio::test]
+    async fn test_v1_tcp4() {
+        let (header, rest) = parse(b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /")
+            .await
+            .unwrap();
+        assert_eq!(header.source, Some("192.0.2.1:56324".parse().unwrap()));
+        assert_eq!(
+            header.destination,
+            Some("198.51.100.1:443".parse().unwrap())
+        );
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn test_v1_tcp6_and_unknown() {
+        let (header, _) = parse(b"PROXY TCP6 2001:db8::1 2001:db8::2 1000 80\r\n")
+            .await
+            .unwrap();
+        assert_eq!(header.source, Some("[2001:db8::1]:1000".parse().unwrap()));
+
+        let (header, _) = parse(b"PROXY UNKNOWN\r\n").await.unwrap();
+        assert_eq!(header.source, None);
+    }
+
+    #[tokio::test]
+    async fn test_v1_rejects_garbage() {
+        assert!(parse(b"GET / HTTP/1.1\r\n\r\n").await.is_err());
+        assert!(
+            parse(b"PROXY TCP4 2001:db8::1 192.0.2.1 1 2\r\n")
+                .await
+                .is_err()
+        );
+        assert!(
+            parse(&[b"PROXY TCP4 ".as_slice(), &[b'1'; 120]].concat())
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_v2_tcp4_with_tlvs() {
+        let mut ssl = vec![PP2_CLIENT_SSL, 0, 0, 0, 0];
+        ssl.extend(tlv(PP2_SUBTYPE_SSL_VERSION, b"TLSv1.3"));
+        ssl.extend(tlv(PP2_SUBTYPE_SSL_CIPHER, b"TLS_AES_128_GCM_SHA256"));
+
+        let mut payload = vec![192, 0, 2, 1, 198, 51, 100, 1, 0x1f, 0x90, 0x01, 0xbb];
+        payload.extend(tlv(PP2_TYPE_AUTHORITY, b"ip.example.com"));
+        payload.extend(tlv(PP2_TYPE_ALPN, b"h2"));
+        payload.extend(tlv(PP2_TYPE_SSL, &ssl));
+
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend([0x21, 0x11]);
+        bytes.extend((payload.len() as u16).to_be_bytes());
+        bytes.extend(payload);
+        bytes.extend(b"GET /");
+
+        let (header, rest) = parse(&bytes).await.unwrap();
+        assert_eq!(header.source, Some("192.0.2.1:8080".parse().unwrap()));
+        assert_eq!(header.tlvs.authority.as_deref(), Some("ip.example.com"));
+        assert_eq!(header.tlvs.alpn.as_deref(), Some("h2"));
+        let ssl = header.tlvs.ssl.unwrap();
+        assert!(ssl.ssl && ssl.verified);
+        assert_eq!(ssl.version.as_deref(), Some("TLSv1.3"));
+        assert_eq!(rest, b"GET /");
+    }
+
+    #[tokio::test]
+    async fn test_v2_local_command() {
+        let mut bytes = V2_SIGNATURE.to_vec();
+        bytes.extend([0x20, 0x00, 0x00, 0x00]);
+
+        let (header, _) = parse(&bytes).await.unwrap();
+        assert_eq!(header.source, None);
+    }
+}
@@ -57,6 +57,39 @@ pub fn sanitize_ip(ip: &str) -> Option<String> {
     }
 }
 
+/// Longest hostname in presentation form, without the trailing dot
+const MAX_HOSTNAME_LENGTH: usize = 253;
+
+/// Longest label of a hostname
+const MAX_LABEL_LENGTH: usize = 63;
+
+/// Sanitize a hostname for DNS queries
+///
+/// Trims whitespace and one trailing dot, then checks the name is made of
+/// letters, digits, hyphens and underscores (as in `_sip._tcp` SRV names),
+/// in dot-separated labels of 1 to 63 characters that do not start or end
+/// with a hyphen. Returns the name in lowercase.
+pub fn sanitize_hostname(name: &str) -> Option<String> {
+    let trimmed = name.trim();
+    let trimmed = trimmed.strip_suffix('.').unwrap_or(trimmed);
+
+    if trimmed.is_empty() || trimmed.len() > MAX_HOSTNAME_LENGTH {
+        return None;
+    }
+
+    let valid = trimmed.split('.').all(|label| {
+        !label.is_empty()
+            && label.len() <= MAX_LABEL_LENGTH
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label
+                .bytes()
+                .all(|b| b.is_ascii_alphanumeric() || b == b'-' || b == b'_')
+    });
+
+    valid.then(|| trimmed.to_ascii_lowercase())
+}
+
 /// Validate user agent string
 ///
 /// Checks if user agent is within reasonable length and doesn't contain
@@ -72,6 +105,17 @@ pub fn is_valid_user_agent(user_agent: &str) -> bool {
     !user_agent.chars().any(|c| c.is_control() && c != '\t')
 }
 
+/// Compare two secrets in constant time
+///
+/// Only the length can leak, not how many leading bytes match.
+pub fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    if a.len() != b.len() {
+        return false;
+    }
+
+    a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -104,10 +148,44 @@ mod tests {
         assert_eq!(sanitize_ip("invalid"), None);
     }
 
+    #[test]
+    fn test_sanitize_hostname() {
+        assert_eq!(
+            sanitize_hostname("  Mail.Example.COM. "),
+            Some("mail.example.com".to_string())
+        );
+        assert_eq!(
+            sanitize_hostname("_sip._tcp.example.com"),
+            Some("_sip._tcp.example.com".to_string())
+        );
+        assert_eq!(
+            sanitize_hostname("xn--bcher-kva.example"),
+            Some("xn--bcher-kva.example".to_string())
+        );
+
+        assert_eq!(sanitize_hostname(""), None);
+        assert_eq!(sanitize_hostname("."), None);
+        assert_eq!(sanitize_hostname("example..com"), None);
+        assert_eq!(sanitize_hostname("-example.com"), None);
+        assert_eq!(sanitize_hostname("example-.com"), None);
+        assert_eq!(sanitize_hostname("exa mple.com"), None);
+        assert_eq!(sanitize_hostname("example.com\0"), None);
+        assert_eq!(sanitize_hostname("bücher.example"), None);
+        assert_eq!(sanitize_hostname(&format!("{}.com", "a".repeat(64))), None);
+        assert_eq!(sanitize_hostname(&format!("{}a", "a.".repeat(127))), None);
+    }
+
     #[test]
     fn test_user_agent_validation() {
         assert!(is_valid_user_agent("Mozilla/5.0"));
         assert!(!is_valid_user_agent("Bad\0Agent"));
         assert!(!is_valid_user_agent(&"a".repeat(600)));
     }
+
+    #[test]
+    fn test_constant_time_eq() {
+        assert!(constant_time_eq(b"secret", b"secret"));
+        assert!(!constant_time_eq(b"secret", b"secreT"));
+        assert!(!constant_time_eq(b"secret", b"secret2"));
+    }
 }
@@ -0,0 +1,362 @@
+//! State snapshots that survive restarts
+//!
+//! On shutdown the rate limiter counters, the ban list and the DNS cache are
+//! written to one file, which is read back on startup so a deploy does not
+//! hand abusive clients a fresh budget or start with a cold DNS cache.
+//! `Instant`s mean nothing to another process, so times are stored as Unix
+//! milliseconds and converted through a `Clock`.
+
+use crate::middleware::rate_limit::{RateLimitPolicies, SavedEntry};
+use crate::utils::bans::{BanList, BanSnapshot};
+use crate::utils::cache::{DnsCache, SavedDnsEntry};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::IpAddr;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::AsyncWriteExt;
+
+/// Version of the snapshot file format
+const SNAPSHOT_VERSION: u32 = 1;
+
+/// On-disk snapshot
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    version: u32,
+
+    /// Unix time in milliseconds the snapshot was taken at
+    saved_at: u64,
+
+    /// Client state by rate limit policy name
+    #[serde(default)]
+    limits: BTreeMap<String, Vec<(IpAddr, SavedEntry)>>,
+
+    #[serde(default)]
+    bans: BanSnapshot,
+
+    /// DNS cache entries, least recently used first
+    #[serde(default)]
+    dns: Vec<SavedDnsEntry>,
+}
+
+/// Counts of state restored from a snapshot
+#[derive(Debug, Default)]
+pub struct Restored {
+    /// Clients still rate limited
+    pub clients: usize,
+
+    /// Bans still active
+    pub bans: usize,
+
+    /// DNS cache entries still servable
+    pub dns_entries: usize,
+}
+
+/// Pair of readings of the monotonic and wall clocks, to convert between them
+pub struct Clock {
+    instant: Instant,
+    unix_millis: u64,
+}
+
+impl Clock {
+    /// Read both clocks now
+    pub fn now() -> Self {
+        Self {
+            instant: Instant::now(),
+            unix_millis: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+        }
+    }
+
+    /// Unix time in milliseconds of an instant
+    pub fn to_unix_millis(&self, instant: Instant) -> u64 {
+        if instant >= self.instant {
+            let ahead = instant.duration_since(self.instant).as_millis() as u64;
+            self.unix_millis.saturating_add(ahead)
+        } else {
+            let behind = self.instant.duration_since(instant).as_millis() as u64;
+            self.unix_millis.saturating_sub(behind)
+        }
+    }
+
+    /// Instant of a Unix time in milliseconds, if this process can
+    /// represent it
+    pub fn to_instant(&self, unix_millis: u64) -> Option<Instant> {
+        if unix_millis >= self.unix_millis {
+            let ahead = Duration::from_millis(unix_millis - self.unix_millis);
+            self.instant.checked_add(ahead)
+        } else {
+            let behind = Duration::from_millis(self.unix_millis - unix_millis);
+            self.instant.checked_sub(behind)
+        }
+    }
+}
+
+/// Take a snapshot of the rate limiter, ban and DNS cache state
+pub async fn capture(policies: &RateLimitPolicies, bans: &BanList, dns: &DnsCache) -> Snapshot {
+    let clock = Clock::now();
+    Snapshot {
+        version: SNAPSHOT_VERSION,
+        saved_at: clock.unix_millis,
+        limits: policies.snapshot(&clock).await,
+        bans: bans.snapshot().await,
+        dns: dns.snapshot(&clock).await,
+    }
+}
+
+/// Restore state from a snapshot, dropping anything that expired since
+pub async fn restore(
+    snapshot: Snapshot,
+    policies: &RateLimitPolicies,
+    bans: &BanList,
+    dns: &DnsCache,
+) -> Restored {
+    let clock = Clock::now();
+    Restored {
+        clients: policies.restore(snapshot.limits, &clock).await,
+        bans: bans.restore(snapshot.bans).await,
+        dns_entries: dns.restore(snapshot.dns, &clock).await,
+    }
+}
+
+/// Read a snapshot, or `None` if there is none yet
+pub async fn load(path: &Path) -> Result<Option<Snapshot>, String> {
+    let contents = match tokio::fs::read(path).await {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(format!("failed to read {}: {}", path.display(), e)),
+    };
+
+    let snapshot: Snapshot = serde_json::from_slice(&contents)
+        .map_err(|e| format!("invalid snapshot {}: {}", path.display(), e))?;
+    if snapshot.version != SNAPSHOT_VERSION {
+        return Err(format!(
+            "unsupported snapshot version {} in {}",
+            snapshot.version,
+            path.display()
+        ));
+    }
+
+    Ok(Some(snapshot))
+}
+
+/// Write a snapshot, replacing any previous one atomically
+pub async fn save(path: &Path, snapshot: &Snapshot) -> std::io::Result<()> {
+    let contents = serde_json::to_vec(snapshot).map_err(std::io::Error::other)?;
+    write_atomic(path, &contents).await
+}
+
+/// Replace a file's contents so readers see either the old or the new file
+///
+/// The contents go to a temporary file next to `path`, which is synced and
+/// then renamed over it. Each write gets its own temporary file, so concurrent
+/// writes never interleave.
+pub async fn write_atomic(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    static WRITES: AtomicU64 = AtomicU64::new(0);
+
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        WRITES.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let mut file = tokio::fs::File::create(&tmp).await?;
+    file.write_all(contents).await?;
+    file.sync_all().await?;
+    drop(file);
+
+    tokio::fs::rename(&tmp, path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::middleware::rate_limit::{RateLimitAlgorithm, RateLimiter};
+    use crate::utils::bans::{BanConfig, Offense};
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    fn policies() -> RateLimitPolicies {
+        let gcra = RateLimiter::new(60, Duration::from_secs(60))
+            .with_name("lookup")
+            .with_algorithm(RateLimitAlgorithm::Gcra, 2);
+        RateLimitPolicies::new(RateLimiter::new(3, Duration::from_secs(60)))
+            .with_policy(gcra, &["/lookup".to_string()])
+    }
+
+    fn dns_cache() -> DnsCache {
+        DnsCache::new(10, Duration::from_secs(300), Duration::from_secs(60))
+    }
+
+    fn bans() -> BanList {
+        let config = BanConfig {
+            max_strikes: 3,
+            find_time: Duration::from_secs(600),
+            ban_time: Duration::from_secs(60),
+            max_ban_time: Duration::from_secs(600),
+            ipv4_prefix: 32,
+            ipv6_prefix: 64,
+        };
+        BanList::new(config, None)
+    }
+
+    #[test]
+    fn test_clock_round_trip() {
+        let clock = Clock::now();
+        for offset in [Duration::ZERO, Duration::from_secs(5)] {
+            let past = clock.instant - offset;
+            let future = clock.instant + offset;
+            assert_eq!(clock.to_instant(clock.to_unix_millis(past)), Some(past));
+            assert_eq!(clock.to_instant(clock.to_unix_millis(future)), Some(future));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip() {
+        let dir = std::env::temp_dir().join(format!("ip-api-snapshot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.json");
+
+        let (limits, list) = (policies(), bans());
+        for _ in 0..3 {
+            limits.for_path("/").check_rate_limit(ip("192.0.2.1")).await;
+        }
+        limits
+            .for_path("/lookup")
+            .check_rate_limit(ip("192.0.2.2"))
+            .await;
+        list.record_offense(ip("192.0.2.3"), Offense::InvalidIp)
+            .await;
+        for _ in 0..3 {
+            list.record_offense(ip("192.0.2.4"), Offense::RateLimited)
+                .await;
+        }
+
+        let dns = dns_cache();
+        dns.insert(
+            "192.0.2.5".to_string(),
+            Some(vec!["host.example".to_string()]),
+        )
+        .await;
+
+        save(&path, &capture(&limits, &list, &dns).await)
+            .await
+            .unwrap();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        let (limits, list, dns) = (policies(), bans(), dns_cache());
+        let snapshot = load(&path).await.unwrap().unwrap();
+        let restored = restore(snapshot, &limits, &list, &dns).await;
+        assert_eq!(restored.clients, 2);
+        assert_eq!(restored.bans, 1);
+        assert_eq!(restored.dns_entries, 1);
+        assert_eq!(
+            dns.get("192.0.2.5").await,
+            Some(Some(vec!["host.example".to_string()]))
+        );
+
+        assert!(
+            !limits
+                .for_path("/")
+                .check_rate_limit(ip("192.0.2.1"))
+                .await
+                .allowed
+        );
+        assert_eq!(
+            limits
+                .for_path("/lookup")
+                .peek(ip("192.0.2.2"))
+                .await
+                .remaining,
+            1
+        );
+        assert!(list.check(ip("192.0.2.4")).await.is_some());
+
+        // Strikes carry over too
+        list.record_offense(ip("192.0.2.3"), Offense::InvalidIp)
+            .await;
+        assert!(
+            list.record_offense(ip("192.0.2.3"), Offense::InvalidIp)
+                .await
+                .is_some()
+        );
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_expired_entries_are_dropped() {
+        let limits = policies();
+        let clock = Clock::now();
+        let hour_ago = clock.unix_millis - 3_600_000;
+        let entries = BTreeMap::from([
+            (
+                "default".to_string(),
+                vec![
+                    (ip("192.0.2.1"), SavedEntry::FixedWindow(3, hour_ago)),
+                    (
+                        ip("192.0.2.2"),
+                        SavedEntry::FixedWindow(3, clock.unix_millis),
+                    ),
+                    // Saved under a different algorithm
+                    (
+                        ip("192.0.2.3"),
+                        SavedEntry::Gcra(clock.unix_millis + 60_000),
+                    ),
+                ],
+            ),
+            (
+                "removed".to_string(),
+                vec![(
+                    ip("192.0.2.4"),
+                    SavedEntry::FixedWindow(3, clock.unix_millis),
+                )],
+            ),
+        ]);
+
+        assert_eq!(limits.restore(entries, &clock).await, 1);
+        assert!(limits.for_path("/").peek(ip("192.0.2.1")).await.allowed);
+        assert!(!limits.for_path("/").peek(ip("192.0.2.2")).await.allowed);
+    }
+
+    #[tokio::test]
+    async fn test_unsupported_version_is_rejected() {
+        let dir = std::env::temp_dir().join(format!("ip-api-snapshot-v-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.json");
+        std::fs::write(&path, r#"{"version":99,"saved_at":0}"#).unwrap();
+
+        assert!(load(&path).await.is_err());
+        assert!(load(&dir.join("missing.json")).await.unwrap().is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_writes_do_not_interleave() {
+        let dir = std::env::temp_dir().join(format!("ip-api-snapshot-w-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("state.json");
+
+        let writes = (0..16u8).map(|i| {
+            let path = path.clone();
+            tokio::spawn(async move { write_atomic(&path, &vec![i; 64 * 1024]).await })
+        });
+        for write in writes.collect::<Vec<_>>() {
+            write.await.unwrap().unwrap();
+        }
+
+        let contents = std::fs::read(&path).unwrap();
+        assert_eq!(contents.len(), 64 * 1024);
+        assert!(contents.iter().all(|&b| b == contents[0]));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,195 @@
+//! Abuse ban and admin API integration tests
+
+mod common;
+
+use common::TestServer;
+
+const TOKEN: &str = "test-admin-token";
+
+fn admin(server: &TestServer, method: &str, path: &str, body: &str) -> common::Response {
+    let authorization = format!("Bearer {}", TOKEN);
+    server.request(
+        method,
+        path,
+        &[
+            ("Authorization", &authorization),
+            ("Content-Type", "application/json"),
+        ],
+        body,
+    )
+}
+
+#[test]
+fn test_repeated_rate_limit_violations_ban_client() {
+    let server = TestServer::start(&[("RATE_LIMIT_REQUESTS", "1"), ("BAN_MAX_STRIKES", "2")]);
+    let client = [("X-Forwarded-For", "198.51.100.1")];
+
+    assert_eq!(server.get("/version", &client).status, 200);
+    assert_eq!(server.get("/version", &client).status, 429);
+    assert_eq!(server.get("/version", &client).status, 429);
+
+    let response = server.get("/version", &client);
+    assert_eq!(response.status, 403);
+    assert!(response.header("retry-after").is_some());
+    assert_eq!(response.header("ratelimit-policy"), Some("1;w=60"));
+    assert_eq!(response.header("ratelimit-remaining"), Some("0"));
+    let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(body["error"], "Client banned.");
+
+    // Other clients are unaffected
+    let response = server.get("/version", &[("X-Forwarded-For", "198.51.100.2")]);
+    assert_eq!(response.status, 200);
+}
+
+#[test]
+fn test_invalid_input_counts_towards_ban() {
+    let server = TestServer::start(&[("BAN_MAX_STRIKES", "2")]);
+    let client = [("X-Forwarded-For", "198.51.100.1")];
+
+    assert_eq!(server.get("/lookup?ip=not-an-ip", &client).status, 400);
+    assert_eq!(server.get("/lookup?ip=not-an-ip", &client).status, 400);
+    assert_eq!(server.get("/version", &client).status, 403);
+}
+
+#[test]
+fn test_admin_api_disabled_without_token() {
+    let server = TestServer::start(&[]);
+    assert_eq!(admin(&server, "GET", "/admin/bans", "").status, 404);
+}
+
+#[test]
+fn test_admin_api_requires_token() {
+    let server = TestServer::start(&[("ADMIN_TOKEN", TOKEN)]);
+
+    let response = server.get("/admin/bans", &[]);
+    assert_eq!(response.status, 401);
+    assert!(response.header("www-authenticate").is_some());
+
+    let response = server.get("/admin/bans", &[("Authorization", "Bearer wrong")]);
+    assert_eq!(response.status, 401);
+
+    assert_eq!(admin(&server, "GET", "/admin/bans", "").status, 200);
+}
+
+#[test]
+fn test_admin_api_bans_and_unbans_networks() {
+    let server = TestServer::start(&[("ADMIN_TOKEN", TOKEN)]);
+    let client = [("X-Forwarded-For", "198.51.100.7")];
+
+    let response = admin(
+        &server,
+        "POST",
+        "/admin/bans",
+        r#"{"network": "198.51.100.0/24", "reason": "scraping"}"#,
+    );
+    assert_eq!(response.status, 201);
+    let ban: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(ban["network"], "198.51.100.0/24");
+    assert_eq!(ban["source"], "manual");
+    assert!(ban["expires_at"].is_null());
+
+    assert_eq!(server.get("/version", &client).status, 403);
+
+    let list: serde_json::Value =
+        serde_json::from_str(&admin(&server, "GET", "/admin/bans", "").body).unwrap();
+    assert_eq!(list["bans"][0]["reason"], "scraping");
+
+    let response = admin(&server, "DELETE", "/admin/bans?network=198.51.100.0/24", "");
+    assert_eq!(response.status, 200);
+    assert_eq!(server.get("/version", &client).status, 200);
+
+    let response = admin(&server, "DELETE", "/admin/bans?network=198.51.100.0/24", "");
+    assert_eq!(response.status, 404);
+
+    let response = admin(&server, "POST", "/admin/bans", r#"{"network": "nope"}"#);
+    assert_eq!(response.status, 400);
+}
+
+#[test]
+fn test_banned_operator_can_still_unban() {
+    let server = TestServer::start(&[("ADMIN_TOKEN", TOKEN)]);
+
+    let response = admin(
+        &server,
+        "POST",
+        "/admin/bans",
+        r#"{"network": "0.0.0.0/0"}"#,
+    );
+    assert_eq!(response.status, 201);
+    assert_eq!(server.get("/version", &[]).status, 403);
+
+    // The admin API still requires the token
+    assert_eq!(server.get("/admin/bans", &[]).status, 401);
+
+    let response = admin(&server, "DELETE", "/admin/bans?network=0.0.0.0/0", "");
+    assert_eq!(response.status, 200);
+    assert_eq!(server.get("/version", &[]).status, 200);
+}
+
+#[test]
+fn test_bans_survive_restart() {
+    let dir = std::env::temp_dir().join(format!("ip-api-bans-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("bans.json");
+    let path = path.to_str().unwrap();
+    let env = [("ADMIN_TOKEN", TOKEN), ("BAN_FILE", path)];
+    let client = [("X-Forwarded-For", "203.0.113.9")];
+
+    {
+        let server = TestServer::start(&env);
+        let response = admin(
+            &server,
+            "POST",
+            "/admin/bans",
+            r#"{"network": "203.0.113.9", "duration_secs": 3600}"#,
+        );
+        assert_eq!(response.status, 201);
+    }
+
+    let server = TestServer::start(&env);
+    let response = server.get("/version", &client);
+    assert_eq!(response.status, 403);
+    assert!(response.header("retry-after").is_some());
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_corrupt_ban_file_is_ignored() {
+    let dir = std::env::temp_dir().join(format!("ip-api-bad-bans-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("bans.json");
+    std::fs::write(&path, "{not json").unwrap();
+
+    let server = TestServer::start(&[("BAN_FILE", path.to_str().unwrap())]);
+    assert_eq!(server.get("/version", &[]).status, 200);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_strikes_and_bans_survive_restart_in_snapshot() {
+    let dir = std::env::temp_dir().join(format!("ip-api-snapshot-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("state.json");
+    let env = [
+        ("BAN_MAX_STRIKES", "2"),
+        ("SNAPSHOT_FILE", path.to_str().unwrap()),
+    ];
+    let banned = [("X-Forwarded-For", "198.51.100.1")];
+    let striking = [("X-Forwarded-For", "198.51.100.2")];
+
+    let server = TestServer::start(&env);
+    server.get("/lookup?ip=nope", &banned);
+    server.get("/lookup?ip=nope", &banned);
+    server.get("/lookup?ip=nope", &striking);
+    server.terminate();
+
+    let server = TestServer::start(&env);
+    assert_eq!(server.get("/version", &banned).status, 403);
+    assert_eq!(server.get("/version", &striking).status, 200);
+    server.get("/lookup?ip=nope", &striking);
+    assert_eq!(server.get("/version", &striking).status, 403);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,443 @@
+//! Stub DNS server answering queries from a fixed table
+//!
+//! Listens for UDP and TCP on the same local port, so it can be used as the
+//! server's only upstream through `DNS_SERVERS`.
+//!
+//! The table can also be served as a DNSSEC-signed root zone (see
+//! `start_signed`), for validating against the root key.
+
+use hickory_proto::dnssec::crypto::EcdsaSigningKey;
+use hickory_proto::dnssec::rdata::{DNSKEY, DNSSECRData, NSEC, RRSIG};
+use hickory_proto::dnssec::{Algorithm, DnssecSigner, SigningKey};
+use hickory_proto::op::{Message, ResponseCode};
+use hickory_proto::rr::rdata::{A, AAAA, NS, PTR, SOA};
+use hickory_proto::rr::{DNSClass, Name, RData, Record, RecordSet, RecordType};
+use std::collections::{BTreeSet, HashMap};
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener, TcpStream, UdpSocket};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// Records by owner name
+type Zone = HashMap<Name, Vec<RData>>;
+
+/// TTL of answers unless set with `start_with_ttl`
+const DEFAULT_TTL: u32 = 300;
+
+/// How answers are served
+#[derive(Clone, Copy)]
+struct Options {
+    /// Truncate every UDP answer
+    truncate_udp: bool,
+
+    /// Wait before sending each UDP answer
+    delay: Duration,
+
+    /// TTL of answer records
+    ttl: u32,
+
+    /// SOA minimum sent with negative answers, if any
+    soa_minimum: Option<u32>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            truncate_udp: false,
+            delay: Duration::ZERO,
+            ttl: DEFAULT_TTL,
+            soa_minimum: None,
+        }
+    }
+}
+
+/// Query counters, by transport
+#[derive(Default)]
+struct Counters {
+    udp: AtomicUsize,
+    tcp: AtomicUsize,
+    edns: AtomicUsize,
+}
+
+/// A running stub DNS server
+pub struct StubDns {
+    pub addr: SocketAddr,
+    counters: Arc<Counters>,
+}
+
+impl StubDns {
+    /// Serve PTR records for `(ip, hostname)` pairs
+    pub fn start(ptr: &[(&str, &str)]) -> Self {
+        Self::serve(zone(ptr, &[]), Options::default())
+    }
+
+    /// Like `start`, but wait `delay` before answering each query
+    pub fn start_slow(ptr: &[(&str, &str)], delay: Duration) -> Self {
+        let options = Options {
+            delay,
+            ..Options::default()
+        };
+        Self::serve(zone(ptr, &[]), options)
+    }
+
+    /// Like `start`, with answers valid for `ttl` seconds and negative
+    /// answers carrying an SOA record with the given minimum
+    pub fn start_with_ttl(ptr: &[(&str, &str)], ttl: u32, soa_minimum: u32) -> Self {
+        let options = Options {
+            ttl,
+            soa_minimum: Some(soa_minimum),
+            ..Options::default()
+        };
+        Self::serve(zone(ptr, &[]), options)
+    }
+
+    /// Serve PTR records and A/AAAA records for `(hostname, ip)` pairs
+    pub fn start_with_hosts(ptr: &[(&str, &str)], hosts: &[(&str, &str)]) -> Self {
+        Self::serve(zone(ptr, hosts), Options::default())
+    }
+
+    /// Serve PTR records and records of any type given as `(name, type,
+    /// data)` in zone file syntax, e.g. `("example.net", MX, "10 mail.example.net.")`
+    pub fn start_with_records(ptr: &[(&str, &str)], records: &[(&str, RecordType, &str)]) -> Self {
+        let mut zone = zone(ptr, &[]);
+        for (name, record_type, data) in records {
+            let rdata = RData::try_from_str(*record_type, data).unwrap();
+            zone.entry(fqdn(name)).or_default().push(rdata);
+        }
+        Self::serve(zone, Options::default())
+    }
+
+    /// Like `start_with_records`, but serve the records as a signed root
+    /// zone, returning the root key in trust anchor file syntax
+    ///
+    /// Names under `insecure.` are served unsigned, behind a delegation the
+    /// root proves has no DS record. Names under `bogus.` are signed with a
+    /// key that is not in the zone, so their signatures never validate.
+    /// Empty answers carry the zone's whole NSEC chain.
+    pub fn start_signed(
+        ptr: &[(&str, &str)],
+        records: &[(&str, RecordType, &str)],
+    ) -> (Self, String) {
+        let mut zone = zone(ptr, &[]);
+        for (name, record_type, data) in records {
+            let rdata = RData::try_from_str(*record_type, data).unwrap();
+            zone.entry(fqdn(name)).or_default().push(rdata);
+        }
+        let (zone, trust_anchor) = sign(zone);
+        (Self::serve(zone, Options::default()), trust_anchor)
+    }
+
+    /// Like `start`, but truncate every UDP answer so clients retry over TCP
+    pub fn start_truncating(ptr: &[(&str, &str)]) -> Self {
+        let options = Options {
+            truncate_udp: true,
+            ..Options::default()
+        };
+        Self::serve(zone(ptr, &[]), options)
+    }
+
+    fn serve(records: Zone, options: Options) -> Self {
+        let records = Arc::new(records);
+        let counters = Arc::new(Counters::default());
+
+        // Bind UDP first, then TCP on the same port
+        let (udp, tcp) = loop {
+            let udp = UdpSocket::bind("127.0.0.1:0").unwrap();
+            if let Ok(tcp) = TcpListener::bind(udp.local_addr().unwrap()) {
+                break (udp, tcp);
+            }
+        };
+        let addr = udp.local_addr().unwrap();
+
+        {
+            let (records, counters) = (records.clone(), counters.clone());
+            std::thread::spawn(move || {
+                let mut buf = [0u8; 4096];
+                while let Ok((len, peer)) = udp.recv_from(&mut buf) {
+                    counters.udp.fetch_add(1, Ordering::SeqCst);
+                    let Some(response) = answer(&buf[..len], &records, &counters, options, true)
+                    else {
+                        continue;
+                    };
+                    let udp = udp.try_clone().unwrap();
+                    std::thread::spawn(move || {
+                        std::thread::sleep(options.delay);
+                        let _ = udp.send_to(&response, peer);
+                    });
+                }
+            });
+        }
+
+        {
+            let counters = counters.clone();
+            std::thread::spawn(move || {
+                for stream in tcp.incoming().flatten() {
+                    let (records, counters) = (records.clone(), counters.clone());
+                    std::thread::spawn(move || serve_tcp(stream, &records, &counters, options));
+                }
+            });
+        }
+
+        Self { addr, counters }
+    }
+
+    /// Queries received over UDP
+    pub fn udp_queries(&self) -> usize {
+        self.counters.udp.load(Ordering::SeqCst)
+    }
+
+    /// Queries received over TCP
+    pub fn tcp_queries(&self) -> usize {
+        self.counters.tcp.load(Ordering::SeqCst)
+    }
+
+    /// Queries that carried an EDNS OPT record
+    pub fn edns_queries(&self) -> usize {
+        self.counters.edns.load(Ordering::SeqCst)
+    }
+}
+
+/// A UDP socket that accepts queries and never answers them
+pub struct SilentDns {
+    pub addr: SocketAddr,
+    _socket: UdpSocket,
+}
+
+impl SilentDns {
+    /// Bind a socket on a free local port
+    pub fn start() -> Self {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        Self {
+            addr: socket.local_addr().unwrap(),
+            _socket: socket,
+        }
+    }
+}
+
+fn serve_tcp(mut stream: TcpStream, records: &Zone, counters: &Counters, options: Options) {
+    loop {
+        let mut len = [0u8; 2];
+        if stream.read_exact(&mut len).is_err() {
+            return;
+        }
+        let mut query = vec![0u8; u16::from_be_bytes(len) as usize];
+        if stream.read_exact(&mut query).is_err() {
+            return;
+        }
+
+        counters.tcp.fetch_add(1, Ordering::SeqCst);
+        let options = Options {
+            truncate_udp: false,
+            ..options
+        };
+        let Some(response) = answer(&query, records, counters, options, false) else {
+            return;
+        };
+        let mut framed = (response.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(&response);
+        if stream.write_all(&framed).is_err() {
+            return;
+        }
+    }
+}
+
+fn answer(
+    query: &[u8],
+    records: &Zone,
+    counters: &Counters,
+    options: Options,
+    udp: bool,
+) -> Option<Vec<u8>> {
+    let query = Message::from_vec(query).ok()?;
+    if query.edns.is_some() {
+        counters.edns.fetch_add(1, Ordering::SeqCst);
+    }
+
+    let mut response = Message::response(query.metadata.id, query.metadata.op_code);
+    response.metadata.recursion_desired = query.metadata.recursion_desired;
+    response.metadata.recursion_available = true;
+    response.add_queries(query.queries.clone());
+
+    let question = query.queries.first()?;
+    match records.get(&question.name) {
+        Some(_) if options.truncate_udp => response.metadata.truncation = true,
+        Some(rdatas) => {
+            for rdata in rdatas {
+                if rdata.record_type() == question.query_type
+                    || covered_type(rdata) == Some(question.query_type)
+                {
+                    let record =
+                        Record::from_rdata(question.name.clone(), options.ttl, rdata.clone());
+                    response.add_answer(record);
+                }
+            }
+        }
+        None => response.metadata.response_code = ResponseCode::NXDomain,
+    }
+
+    // Deny the answer with the NSEC chain, unless the name is delegated
+    let delegated = records.iter().any(|(name, rdatas)| {
+        name != &question.name
+            && name.zone_of(&question.name)
+            && rdatas
+                .iter()
+                .any(|rdata| rdata.record_type() == RecordType::NS)
+    });
+    if response.answers.is_empty() && !delegated {
+        for (name, rdatas) in records.iter() {
+            for rdata in rdatas {
+                if rdata.record_type() == RecordType::NSEC
+                    || covered_type(rdata) == Some(RecordType::NSEC)
+                {
+                    response.add_authority(Record::from_rdata(
+                        name.clone(),
+                        options.ttl,
+                        rdata.clone(),
+                    ));
+                }
+            }
+        }
+    }
+
+    if response.answers.is_empty()
+        && let Some(minimum) = options.soa_minimum
+    {
+        let soa = SOA::new(
+            fqdn("ns.example"),
+            fqdn("hostmaster.example"),
+            1,
+            3600,
+            600,
+            86400,
+            minimum,
+        );
+        response.add_authority(Record::from_rdata(Name::root(), 3600, RData::SOA(soa)));
+    }
+
+    // Truncate what does not fit the client's UDP buffer, so it retries
+    // over TCP
+    let bytes = response.to_vec().ok()?;
+    let max_size = query
+        .edns
+        .as_ref()
+        .map_or(512, |edns| edns.max_payload().max(512));
+    if udp && bytes.len() > max_size as usize {
+        response.answers.clear();
+        response.authorities.clear();
+        response.metadata.truncation = true;
+        return response.to_vec().ok();
+    }
+    Some(bytes)
+}
+
+fn zone(ptr: &[(&str, &str)], hosts: &[(&str, &str)]) -> Zone {
+    let mut zone = Zone::new();
+    for (ip, host) in ptr {
+        let ip: IpAddr = ip.parse().unwrap();
+        let rdata = RData::PTR(PTR(fqdn(host)));
+        zone.entry(Name::from(ip)).or_default().push(rdata);
+    }
+    for (host, ip) in hosts {
+        let rdata = match ip.parse().unwrap() {
+            IpAddr::V4(ip) => RData::A(A(ip)),
+            IpAddr::V6(ip) => RData::AAAA(AAAA(ip)),
+        };
+        zone.entry(fqdn(host)).or_default().push(rdata);
+    }
+    zone
+}
+
+/// Sign `zone` as the root zone, returning it and the root key as a trust
+/// anchor
+fn sign(mut zone: Zone) -> (Zone, String) {
+    let (key, dnskey) = signing_key();
+    let signer = DnssecSigner::new(
+        dnskey.clone(),
+        key,
+        Name::root(),
+        Duration::from_secs(86400),
+    );
+    let (rogue_key, rogue_dnskey) = signing_key();
+    let rogue = DnssecSigner::new(
+        rogue_dnskey,
+        rogue_key,
+        Name::root(),
+        Duration::from_secs(86400),
+    );
+
+    let dnskey = RData::DNSSEC(DNSSECRData::DNSKEY(dnskey));
+    let trust_anchor = Record::from_rdata(Name::root(), DEFAULT_TTL, dnskey.clone()).to_string();
+    zone.entry(Name::root()).or_default().push(dnskey);
+
+    // An unsigned zone is delegated without a DS record
+    let (insecure, bogus) = (fqdn("insecure"), fqdn("bogus"));
+    let delegation = RData::NS(NS(fqdn("ns.insecure")));
+    zone.entry(insecure.clone()).or_default().push(delegation);
+
+    // Chain the names of the signed zone in canonical order
+    let mut names: Vec<Name> = zone
+        .keys()
+        .filter(|name| *name == &insecure || !insecure.zone_of(name))
+        .cloned()
+        .collect();
+    names.sort();
+    for (i, name) in names.iter().enumerate() {
+        let next = names[(i + 1) % names.len()].clone();
+        let types: BTreeSet<RecordType> = zone[name].iter().map(RData::record_type).collect();
+        let nsec = NSEC::new_cover_self(next, types);
+        zone.get_mut(name)
+            .unwrap()
+            .push(RData::DNSSEC(DNSSECRData::NSEC(nsec)));
+    }
+
+    // Sign every RRset the zone is authoritative for
+    let inception = time::OffsetDateTime::now_utc() - time::Duration::hours(1);
+    for name in names {
+        let types: BTreeSet<RecordType> = zone[&name].iter().map(RData::record_type).collect();
+        for record_type in types {
+            if name == insecure && record_type == RecordType::NS {
+                continue;
+            }
+            let mut rrset = RecordSet::with_ttl(name.clone(), record_type, DEFAULT_TTL);
+            for rdata in zone[&name]
+                .iter()
+                .filter(|r| r.record_type() == record_type)
+            {
+                rrset.add_rdata(rdata.clone());
+            }
+            let signer = if bogus.zone_of(&name) && record_type != RecordType::NSEC {
+                &rogue
+            } else {
+                &signer
+            };
+            let rrsig = RRSIG::from_rrset(&rrset, DNSClass::IN, inception, signer).unwrap();
+            zone.get_mut(&name)
+                .unwrap()
+                .push(RData::DNSSEC(DNSSECRData::RRSIG(rrsig)));
+        }
+    }
+
+    (zone, trust_anchor)
+}
+
+/// A new ECDSA P-256 zone signing key and its DNSKEY
+fn signing_key() -> (Box<dyn SigningKey>, DNSKEY) {
+    let algorithm = Algorithm::ECDSAP256SHA256;
+    let pkcs8 = EcdsaSigningKey::generate_pkcs8(algorithm).unwrap();
+    let key = EcdsaSigningKey::from_pkcs8(&pkcs8, algorithm).unwrap();
+    let dnskey = DNSKEY::from_key(&key.to_public_key().unwrap());
+    (Box::new(key), dnskey)
+}
+
+/// Type an RRSIG covers, if `rdata` is one
+fn covered_type(rdata: &RData) -> Option<RecordType> {
+    match rdata {
+        RData::DNSSEC(DNSSECRData::RRSIG(rrsig)) => Some(rrsig.input().type_covered),
+        _ => None,
+    }
+}
+
+fn fqdn(host: &str) -> Name {
+    Name::from_ascii(format!("{}.", host)).unwrap()
+}
@@ -0,0 +1,189 @@
+//! Shared helpers for integration tests
+//!
+//! Each test starts the real `ip-api` binary on a free local port and talks
+//! to it over plain HTTP/1.1.
+
+#![allow(dead_code)]
+
+pub mod dns;
+
+use std::collections::HashMap;
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// A running `ip-api` process, killed on drop
+pub struct TestServer {
+    pub port: u16,
+    child: Child,
+}
+
+impl TestServer {
+    /// Start the server with the given environment variables
+    pub fn start(env: &[(&str, &str)]) -> Self {
+        Self::start_with_args(env, &[])
+    }
+
+    /// Start the server with extra command line arguments
+    pub fn start_with_args(env: &[(&str, &str)], args: &[&str]) -> Self {
+        let port = free_port();
+
+        let child = Command::new(env!("CARGO_BIN_EXE_ip-api"))
+            .arg("--port")
+            .arg(port.to_string())
+            .args(args)
+            .env("RUST_LOG", "warn")
+            .envs(env.iter().copied())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("failed to start ip-api");
+
+        let server = Self { port, child };
+        server.wait_until_ready();
+        server
+    }
+
+    fn wait_until_ready(&self) {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if TcpStream::connect(("127.0.0.1", self.port)).is_ok() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        panic!("ip-api did not start listening on port {}", self.port);
+    }
+
+    /// Send a GET request with extra headers
+    pub fn get(&self, path: &str, headers: &[(&str, &str)]) -> Response {
+        self.request("GET", path, headers, "")
+    }
+
+    /// Send a request and read the full response
+    pub fn request(
+        &self,
+        method: &str,
+        path: &str,
+        headers: &[(&str, &str)],
+        body: &str,
+    ) -> Response {
+        let mut stream = TcpStream::connect(("127.0.0.1", self.port)).expect("connect");
+        stream
+            .set_read_timeout(Some(Duration::from_secs(30)))
+            .unwrap();
+
+        let mut request = format!(
+            "{} {} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Length: {}\r\n",
+            method,
+            path,
+            body.len()
+        );
+        for (name, value) in headers {
+            request.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        request.push_str("\r\n");
+        request.push_str(body);
+
+        stream.write_all(request.as_bytes()).expect("write request");
+        read_response(&mut stream)
+    }
+}
+
+impl TestServer {
+    /// Stop the server with SIGTERM and wait for it to shut down gracefully
+    pub fn terminate(mut self) {
+        let status = Command::new("kill")
+            .arg("-TERM")
+            .arg(self.child.id().to_string())
+            .status()
+            .expect("failed to run kill");
+        assert!(status.success(), "failed to signal ip-api");
+        let status = self.child.wait().expect("failed to wait for ip-api");
+        assert!(status.success(), "ip-api exited with {}", status);
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// A parsed HTTP response
+#[derive(Debug)]
+pub struct Response {
+    pub status: u16,
+    pub headers: HashMap<String, String>,
+    pub body: String,
+}
+
+impl Response {
+    /// Get a header value by lowercase name
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name).map(String::as_str)
+    }
+}
+
+/// Read an HTTP/1.1 response from a stream closed by the server
+pub fn read_response(stream: &mut TcpStream) -> Response {
+    let mut raw = Vec::new();
+    stream.read_to_end(&mut raw).expect("read response");
+    let raw = String::from_utf8_lossy(&raw);
+
+    let (head, body) = raw.split_once("\r\n\r\n").expect("malformed response");
+    let mut lines = head.lines();
+    let status = lines
+        .next()
+        .and_then(|line| line.split(' ').nth(1))
+        .and_then(|code| code.parse().ok())
+        .expect("malformed status line");
+
+    let headers = lines
+        .filter_map(|line| line.split_once(':'))
+        .map(|(name, value)| (name.trim().to_lowercase(), value.trim().to_string()))
+        .collect::<HashMap<_, _>>();
+
+    let body = if headers.get("transfer-encoding").map(String::as_str) == Some("chunked") {
+        decode_chunked(body)
+    } else {
+        body.to_string()
+    };
+
+    Response {
+        status,
+        headers,
+        body,
+    }
+}
+
+fn decode_chunked(mut body: &str) -> String {
+    let mut out = String::new();
+    while let Some((size, rest)) = body.split_once("\r\n") {
+        let size = usize::from_str_radix(size.trim(), 16).unwrap_or(0);
+        if size == 0 {
+            break;
+        }
+        out.push_str(&rest[..size]);
+        body = &rest[size + 2..];
+    }
+    out
+}
+
+/// Find a free local TCP port
+pub fn free_port() -> u16 {
+    TcpListener::bind("127.0.0.1:0")
+        .and_then(|listener| listener.local_addr())
+        .map(|addr| addr.port())
+        .expect("no free port")
+}
+
+/// Find a free local UDP port
+pub fn free_udp_port() -> u16 {
+    std::net::UdpSocket::bind("127.0.0.1:0")
+        .and_then(|socket| socket.local_addr())
+        .map(|addr| addr.port())
+        .expect("no free port")
+}
@@ -0,0 +1,737 @@
+//! DNS integration tests against a local stub nameserver
+
+mod common;
+
+use common::TestServer;
+use common::dns::{SilentDns, StubDns};
+use hickory_proto::rr::RecordType;
+use std::time::{Duration, Instant};
+
+const PTR: &[(&str, &str)] = &[
+    ("192.0.2.10", "host.example.net"),
+    ("192.0.2.20", "spoofed.example.net"),
+    ("192.0.2.30", "stale.example.net"),
+    ("192.0.2.30", "current.example.net"),
+    ("2001:db8::10", "v6.example.net"),
+];
+
+const HOSTS: &[(&str, &str)] = &[
+    ("host.example.net", "192.0.2.10"),
+    ("spoofed.example.net", "198.51.100.1"),
+    ("current.example.net", "192.0.2.30"),
+    ("v6.example.net", "2001:db8::10"),
+];
+
+fn rdns(server: &TestServer, ip: &str) -> serde_json::Value {
+    let response = server.get(&format!("/lookup?ip={}", ip), &[]);
+    assert_eq!(response.status, 200);
+    let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    body["rDNS"].clone()
+}
+
+#[test]
+fn test_reverse_lookup_uses_configured_server() {
+    let dns = StubDns::start(PTR);
+    let server = TestServer::start(&[("DNS_SERVERS", &dns.addr.to_string())]);
+
+    assert_eq!(rdns(&server, "192.0.2.10"), "host.example.net");
+    assert_eq!(rdns(&server, "2001:db8::10"), "v6.example.net");
+    assert!(rdns(&server, "192.0.2.11").is_null());
+
+    assert!(dns.udp_queries() >= 3);
+    assert_eq!(dns.tcp_queries(), 0);
+    assert!(dns.edns_queries() >= 3);
+}
+
+#[test]
+fn test_reverse_lookup_over_tcp() {
+    let dns = StubDns::start(PTR);
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_PROTOCOL", "tcp"),
+    ]);
+
+    assert_eq!(rdns(&server, "192.0.2.10"), "host.example.net");
+    assert_eq!(dns.udp_queries(), 0);
+    assert!(dns.tcp_queries() >= 1);
+}
+
+#[test]
+fn test_truncated_answers_are_retried_over_tcp() {
+    let dns = StubDns::start_truncating(PTR);
+    let server = TestServer::start(&[("DNS_SERVERS", &dns.addr.to_string())]);
+
+    assert_eq!(rdns(&server, "192.0.2.10"), "host.example.net");
+    assert!(dns.udp_queries() >= 1);
+    assert!(dns.tcp_queries() >= 1);
+}
+
+#[test]
+fn test_edns_can_be_disabled() {
+    let dns = StubDns::start(PTR);
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_EDNS", "false"),
+    ]);
+
+    assert_eq!(rdns(&server, "192.0.2.10"), "host.example.net");
+    assert_eq!(dns.edns_queries(), 0);
+}
+
+#[test]
+fn test_unresponsive_server_times_out() {
+    let dns = SilentDns::start();
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_TIMEOUT_MS", "200"),
+        ("DNS_ATTEMPTS", "1"),
+    ]);
+
+    let started = Instant::now();
+    assert!(rdns(&server, "192.0.2.10").is_null());
+    assert!(started.elapsed() < Duration::from_secs(3));
+}
+
+#[test]
+fn test_lookup_deadline_caps_retries() {
+    let dns = SilentDns::start();
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_TIMEOUT_MS", "5000"),
+        ("DNS_ATTEMPTS", "3"),
+        ("DNS_DEADLINE_MS", "300"),
+    ]);
+
+    let started = Instant::now();
+    let body = lookup(&server, "ip=192.0.2.10");
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert!(body["rDNS"].is_null());
+    assert_eq!(body["rDNS-Status"], "timeout");
+}
+
+#[test]
+fn test_breaker_skips_lookups_while_upstream_is_down() {
+    let dns = SilentDns::start();
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_DEADLINE_MS", "200"),
+        ("DNS_BREAKER_FAILURES", "2"),
+    ]);
+
+    let health = |server: &TestServer| -> serde_json::Value {
+        let response = server.get("/health", &[]);
+        assert_eq!(response.status, 200);
+        serde_json::from_str(&response.body).unwrap()
+    };
+    assert_eq!(health(&server)["dns_breaker"]["state"], "closed");
+
+    assert_eq!(lookup(&server, "ip=192.0.2.10")["rDNS-Status"], "timeout");
+    assert_eq!(lookup(&server, "ip=192.0.2.20")["rDNS-Status"], "timeout");
+
+    // Open: further lookups are skipped without waiting for the deadline
+    let started = Instant::now();
+    let body = lookup(&server, "ip=192.0.2.30");
+    assert!(started.elapsed() < Duration::from_millis(150));
+    assert!(body["rDNS"].is_null());
+    assert_eq!(body["rDNS-Status"], "circuit_open");
+
+    let breaker = &health(&server)["dns_breaker"];
+    assert_eq!(breaker["state"], "open");
+    assert_eq!(breaker["consecutive_failures"], 2);
+    assert_eq!(breaker["retry_in_seconds"], 30);
+}
+
+#[test]
+fn test_rdns_status_reports_outcome() {
+    let dns = StubDns::start(PTR);
+    let server = TestServer::start(&[("DNS_SERVERS", &dns.addr.to_string())]);
+
+    assert_eq!(lookup(&server, "ip=192.0.2.10")["rDNS-Status"], "ok");
+    assert_eq!(lookup(&server, "ip=192.0.2.40")["rDNS-Status"], "nxdomain");
+
+    let response = server.get("/?format=text", &[]);
+    assert!(response.body.contains("rDNS-Status: "));
+}
+
+#[test]
+fn test_invalid_dns_server_fails_startup() {
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_ip-api"))
+        .arg("--port")
+        .arg(common::free_port().to_string())
+        .env("DNS_SERVERS", "dns.example")
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+fn lookup(server: &TestServer, query: &str) -> serde_json::Value {
+    let response = server.get(&format!("/lookup?{}", query), &[]);
+    assert_eq!(response.status, 200);
+    serde_json::from_str(&response.body).unwrap()
+}
+
+#[test]
+fn test_lookup_verifies_reverse_dns_on_request() {
+    let dns = StubDns::start_with_hosts(PTR, HOSTS);
+    let server = TestServer::start(&[("DNS_SERVERS", &dns.addr.to_string())]);
+
+    let body = lookup(&server, "ip=192.0.2.10");
+    assert_eq!(body["rDNS-Names"], serde_json::json!(["host.example.net"]));
+    assert!(body.get("rDNS-Verified").is_none());
+
+    let body = lookup(&server, "ip=192.0.2.10&verify=1");
+    assert_eq!(body["rDNS"], "host.example.net");
+    assert_eq!(body["rDNS-Verified"], true);
+
+    let body = lookup(&server, "ip=2001:db8::10&verify=1");
+    assert_eq!(body["rDNS-Verified"], true);
+}
+
+#[test]
+fn test_chain_resolves_only_the_client_and_trusted_hops() {
+    let dns = StubDns::start(PTR);
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("TRUSTED_PROXIES", "127.0.0.1/32,192.0.2.30/32"),
+    ]);
+
+    let response = server.get(
+        "/?chain=1",
+        &[
+            ("Accept", "application/json"),
+            ("X-Forwarded-For", "192.0.2.20, 192.0.2.10, 192.0.2.30"),
+        ],
+    );
+    assert_eq!(response.status, 200);
+    let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    let chain = body["Chain"].as_array().unwrap();
+    assert_eq!(chain.len(), 4);
+
+    // A hop beyond the client is only what the client claims, so it is not looked up
+    assert_eq!(chain[0]["IP"], "192.0.2.20");
+    assert_eq!(chain[0]["Trusted"], false);
+    assert!(chain[0]["rDNS"].is_null());
+
+    assert_eq!(chain[1]["IP"], "192.0.2.10");
+    assert_eq!(chain[1]["rDNS"], "host.example.net");
+
+    assert_eq!(chain[2]["IP"], "192.0.2.30");
+    assert_eq!(chain[2]["Trusted"], true);
+    assert!(chain[2]["rDNS"].is_string());
+}
+
+#[test]
+fn test_spoofed_reverse_dns_is_not_verified() {
+    let dns = StubDns::start_with_hosts(PTR, HOSTS);
+    let server = TestServer::start(&[("DNS_SERVERS", &dns.addr.to_string())]);
+
+    let body = lookup(&server, "ip=192.0.2.20&verify=1");
+    assert_eq!(body["rDNS"], "spoofed.example.net");
+    assert_eq!(body["rDNS-Verified"], false);
+
+    // No PTR record leaves nothing to confirm
+    let body = lookup(&server, "ip=192.0.2.40&verify=1");
+    assert!(body["rDNS"].is_null());
+    assert_eq!(body["rDNS-Names"], serde_json::json!([]));
+    assert_eq!(body["rDNS-Verified"], "unknown");
+}
+
+#[test]
+fn test_any_ptr_name_can_verify() {
+    let dns = StubDns::start_with_hosts(PTR, HOSTS);
+    let server = TestServer::start(&[("DNS_SERVERS", &dns.addr.to_string())]);
+
+    let body = lookup(&server, "ip=192.0.2.30&verify=1");
+    let mut names: Vec<String> = serde_json::from_value(body["rDNS-Names"].clone()).unwrap();
+    names.sort();
+    assert_eq!(names, ["current.example.net", "stale.example.net"]);
+    assert_eq!(body["rDNS-Verified"], true);
+}
+
+#[test]
+fn test_client_reverse_dns_is_verified_on_request() {
+    let dns = StubDns::start_with_hosts(PTR, HOSTS);
+    let server = TestServer::start(&[("DNS_SERVERS", &dns.addr.to_string())]);
+
+    let response = server.get("/", &[("X-Forwarded-For", "192.0.2.10")]);
+    let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(body["rDNS"], "host.example.net");
+    assert!(body.get("rDNS-Verified").is_none());
+
+    let response = server.get("/?verify=1", &[("X-Forwarded-For", "192.0.2.10")]);
+    let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(body["rDNS-Verified"], true);
+
+    let response = server.get(
+        "/?format=text&verify=1",
+        &[("X-Forwarded-For", "192.0.2.20")],
+    );
+    assert!(response.body.contains("rDNS-Names: spoofed.example.net"));
+    assert!(response.body.contains("rDNS-Verified: false"));
+}
+
+#[test]
+fn test_failed_lookup_is_unknown() {
+    let dns = SilentDns::start();
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_TIMEOUT_MS", "200"),
+        ("DNS_ATTEMPTS", "1"),
+    ]);
+
+    let body = lookup(&server, "ip=192.0.2.10&verify=1");
+    assert!(body.get("rDNS-Names").is_none());
+    assert_eq!(body["rDNS-Verified"], "unknown");
+}
+
+fn dns_cache_stats(server: &TestServer) -> serde_json::Value {
+    let response = server.get("/metrics", &[]);
+    assert_eq!(response.status, 200);
+    let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    body["dns_cache"].clone()
+}
+
+#[test]
+fn test_dns_cache_is_bounded_and_reports_stats() {
+    let dns = StubDns::start(PTR);
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_CACHE_MAX_ENTRIES", "2"),
+    ]);
+
+    rdns(&server, "192.0.2.10");
+    rdns(&server, "192.0.2.10");
+    rdns(&server, "192.0.2.20");
+    rdns(&server, "192.0.2.30");
+    let queries = dns.udp_queries();
+
+    // The oldest entry was evicted and has to be looked up again
+    assert_eq!(rdns(&server, "192.0.2.10"), "host.example.net");
+    assert_eq!(dns.udp_queries(), queries + 1);
+
+    let stats = dns_cache_stats(&server);
+    assert_eq!(stats["size"], 2);
+    assert_eq!(stats["capacity"], 2);
+    assert_eq!(stats["hits"], 1);
+    assert_eq!(stats["misses"], 4);
+    assert_eq!(stats["evictions"], 2);
+}
+
+#[test]
+fn test_negative_results_expire_separately() {
+    let dns = StubDns::start(PTR);
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_CACHE_NEGATIVE_TTL_SECS", "0"),
+    ]);
+
+    rdns(&server, "192.0.2.10");
+    rdns(&server, "192.0.2.40");
+    let queries = dns.udp_queries();
+
+    // The answer stays cached, the missing record is asked for again
+    rdns(&server, "192.0.2.10");
+    assert_eq!(dns.udp_queries(), queries);
+    rdns(&server, "192.0.2.40");
+    assert_eq!(dns.udp_queries(), queries + 1);
+
+    let stats = dns_cache_stats(&server);
+    assert_eq!(stats["expirations"], 1);
+}
+
+#[test]
+fn test_concurrent_lookups_are_coalesced() {
+    // Below the resolver's 333ms UDP retransmit interval
+    let dns = StubDns::start_slow(PTR, Duration::from_millis(250));
+    let server = std::sync::Arc::new(TestServer::start(&[("DNS_SERVERS", &dns.addr.to_string())]));
+
+    let requests: Vec<_> = (0..10)
+        .map(|_| {
+            let server = server.clone();
+            std::thread::spawn(move || rdns(&server, "192.0.2.10"))
+        })
+        .collect();
+    for request in requests {
+        assert_eq!(request.join().unwrap(), "host.example.net");
+    }
+
+    assert_eq!(dns.udp_queries(), 1);
+    let stats = dns_cache_stats(&server);
+    assert_eq!(stats["coalesced"], 9);
+    assert_eq!(stats["misses"], 10);
+}
+
+#[test]
+fn test_expired_entries_are_served_stale() {
+    let dns = StubDns::start_slow(PTR, Duration::from_millis(250));
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_CACHE_MAX_TTL_SECS", "0"),
+        ("DNS_CACHE_STALE_SECS", "60"),
+    ]);
+
+    assert_eq!(rdns(&server, "192.0.2.10"), "host.example.net");
+
+    // Expired at once, so served stale without waiting for the upstream
+    let started = Instant::now();
+    assert_eq!(rdns(&server, "192.0.2.10"), "host.example.net");
+    assert!(started.elapsed() < Duration::from_millis(200));
+
+    std::thread::sleep(Duration::from_millis(400));
+    assert_eq!(dns.udp_queries(), 2);
+    assert_eq!(dns_cache_stats(&server)["stale_hits"], 1);
+}
+
+#[test]
+fn test_cache_honours_record_ttls() {
+    let dns = StubDns::start_with_ttl(PTR, 120, 45);
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_CACHE_TTL_SECS", "300"),
+        ("DNS_CACHE_NEGATIVE_TTL_SECS", "60"),
+    ]);
+
+    let body = lookup(&server, "ip=192.0.2.10");
+    assert_eq!(body["rDNS-TTL"], 120);
+
+    // Negative answers are cached for the SOA minimum
+    let body = lookup(&server, "ip=192.0.2.40");
+    assert_eq!(body["rDNS-TTL"], 45);
+
+    // Later requests see the time left
+    std::thread::sleep(Duration::from_millis(1100));
+    let body = lookup(&server, "ip=192.0.2.10");
+    let ttl = body["rDNS-TTL"].as_u64().unwrap();
+    assert!((110..120).contains(&ttl));
+}
+
+#[test]
+fn test_record_ttls_are_clamped() {
+    let dns = StubDns::start_with_ttl(PTR, 5, 86400);
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_CACHE_MIN_TTL_SECS", "30"),
+        ("DNS_CACHE_MAX_TTL_SECS", "600"),
+    ]);
+
+    assert_eq!(lookup(&server, "ip=192.0.2.10")["rDNS-TTL"], 30);
+    assert_eq!(lookup(&server, "ip=192.0.2.40")["rDNS-TTL"], 600);
+
+    let response = server.get("/?format=text", &[]);
+    assert!(response.body.contains("rDNS-TTL: "));
+}
+
+#[test]
+fn test_dns_cache_survives_restart_in_snapshot() {
+    let dir = std::env::temp_dir().join(format!("ip-api-dns-snapshot-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("state.json");
+    let dns = StubDns::start_with_ttl(PTR, 120, 45);
+    let addr = dns.addr.to_string();
+    let env = [
+        ("DNS_SERVERS", addr.as_str()),
+        ("SNAPSHOT_FILE", path.to_str().unwrap()),
+    ];
+
+    let server = TestServer::start(&env);
+    assert_eq!(rdns(&server, "192.0.2.10"), "host.example.net");
+    server.terminate();
+    let queries = dns.udp_queries();
+
+    std::thread::sleep(Duration::from_millis(1100));
+    let server = TestServer::start(&env);
+    let body = lookup(&server, "ip=192.0.2.10");
+    assert_eq!(body["rDNS"], "host.example.net");
+    let ttl = body["rDNS-TTL"].as_u64().unwrap();
+    assert!((100..120).contains(&ttl));
+    assert_eq!(dns.udp_queries(), queries);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Needs a running `redis-server`, e.g. `TEST_REDIS_URL=redis://127.0.0.1:6379`
+#[test]
+#[ignore = "needs TEST_REDIS_URL"]
+fn test_replicas_share_answers_through_redis() {
+    let url = std::env::var("TEST_REDIS_URL").expect("TEST_REDIS_URL is not set");
+    let dns = StubDns::start_with_ttl(PTR, 120, 45);
+    let addr = dns.addr.to_string();
+    let prefix = format!("ip-api-test:dns-replicas:{}", std::process::id());
+    let env = [
+        ("DNS_SERVERS", addr.as_str()),
+        ("DNS_CACHE_REDIS_URL", url.as_str()),
+        ("DNS_CACHE_REDIS_PREFIX", prefix.as_str()),
+    ];
+    let first = TestServer::start(&env);
+    let second = TestServer::start(&env);
+
+    assert_eq!(rdns(&first, "192.0.2.10"), "host.example.net");
+    let queries = dns.udp_queries();
+
+    // Give the first replica time to write its answer to Redis
+    std::thread::sleep(Duration::from_millis(200));
+    let body = lookup(&second, "ip=192.0.2.10");
+    assert_eq!(body["rDNS"], "host.example.net");
+    assert!(body["rDNS-TTL"].as_u64().unwrap() > 110);
+    assert_eq!(dns.udp_queries(), queries);
+
+    let l2 = &dns_cache_stats(&second)["l2"];
+    assert_eq!(l2["hits"], 1);
+    assert_eq!(l2["errors"], 0);
+}
+
+#[test]
+fn test_unreachable_redis_falls_back_to_lookups() {
+    let dns = StubDns::start(PTR);
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_CACHE_REDIS_URL", "redis://127.0.0.1:1"),
+    ]);
+
+    assert_eq!(rdns(&server, "192.0.2.10"), "host.example.net");
+    assert_eq!(rdns(&server, "192.0.2.10"), "host.example.net");
+
+    let stats = dns_cache_stats(&server);
+    assert_eq!(stats["hits"], 1);
+    assert_eq!(stats["l2"]["misses"], 1);
+    assert!(stats["l2"]["errors"].as_u64().unwrap() >= 1);
+}
+
+const RECORDS: &[(&str, RecordType, &str)] = &[
+    ("example.net", RecordType::A, "192.0.2.1"),
+    ("example.net", RecordType::AAAA, "2001:db8::1"),
+    ("example.net", RecordType::MX, "10 mail.example.net."),
+    ("example.net", RecordType::MX, "20 backup.example.net."),
+    ("example.net", RecordType::TXT, "\"v=spf1 \" \"-all\""),
+    ("example.net", RecordType::NS, "ns1.example.net."),
+    (
+        "example.net",
+        RecordType::SOA,
+        "ns1.example.net. hostmaster.example.net. 2024010101 7200 3600 1209600 300",
+    ),
+    ("example.net", RecordType::CAA, "0 issue \"ca.example.org\""),
+    (
+        "_sip._tcp.example.net",
+        RecordType::SRV,
+        "5 10 5060 sip.example.net.",
+    ),
+    ("www.example.net", RecordType::CNAME, "example.net."),
+];
+
+fn query_dns(server: &TestServer, query: &str) -> serde_json::Value {
+    let response = server.get(&format!("/dns?{}", query), &[]);
+    assert_eq!(response.status, 200);
+    serde_json::from_str(&response.body).unwrap()
+}
+
+#[test]
+fn test_dns_query_returns_structured_records() {
+    let dns = StubDns::start_with_records(PTR, RECORDS);
+    let server = TestServer::start(&[("DNS_SERVERS", &dns.addr.to_string())]);
+
+    let body = query_dns(&server, "name=Example.NET.&type=mx");
+    assert_eq!(body["Name"], "example.net");
+    assert_eq!(body["Type"], "MX");
+    assert_eq!(body["Status"], "ok");
+    assert_eq!(body["TTL"], 300);
+    assert!(body.get("DNSSEC").is_none());
+    let mut records = body["Records"].as_array().unwrap().clone();
+    records.sort_by_key(|record| record["Preference"].as_u64());
+    assert_eq!(records[0]["Data"], "10 mail.example.net");
+    assert_eq!(records[0]["Exchange"], "mail.example.net");
+    assert_eq!(records[1]["Preference"], 20);
+
+    let srv = &query_dns(&server, "name=_sip._tcp.example.net&type=SRV")["Records"][0];
+    assert_eq!(srv["Data"], "5 10 5060 sip.example.net");
+    assert_eq!(srv["Port"], 5060);
+    assert_eq!(srv["Target"], "sip.example.net");
+
+    let soa = &query_dns(&server, "name=example.net&type=SOA")["Records"][0];
+    assert_eq!(soa["MName"], "ns1.example.net");
+    assert_eq!(soa["Serial"], 2024010101u64);
+    assert_eq!(soa["Minimum"], 300);
+
+    let caa = &query_dns(&server, "name=example.net&type=CAA")["Records"][0];
+    assert_eq!(caa["Data"], "0 issue \"ca.example.org\"");
+    assert_eq!(caa["Tag"], "issue");
+    assert_eq!(caa["Value"], "ca.example.org");
+
+    // Types with a single value only have Data
+    let txt = &query_dns(&server, "name=example.net&type=TXT")["Records"][0];
+    assert_eq!(txt, &serde_json::json!({ "Data": "v=spf1 -all" }));
+    let records = |query| query_dns(&server, query)["Records"][0]["Data"].clone();
+    assert_eq!(records("name=example.net"), "192.0.2.1");
+    assert_eq!(records("name=example.net&type=AAAA"), "2001:db8::1");
+    assert_eq!(records("name=example.net&type=NS"), "ns1.example.net");
+    assert_eq!(records("name=www.example.net&type=CNAME"), "example.net");
+    assert_eq!(records("name=192.0.2.10&type=PTR"), "host.example.net");
+    assert_eq!(
+        records("name=10.2.0.192.in-addr.arpa&type=PTR"),
+        "host.example.net"
+    );
+
+    let missing = query_dns(&server, "name=missing.example.net&type=MX");
+    assert_eq!(missing["Status"], "nxdomain");
+    assert_eq!(missing["Records"], serde_json::json!([]));
+}
+
+#[test]
+fn test_dns_query_as_plain_text() {
+    let dns = StubDns::start_with_records(PTR, RECORDS);
+    let server = TestServer::start(&[("DNS_SERVERS", &dns.addr.to_string())]);
+
+    let response = server.get("/dns?name=example.net&type=NS&format=text", &[]);
+    assert_eq!(response.status, 200);
+    assert_eq!(
+        response.body,
+        "Name: example.net\nType: NS\nStatus: ok\nTTL: 300\nRecords:\n  ns1.example.net"
+    );
+}
+
+#[test]
+fn test_dns_query_rejects_invalid_input() {
+    let server = TestServer::start(&[]);
+
+    for query in [
+        "name=",
+        "name=exa%20mple.net",
+        "name=example..net",
+        "name=-example.net",
+        "name=example.net%00",
+        "name=example.net&type=ANY",
+        "name=example.net&type=AXFR",
+        "name=2001:db8::1&type=A",
+        "type=MX",
+    ] {
+        let response = server.get(&format!("/dns?{}", query), &[]);
+        assert_eq!(response.status, 400, "{}", query);
+    }
+}
+
+#[test]
+fn test_dns_query_shares_the_dns_cache() {
+    let dns = StubDns::start_with_records(PTR, RECORDS);
+    let server = TestServer::start(&[("DNS_SERVERS", &dns.addr.to_string())]);
+
+    query_dns(&server, "name=example.net&type=MX");
+    assert_eq!(rdns(&server, "192.0.2.10"), "host.example.net");
+    let queries = dns.udp_queries();
+
+    // Same answers, from the cache
+    let body = query_dns(&server, "name=EXAMPLE.net&type=MX");
+    assert_eq!(body["Records"].as_array().unwrap().len(), 2);
+    let body = query_dns(&server, "name=192.0.2.10&type=PTR");
+    assert_eq!(body["Records"][0]["Data"], "host.example.net");
+    assert_eq!(dns.udp_queries(), queries);
+    assert_eq!(dns_cache_stats(&server)["hits"], 2);
+}
+
+const SIGNED_RECORDS: &[(&str, RecordType, &str)] = &[
+    ("secure.example", RecordType::A, "192.0.2.1"),
+    ("host.insecure", RecordType::A, "192.0.2.2"),
+    ("host.bogus", RecordType::A, "192.0.2.3"),
+];
+
+/// Write a trust anchor to a file unique to this test process
+fn trust_anchor_file(name: &str, trust_anchor: &str) -> std::path::PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "ip-api-trust-anchor-{}-{}",
+        name,
+        std::process::id()
+    ));
+    std::fs::write(&path, trust_anchor).unwrap();
+    path
+}
+
+#[test]
+fn test_dnssec_status_is_reported() {
+    let (dns, trust_anchor) = StubDns::start_signed(PTR, SIGNED_RECORDS);
+    let path = trust_anchor_file("status", &trust_anchor);
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_TRUST_ANCHOR_FILE", path.to_str().unwrap()),
+    ]);
+
+    let body = query_dns(&server, "name=secure.example");
+    assert_eq!(body["Status"], "ok");
+    assert_eq!(body["DNSSEC"], "secure");
+    assert_eq!(body["Records"][0]["Data"], "192.0.2.1");
+
+    // Denials are proven by the NSEC chain
+    let body = query_dns(&server, "name=secure.example&type=TXT");
+    assert_eq!(body["Status"], "nxdomain");
+    assert_eq!(body["DNSSEC"], "secure");
+    let body = query_dns(&server, "name=missing.example");
+    assert_eq!(body["Status"], "nxdomain");
+    assert_eq!(body["DNSSEC"], "secure");
+
+    let body = query_dns(&server, "name=host.insecure");
+    assert_eq!(body["Records"][0]["Data"], "192.0.2.2");
+    assert_eq!(body["DNSSEC"], "insecure");
+
+    // Bogus answers are dropped
+    let body = query_dns(&server, "name=host.bogus");
+    assert_eq!(body["Status"], "error");
+    assert_eq!(body["DNSSEC"], "bogus");
+    assert_eq!(body["Records"], serde_json::json!([]));
+
+    let body = lookup(&server, "ip=192.0.2.10");
+    assert_eq!(body["rDNS"], "host.example.net");
+    assert_eq!(body["rDNS-DNSSEC"], "secure");
+
+    let response = server.get("/dns?name=secure.example&format=text", &[]);
+    assert!(response.body.contains("\nDNSSEC: secure\n"));
+    let response = server.get("/?format=text", &[("X-Forwarded-For", "192.0.2.10")]);
+    assert!(response.body.contains("rDNS-DNSSEC: secure"));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_failed_lookup_is_indeterminate() {
+    let (_, trust_anchor) = StubDns::start_signed(PTR, SIGNED_RECORDS);
+    let path = trust_anchor_file("indeterminate", &trust_anchor);
+    let dns = SilentDns::start();
+    let server = TestServer::start(&[
+        ("DNS_SERVERS", &dns.addr.to_string()),
+        ("DNS_TRUST_ANCHOR_FILE", path.to_str().unwrap()),
+        ("DNS_TIMEOUT_MS", "200"),
+        ("DNS_ATTEMPTS", "1"),
+    ]);
+
+    let body = query_dns(&server, "name=secure.example");
+    assert_eq!(body["Status"], "timeout");
+    assert_eq!(body["DNSSEC"], "indeterminate");
+    assert_eq!(
+        lookup(&server, "ip=192.0.2.10")["rDNS-DNSSEC"],
+        "indeterminate"
+    );
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_invalid_trust_anchor_fails_startup() {
+    let path = trust_anchor_file("invalid", "not a DNSKEY record\n");
+    let empty = trust_anchor_file("empty", "");
+    for file in [
+        path.to_str().unwrap(),
+        empty.to_str().unwrap(),
+        "/nonexistent/trust-anchor",
+    ] {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_ip-api"))
+            .arg("--port")
+            .arg(common::free_port().to_string())
+            .env("DNS_TRUST_ANCHOR_FILE", file)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!status.success(), "{}", file);
+    }
+
+    std::fs::remove_file(&path).unwrap();
+    std::fs::remove_file(&empty).unwrap();
+}
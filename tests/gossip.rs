@@ -0,0 +1,135 @@
+//! Gossip integration tests, with several instances on localhost
+
+mod common;
+
+use common::{TestServer, free_udp_port};
+use std::time::{Duration, Instant};
+
+const SECRET: &str = "gossip-test-secret";
+const TOKEN: &str = "test-admin-token";
+
+/// Start one instance per secret, each gossiping with all the others
+fn cluster(secrets: &[&str], env: &[(&str, &str)]) -> Vec<TestServer> {
+    let ports: Vec<u16> = secrets.iter().map(|_| free_udp_port()).collect();
+
+    ports
+        .iter()
+        .zip(secrets)
+        .map(|(port, secret)| {
+            let bind = format!("127.0.0.1:{}", port);
+            let peers = ports
+                .iter()
+                .filter(|other| *other != port)
+                .map(|other| format!("127.0.0.1:{}", other))
+                .collect::<Vec<_>>()
+                .join(",");
+
+            let mut env = env.to_vec();
+            env.extend([
+                ("GOSSIP_BIND", bind.as_str()),
+                ("GOSSIP_PEERS", peers.as_str()),
+                ("GOSSIP_SECRET", secret),
+                ("GOSSIP_INTERVAL_MS", "50"),
+                ("ADMIN_TOKEN", TOKEN),
+            ]);
+            TestServer::start(&env)
+        })
+        .collect()
+}
+
+/// Wait until `condition` holds, failing after a few seconds
+fn eventually(mut condition: impl FnMut() -> bool) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while !condition() {
+        assert!(Instant::now() < deadline, "condition not reached");
+        std::thread::sleep(Duration::from_millis(50));
+    }
+}
+
+fn remaining(server: &TestServer, client: &str) -> u64 {
+    let response = server.get("/quota", &[("X-Forwarded-For", client)]);
+    let quota: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    quota["remaining"].as_u64().unwrap()
+}
+
+#[test]
+fn test_rate_limits_are_shared_between_instances() {
+    let servers = cluster(&[SECRET, SECRET, SECRET], &[("RATE_LIMIT_REQUESTS", "100")]);
+    let client = [("X-Forwarded-For", "198.51.100.1")];
+
+    for _ in 0..10 {
+        assert_eq!(servers[0].get("/version", &client).status, 200);
+    }
+
+    // Each /quota request counts as well
+    eventually(|| remaining(&servers[1], "198.51.100.1") < 90);
+    eventually(|| remaining(&servers[2], "198.51.100.1") < 90);
+}
+
+#[test]
+fn test_bans_are_shared_between_instances() {
+    let servers = cluster(&[SECRET, SECRET], &[]);
+    let client = [("X-Forwarded-For", "198.51.100.7")];
+    let authorization = format!("Bearer {}", TOKEN);
+    let admin = [
+        ("Authorization", authorization.as_str()),
+        ("Content-Type", "application/json"),
+    ];
+
+    let response = servers[0].request(
+        "POST",
+        "/admin/bans",
+        &admin,
+        r#"{"network": "198.51.100.0/24"}"#,
+    );
+    assert_eq!(response.status, 201);
+    eventually(|| servers[1].get("/version", &client).status == 403);
+
+    let response = servers[1].request("DELETE", "/admin/bans?network=198.51.100.0/24", &admin, "");
+    assert_eq!(response.status, 200);
+    eventually(|| servers[0].get("/version", &client).status == 200);
+}
+
+#[test]
+fn test_peers_with_another_secret_are_ignored() {
+    let servers = cluster(&[SECRET, "some-other-secret"], &[]);
+    let client = [("X-Forwarded-For", "198.51.100.7")];
+    let authorization = format!("Bearer {}", TOKEN);
+
+    let response = servers[0].request(
+        "POST",
+        "/admin/bans",
+        &[
+            ("Authorization", authorization.as_str()),
+            ("Content-Type", "application/json"),
+        ],
+        r#"{"network": "198.51.100.0/24"}"#,
+    );
+    assert_eq!(response.status, 201);
+
+    std::thread::sleep(Duration::from_millis(500));
+    assert_eq!(servers[1].get("/version", &client).status, 200);
+}
+
+#[test]
+fn test_gossip_requires_secret() {
+    for secret in ["", "short", "fifteen-bytes!!"] {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_ip-api"))
+            .arg("--port")
+            .arg(common::free_port().to_string())
+            .env("GOSSIP_BIND", "127.0.0.1:0")
+            .env("GOSSIP_SECRET", secret)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!status.success(), "{:?}", secret);
+    }
+
+    // A secret of the minimum length is accepted
+    let server = TestServer::start(&[
+        ("GOSSIP_BIND", "127.0.0.1:0"),
+        ("GOSSIP_SECRET", "sixteen-bytes!!!"),
+    ]);
+    assert_eq!(server.get("/version", &[]).status, 200);
+}
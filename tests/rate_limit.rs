@@ -0,0 +1,234 @@
+//! Rate limiting integration tests
+
+mod common;
+
+use common::TestServer;
+
+#[test]
+fn test_direct_clients_are_rate_limited() {
+    let server = TestServer::start(&[("RATE_LIMIT_REQUESTS", "5")]);
+
+    for _ in 0..5 {
+        assert_eq!(server.get("/version", &[]).status, 200);
+    }
+
+    assert_eq!(server.get("/version", &[]).status, 429);
+}
+
+#[test]
+fn test_spoofed_forwarding_headers_do_not_bypass_limit() {
+    let server = TestServer::start(&[("RATE_LIMIT_REQUESTS", "3"), ("TRUSTED_PROXIES", "")]);
+
+    for i in 0..3 {
+        let spoofed = format!("198.51.100.{}", i);
+        let response = server.get("/version", &[("X-Forwarded-For", &spoofed)]);
+        assert_eq!(response.status, 200);
+    }
+
+    let response = server.get("/version", &[("X-Forwarded-For", "198.51.100.99")]);
+    assert_eq!(response.status, 429);
+}
+
+#[test]
+fn test_trusted_proxy_clients_are_counted_separately() {
+    let server = TestServer::start(&[("RATE_LIMIT_REQUESTS", "2")]);
+
+    for client in ["198.51.100.1", "198.51.100.2"] {
+        for _ in 0..2 {
+            let response = server.get("/version", &[("X-Forwarded-For", client)]);
+            assert_eq!(response.status, 200);
+        }
+    }
+
+    let response = server.get("/version", &[("X-Forwarded-For", "198.51.100.1")]);
+    assert_eq!(response.status, 429);
+}
+
+#[test]
+fn test_rate_limit_headers() {
+    let server = TestServer::start(&[("RATE_LIMIT_REQUESTS", "2")]);
+
+    let response = server.get("/version", &[]);
+    assert_eq!(response.header("ratelimit-limit"), Some("2"));
+    assert_eq!(response.header("ratelimit-remaining"), Some("1"));
+    assert_eq!(response.header("ratelimit-policy"), Some("2;w=60"));
+    assert!(response.header("ratelimit-reset").is_some());
+    assert!(response.header("retry-after").is_none());
+}
+
+#[test]
+fn test_rate_limited_response_negotiates_format() {
+    let server = TestServer::start(&[("RATE_LIMIT_REQUESTS", "1")]);
+    assert_eq!(server.get("/version", &[]).status, 200);
+
+    let response = server.get("/version", &[]);
+    assert_eq!(response.status, 429);
+    assert_eq!(response.header("ratelimit-remaining"), Some("0"));
+    assert!(response.header("retry-after").is_some());
+    let body: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert!(body["retry_after"].as_u64().unwrap() > 0);
+
+    let response = server.get("/version?format=text", &[]);
+    assert_eq!(response.status, 429);
+    assert_eq!(
+        response.body,
+        "Rate limit exceeded. Please try again later."
+    );
+
+    let response = server.get("/", &[("Accept", "text/plain")]);
+    assert!(
+        response
+            .header("content-type")
+            .unwrap()
+            .starts_with("text/plain")
+    );
+}
+
+#[test]
+fn test_quota_endpoint() {
+    let server = TestServer::start(&[("RATE_LIMIT_REQUESTS", "10")]);
+    server.get("/version", &[]);
+
+    let response = server.get("/quota", &[]);
+    assert_eq!(response.status, 200);
+    let quota: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(quota["limit"], 10);
+    assert_eq!(quota["used"], 2);
+    assert_eq!(quota["remaining"], 8);
+    assert_eq!(quota["algorithm"], "fixed_window");
+}
+
+#[test]
+fn test_route_policies_have_separate_budgets() {
+    let server = TestServer::start(&[
+        ("RATE_LIMIT_REQUESTS", "100"),
+        ("RATE_LIMIT_POLICIES", "info"),
+        ("RATE_LIMIT_POLICY_INFO_REQUESTS", "1"),
+        ("RATE_LIMIT_POLICY_INFO_ROUTES", "/version"),
+    ]);
+
+    let response = server.get("/version", &[]);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("ratelimit-policy"), Some("1;w=60"));
+    assert_eq!(server.get("/version", &[]).status, 429);
+
+    // Other routes use the default policy
+    let response = server.get("/quota?route=/version", &[]);
+    assert_eq!(response.status, 200);
+    assert_eq!(response.header("ratelimit-limit"), Some("100"));
+    let quota: serde_json::Value = serde_json::from_str(&response.body).unwrap();
+    assert_eq!(quota["policy_name"], "info");
+    assert_eq!(quota["remaining"], 0);
+}
+
+#[test]
+fn test_reserved_and_duplicate_policy_names_fail_startup() {
+    for names in ["Default", "info,INFO", "api-v2,api_v2"] {
+        let status = std::process::Command::new(env!("CARGO_BIN_EXE_ip-api"))
+            .arg("--port")
+            .arg(common::free_port().to_string())
+            .env("RATE_LIMIT_POLICIES", names)
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .unwrap();
+        assert!(!status.success(), "{}", names);
+    }
+}
+
+#[test]
+fn test_window_longer_than_a_year_fails_startup() {
+    let status = std::process::Command::new(env!("CARGO_BIN_EXE_ip-api"))
+        .arg("--port")
+        .arg(common::free_port().to_string())
+        .env("RATE_LIMIT_WINDOW_SECS", u64::MAX.to_string())
+        .stdout(std::process::Stdio::null())
+        .stderr(std::process::Stdio::null())
+        .status()
+        .unwrap();
+    assert!(!status.success());
+}
+
+#[test]
+fn test_exempt_networks_are_not_limited() {
+    let server = TestServer::start(&[
+        ("RATE_LIMIT_REQUESTS", "1"),
+        ("RATE_LIMIT_EXEMPT", "127.0.0.0/8"),
+    ]);
+
+    for _ in 0..3 {
+        let response = server.get("/version", &[]);
+        assert_eq!(response.status, 200);
+        assert!(response.header("ratelimit-limit").is_none());
+        assert_eq!(response.header("ratelimit-policy"), Some("1;w=60"));
+    }
+
+    let quota: serde_json::Value = serde_json::from_str(&server.get("/quota", &[]).body).unwrap();
+    assert_eq!(quota["exempt"], true);
+    assert!(quota.get("remaining").is_none());
+}
+
+#[test]
+fn test_unreachable_redis_fails_closed() {
+    let server = TestServer::start(&[
+        ("RATE_LIMIT_REDIS_URL", "redis://127.0.0.1:1"),
+        ("RATE_LIMIT_FAIL_MODE", "closed"),
+    ]);
+
+    let response = server.get("/version", &[]);
+    assert_eq!(response.status, 429);
+    assert_eq!(response.header("retry-after"), Some("1"));
+}
+
+#[test]
+fn test_unreachable_redis_fails_open() {
+    let server = TestServer::start(&[
+        ("RATE_LIMIT_REQUESTS", "1"),
+        ("RATE_LIMIT_REDIS_URL", "redis://127.0.0.1:1"),
+    ]);
+
+    for _ in 0..3 {
+        assert_eq!(server.get("/version", &[]).status, 200);
+    }
+}
+
+/// Needs a running `redis-server`, e.g. `TEST_REDIS_URL=redis://127.0.0.1:6379`
+#[test]
+#[ignore = "needs TEST_REDIS_URL"]
+fn test_replicas_share_redis_budget() {
+    let url = std::env::var("TEST_REDIS_URL").expect("TEST_REDIS_URL is not set");
+    let prefix = format!("ip-api-test:replicas:{}", std::process::id());
+    let env = [
+        ("RATE_LIMIT_REQUESTS", "2"),
+        ("RATE_LIMIT_REDIS_URL", url.as_str()),
+        ("RATE_LIMIT_REDIS_PREFIX", prefix.as_str()),
+    ];
+    let first = TestServer::start(&env);
+    let second = TestServer::start(&env);
+
+    assert_eq!(first.get("/version", &[]).status, 200);
+    assert_eq!(second.get("/version", &[]).status, 200);
+    assert_eq!(first.get("/version", &[]).status, 429);
+}
+
+#[test]
+fn test_limits_survive_restart() {
+    let dir = std::env::temp_dir().join(format!("ip-api-limits-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("state.json");
+    let env = [
+        ("RATE_LIMIT_REQUESTS", "2"),
+        ("SNAPSHOT_FILE", path.to_str().unwrap()),
+    ];
+
+    let server = TestServer::start(&env);
+    assert_eq!(server.get("/version", &[]).status, 200);
+    assert_eq!(server.get("/version", &[]).status, 200);
+    server.terminate();
+    assert!(path.exists());
+
+    let server = TestServer::start(&env);
+    assert_eq!(server.get("/version", &[]).status, 429);
+
+    std::fs::remove_dir_all(&dir).unwrap();
+}